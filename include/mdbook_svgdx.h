@@ -0,0 +1,46 @@
+/*
+ * C interface to the mdbook-svgdx renderer.
+ *
+ * Build the library with the `ffi` feature, e.g.:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * All strings are NUL-terminated UTF-8.
+ */
+
+#ifndef MDBOOK_SVGDX_H
+#define MDBOOK_SVGDX_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The source was rendered; the output is the SVG. */
+#define SVGDX_OK 0
+/* An argument was null or not valid UTF-8; no output is returned. */
+#define SVGDX_INVALID_ARGUMENT 1
+/* The book's configuration couldn't be loaded; the output is the error. */
+#define SVGDX_CONFIG_ERROR 2
+/* The source couldn't be rendered; the output is the error. */
+#define SVGDX_RENDER_ERROR 3
+/* An internal error occurred; no output is returned. */
+#define SVGDX_INTERNAL_ERROR 4
+
+/*
+ * Render `source` to SVG as the book in `book_dir` (the directory containing
+ * `book.toml`) would, or with the default configuration if `book_dir` is NULL.
+ *
+ * Returns one of the SVGDX_* codes, setting `*output` to the SVG or error
+ * message, or to NULL. Strings returned must be released with
+ * svgdx_free_string().
+ */
+int svgdx_render(const char *book_dir, const char *source, char **output);
+
+/* Release a string returned by svgdx_render(). NULL is ignored. */
+void svgdx_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MDBOOK_SVGDX_H */
@@ -0,0 +1,148 @@
+//! Diagrams written as separate files rather than inline SVG.
+//!
+//! With `output = "file"`, each rendered diagram is written to the assets
+//! directory within the book's source (from where mdbook copies it to the
+//! output), and shown with an `<img>` element. Files are named by a hash of
+//! their content and only written if missing, so rebuilds don't touch
+//! unchanged files (which would trigger another build under `mdbook serve`).
+//!
+//! For renderers which can't display SVG (e.g. for EPUB or PDF), diagrams can
+//! instead be rasterized to PNG files by an external `resvg` command.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use crate::cache::write_atomically;
+use crate::escape_html;
+use crate::hash::content_hash;
+use crate::svg::root_attr;
+
+/// Hash identifying the content of a rendered diagram.
+pub(crate) fn figure_hash(svg: &str) -> String {
+    // svgdx assigns a random id to each diagram when using local styles,
+    // which mustn't distinguish otherwise identical diagrams.
+    match root_attr(svg, "id") {
+        Some(id) => content_hash(svg.replace(id, "svgdx")),
+        None => content_hash(svg),
+    }
+}
+
+/// Write `svg` to `dir` if not already present, returning its file name.
+pub(crate) fn write_figure(dir: &Path, svg: &str) -> io::Result<String> {
+    let name = format!("{}.svg", figure_hash(svg));
+    let path = dir.join(&name);
+    if !path.exists() {
+        write_atomically(&path, svg)?;
+    }
+    Ok(name)
+}
+
+/// Rasterize `svg` to a PNG file in `dir` at `dpi` using the resvg-compatible
+/// `command`, if not already present, returning the file's name.
+pub(crate) fn write_raster(dir: &Path, svg: &str, command: &str, dpi: u32) -> io::Result<String> {
+    let svg_name = write_figure(dir, svg)?;
+    let name = format!("{}-{}dpi.png", figure_hash(svg), dpi);
+    let path = dir.join(&name);
+    if path.exists() {
+        return Ok(name);
+    }
+    // Written to a temporary file first, so an interrupted build doesn't
+    // leave a partial image to be reused.
+    let partial = dir.join(format!("{}.partial", name));
+    let output = Command::new(command)
+        .arg("--dpi")
+        .arg(dpi.to_string())
+        .arg(dir.join(&svg_name))
+        .arg(&partial)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't run {}: {}", command, e)))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&partial);
+        return Err(io::Error::other(format!(
+            "{} failed ({}): {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    fs::rename(&partial, &path)?;
+    Ok(name)
+}
+
+/// An `<img>` element showing the diagram `svg` from `src`, with the same
+/// classes and size as the inline diagram would have.
+pub(crate) fn img_element(svg: &str, src: &str, alt: &str) -> String {
+    let mut style = match root_attr(svg, "width") {
+        Some(width) => format!("width: {};", width),
+        None => "width: 100%;".to_string(),
+    };
+    if let Some(height) = root_attr(svg, "height") {
+        style.push_str(&format!(" height: {};", height));
+    }
+    if let Some(root_style) = root_attr(svg, "style") {
+        style.push(' ');
+        style.push_str(root_style);
+    }
+    let class = match root_attr(svg, "class") {
+        Some(class) => format!("svgdx {}", class),
+        None => "svgdx".to_string(),
+    };
+    format!(
+        "<img class=\"{}\" src=\"{}\" alt=\"{}\" style=\"{}\">",
+        class,
+        escape_html(src),
+        escape_html(alt),
+        style
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn figure_written_once() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-assets-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let a = r#"<svg id="svgdx-1234" width="30mm" style="max-width: 100%;"><rect/></svg>"#;
+        let b = a.replace("svgdx-1234", "svgdx-5678");
+        let name = write_figure(&dir, a).unwrap();
+        assert_eq!(write_figure(&dir, &b).unwrap(), name);
+        assert_eq!(std::fs::read_to_string(dir.join(&name)).unwrap(), a);
+
+        assert_eq!(
+            img_element(a, "../svgdx-assets/x.svg", "A <box>"),
+            r#"<img class="svgdx" src="../svgdx-assets/x.svg" alt="A &lt;box&gt;" style="width: 30mm; max-width: 100%;">"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn raster_written_once() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("mdbook-svgdx-raster-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        // Stands in for resvg, recording its arguments as the "image".
+        let command = dir.join("fake-resvg");
+        fs::write(&command, "#!/bin/sh\necho \"$@\" > \"$4\"\n").unwrap();
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755)).unwrap();
+        let command = command.to_str().unwrap();
+
+        let svg = r#"<svg width="30mm"><rect/></svg>"#;
+        let name = write_raster(&dir, svg, command, 150).unwrap();
+        assert!(name.ends_with("-150dpi.png"));
+        let png = fs::read_to_string(dir.join(&name)).unwrap();
+        assert!(png.starts_with("--dpi 150 "));
+        fs::write(dir.join(&name), "cached").unwrap();
+        assert_eq!(write_raster(&dir, svg, command, 150).unwrap(), name);
+        assert_eq!(fs::read_to_string(dir.join(&name)).unwrap(), "cached");
+
+        let e = write_raster(&dir, svg, "/bin/false", 96).unwrap_err();
+        assert!(e.to_string().starts_with("/bin/false failed"));
+        assert!(!dir.join(name.replace("150", "96")).exists());
+    }
+}
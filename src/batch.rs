@@ -0,0 +1,52 @@
+//! Rendering of svgdx sources outside of a book.
+//!
+//! Tools generating other documentation can render diagrams exactly as they
+//! would appear in a book with the same configuration, with the same
+//! post-processing, memoization and (with the `cache-dir` option) caching.
+
+use mdbook::errors::Error;
+
+use crate::config::SvgdxConfig;
+use crate::svg::root_tag;
+use crate::{error_message, process_parallel};
+
+/// Render each of `sources` to SVG as a book with configuration `cfg` would,
+/// returning the results in order.
+///
+/// Relative paths (e.g. of images) are resolved against `cfg.src_dir`.
+pub fn render_blocks(sources: &[&str], cfg: &SvgdxConfig) -> Vec<Result<String, Error>> {
+    let (results, _) = process_parallel(sources, cfg, |renderer, source| {
+        renderer.chapter_dir.clone_from(&renderer.cfg.src_dir);
+        let svg = renderer.render_block(source, None);
+        match root_tag(&svg) {
+            Some(_) => Ok(svg),
+            None => Err(Error::msg(error_message(&svg))),
+        }
+    });
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn render_in_order() {
+        let sources = (1..=20)
+            .map(|idx| format!("<svg><rect wh=\"{idx} 5\"/></svg>"))
+            .chain(["<svg><rect xy=\"#missing\"/></svg>".to_string()])
+            .collect::<Vec<_>>();
+        let sources = sources.iter().map(String::as_str).collect::<Vec<_>>();
+        let results = render_blocks(&sources, &SvgdxConfig::default());
+        assert_eq!(results.len(), 21);
+        for (idx, result) in results[..20].iter().enumerate() {
+            assert_contains!(
+                result.as_ref().unwrap(),
+                &format!(r#"<rect width="{}" height="5"/>"#, idx + 1)
+            );
+        }
+        assert_contains!(results[20].as_ref().unwrap_err().to_string(), "#missing");
+    }
+}
@@ -0,0 +1,143 @@
+//! svgdx `<config>` elements within diagram sources.
+//!
+//! svgdx applies a `<config>` element's attributes over the options it was
+//! given. Rather than leaving this to svgdx, the elements are removed from the
+//! source and their settings merged into the transform options here, so the
+//! precedence is explicit: the book's `[preprocessor.svgdx.transform]` table,
+//! then the block's `scale` and `theme` options, then its `<config>` elements.
+
+use crate::config::TransformOptions;
+
+/// The attributes of an XML start tag, e.g. `<config scale="2"/>`.
+fn tag_attrs(tag: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut rest = tag.trim_start_matches('<').trim_end_matches('>');
+    rest = rest.trim_end_matches('/');
+    rest = rest
+        .split_once(char::is_whitespace)
+        .map_or("", |(_, attrs)| attrs);
+    while let Some((key, after)) = rest.split_once('=') {
+        let after = after.trim_start();
+        let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some((value, remaining)) = after[1..].split_once(quote) else {
+            break;
+        };
+        attrs.push((key.trim().to_string(), value.to_string()));
+        rest = remaining;
+    }
+    attrs
+}
+
+/// `source` with any `<config>` elements removed, and the settings from
+/// their attributes in document order.
+pub(crate) fn take_config_elements(source: &str) -> (String, Vec<(String, String)>) {
+    let mut output = String::with_capacity(source.len());
+    let mut settings = Vec::new();
+    let mut rest = source;
+    loop {
+        let config = rest
+            .match_indices("<config")
+            .map(|(i, _)| i)
+            .find(|&start| {
+                rest[start + "<config".len()..]
+                    .starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            });
+        // Elements within comments are left alone.
+        let comment = rest.find("<!--");
+        match (config, comment) {
+            (_, Some(comment)) if config.is_none_or(|start| comment < start) => {
+                let end = rest[comment..]
+                    .find("-->")
+                    .map_or(rest.len(), |end| comment + end + 3);
+                output.push_str(&rest[..end]);
+                rest = &rest[end..];
+            }
+            (Some(start), _) => {
+                output.push_str(&rest[..start]);
+                let Some(tag_len) = rest[start..].find('>').map(|end| end + 1) else {
+                    rest = &rest[start..];
+                    break;
+                };
+                let tag = &rest[start..start + tag_len];
+                settings.extend(tag_attrs(tag));
+                rest = &rest[start + tag_len..];
+                if !tag.ends_with("/>") {
+                    if let Some(end) = rest.find("</config>") {
+                        rest = &rest[end + "</config>".len()..];
+                    }
+                }
+            }
+            _ => break,
+        }
+    }
+    output.push_str(rest);
+    (output, settings)
+}
+
+/// Keys of `settings` which change an option set in the book's `transform`.
+pub(crate) fn overridden_settings<'a>(
+    transform: &TransformOptions,
+    settings: &'a [(String, String)],
+) -> Vec<&'a str> {
+    let Ok(serde_json::Value::Object(book)) = serde_json::to_value(transform) else {
+        return Vec::new();
+    };
+    let mut keys = Vec::new();
+    for (key, value) in settings {
+        let is_set = book.get(key).is_some_and(|v| !v.is_null());
+        let mut changed = transform.clone();
+        if is_set
+            && changed.set(key, value).is_ok()
+            && changed != *transform
+            && !keys.contains(&key.as_str())
+        {
+            keys.push(key.as_str());
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_config() {
+        let source = r#"<svg>
+<config scale="2" theme='bold'/>
+<!-- <config border="1"/> -->
+<rect wh="20 5"/>
+<configure/>
+<config font-size="4"></config>
+</svg>"#;
+        let (stripped, settings) = take_config_elements(source);
+        assert_eq!(
+            stripped,
+            r#"<svg>
+
+<!-- <config border="1"/> -->
+<rect wh="20 5"/>
+<configure/>
+
+</svg>"#
+        );
+        assert_eq!(
+            settings,
+            [
+                ("scale".to_string(), "2".to_string()),
+                ("theme".to_string(), "bold".to_string()),
+                ("font-size".to_string(), "4".to_string()),
+            ]
+        );
+
+        let book = TransformOptions {
+            scale: Some(1.5),
+            theme: Some("bold".to_string()),
+            border: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(overridden_settings(&book, &settings), ["scale"]);
+    }
+}
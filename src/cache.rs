@@ -0,0 +1,109 @@
+//! Persistent cache of rendered diagrams.
+//!
+//! With the `cache-dir` option set, svgdx output is stored in a file named by
+//! a hash of the svgdx version, transform options and diagram source, so
+//! unchanged (or only reformatted) diagrams aren't rendered again by later
+//! builds.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::TransformOptions;
+use crate::hash::content_hash;
+use crate::normalized_source;
+use crate::svg::root_tag;
+
+/// Count of files written, to name temporary files uniquely.
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// Write `contents` to `path`, creating its directory if needed.
+///
+/// A temporary file is written first, so concurrent readers (e.g. other
+/// threads or builds) never see a partially written file.
+pub(crate) fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let Some(dir) = path.parent() else {
+        return fs::write(path, contents);
+    };
+    let temp = path.with_extension(format!(
+        "{}-{}.tmp",
+        process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(dir)?;
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+/// Cache key for rendering `source` with the given `transform` options.
+///
+/// Whitespace that doesn't affect the rendering is ignored, so reformatting a
+/// diagram doesn't invalidate its cached output.
+pub(crate) fn cache_key(source: &str, transform: &TransformOptions) -> String {
+    content_hash(format!(
+        "{}\n{}\n{}",
+        svgdx::VERSION,
+        serde_json::to_string(transform).unwrap_or_default(),
+        normalized_source(source)
+    ))
+}
+
+/// The cached output for `key` in `dir`, or else the output of `render`,
+/// which is cached if it is a successfully rendered diagram.
+pub(crate) fn cached(dir: &Path, key: &str, render: impl FnOnce() -> String) -> String {
+    let path = dir.join(format!("{}.svg", key));
+    if let Ok(svg) = fs::read_to_string(&path) {
+        return svg;
+    }
+    let svg = render();
+    if root_tag(&svg).is_some() {
+        if let Err(e) = write_atomically(&path, &svg) {
+            eprintln!(
+                "Warning: couldn't write svgdx cache file {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_hits_skip_rendering() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-cache-test");
+        let _ = fs::remove_dir_all(&dir);
+        let key = cache_key("<svg/>", &TransformOptions::default());
+        assert_ne!(
+            key,
+            cache_key(
+                "<svg/>",
+                &TransformOptions {
+                    scale: Some(2.0),
+                    ..Default::default()
+                }
+            )
+        );
+
+        assert_eq!(cached(&dir, "err", || "error".to_string()), "error");
+        assert!(!dir.join("err.svg").exists());
+        assert_eq!(cached(&dir, &key, || "<svg a/>".to_string()), "<svg a/>");
+        assert_eq!(cached(&dir, &key, || unreachable!()), "<svg a/>");
+    }
+
+    #[test]
+    fn reformatted_source_hits_cache() {
+        let transform = TransformOptions::default();
+        let key = cache_key("<svg>\n  <rect wh=\"5\"/>\n</svg>\n", &transform);
+        assert_eq!(
+            key,
+            cache_key("<svg>\n\t\t<rect\n\t\t\twh=\"5\"/>\n</svg>", &transform)
+        );
+        assert_ne!(key, cache_key("<svg><rect wh=\"6\"/></svg>", &transform));
+    }
+}
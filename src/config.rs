@@ -0,0 +1,731 @@
+//! Book-level configuration, read from the `[preprocessor.svgdx]` table of `book.toml`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use mdbook::errors::Error;
+use mdbook::preprocess::PreprocessorContext;
+use mdbook::Config;
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::read_source;
+
+/// How each svgdx block is rendered.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Isolation {
+    /// Render within the preprocessor process.
+    #[default]
+    None,
+    /// Render each block in a short-lived worker subprocess with resource limits.
+    Process,
+}
+
+/// Whitespace layout of rendered SVG.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SvgFormat {
+    /// As rendered by svgdx, without blank lines.
+    #[default]
+    Lines,
+    /// On a single line.
+    Minified,
+    /// With each element on its own line, indented by its depth.
+    Pretty,
+}
+
+/// What to do with DOCTYPE declarations and undefined entity references in
+/// diagram sources.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Entities {
+    /// Passed to svgdx unchanged.
+    #[default]
+    Pass,
+    /// Removed before rendering.
+    Strip,
+    /// Shown as an error in place of the diagram.
+    Reject,
+}
+
+/// How rendered SVG images are sized on the page.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sizing {
+    /// Natural size, shrinking to fit narrower containers.
+    #[default]
+    Responsive,
+    /// No explicit width or height; scale to the container using the viewBox.
+    Viewbox,
+    /// Natural size only, e.g. for print-oriented renderers.
+    Fixed,
+}
+
+/// How blocks showing both a diagram and its XML source are laid out.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceLayout {
+    /// One after the other, as given by the block type.
+    #[default]
+    Stacked,
+    /// As tabs, or as an accordion on narrow screens.
+    Tabs,
+    /// One after the other, with the source in a collapsed `<details>` element.
+    Collapsed,
+}
+
+/// The element wrapping each rendered block.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    /// A `<div>` with the block type as its class.
+    #[default]
+    Div,
+    /// A `<p>` around the diagram, with any source listing outside it.
+    Paragraph,
+    /// A `<figure>`, with any caption as its `<figcaption>`.
+    Figure,
+    /// No wrapping element.
+    None,
+}
+
+/// How diagrams are numbered.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FigureNumbers {
+    #[default]
+    None,
+    /// Within each chapter, prefixed by the chapter's number, e.g. "3.2".
+    Chapter,
+    /// Through the whole book.
+    Book,
+}
+
+/// Where rendered diagrams are placed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// Inline SVG within the chapter.
+    #[default]
+    Inline,
+    /// Separate files in the assets directory, shown with `<img>` elements.
+    File,
+    /// The original fenced block, followed by a markdown image link to the
+    /// diagram in a separate file, e.g. for the markdown renderer.
+    Markdown,
+}
+
+/// What to do with local image files referenced by diagrams.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalImages {
+    /// Leave references unchanged.
+    #[default]
+    Keep,
+    /// Inline as `data:` URIs, up to the `inline-image-limit` size.
+    Inline,
+    /// Copy to the assets directory.
+    Copy,
+}
+
+/// The kind of build, affecting how work-in-progress diagrams are shown.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// Published output.
+    #[default]
+    Release,
+    /// Review builds, showing diagram ownership and status.
+    Draft,
+}
+
+/// What to do with the styles svgdx includes in each diagram.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoStyles {
+    /// As emitted by svgdx, nested within a rule for the diagram's id.
+    #[default]
+    Nested,
+    /// Flattened, with every selector scoped to a class unique to the diagram.
+    Scoped,
+    /// Removed, leaving diagrams to be styled by the book's CSS.
+    None,
+}
+
+/// Inline style of the elements laying out `-inline` blocks and groups.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum WrapperStyle {
+    /// No inline style, leaving the layout to the book's CSS.
+    None,
+    /// Diagrams spaced out in a wrapping flex row.
+    #[default]
+    Flex,
+    /// The given CSS, from `"custom:<css>"`.
+    Custom(String),
+}
+
+impl TryFrom<String> for WrapperStyle {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "none" => Ok(Self::None),
+            "flex" => Ok(Self::Flex),
+            _ => match value.strip_prefix("custom:") {
+                Some(css) => Ok(Self::Custom(css.trim().to_string())),
+                None => Err(format!(
+                    "invalid wrapper-style '{}', expected \"none\", \"flex\" or \"custom:<css>\"",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+/// Options passed to svgdx when rendering, from the
+/// `[preprocessor.svgdx.transform]` table.
+///
+/// Unset options use svgdx's defaults, except as noted.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TransformOptions {
+    /// Size of a user unit in mm (default 1.5). The book-level value may be
+    /// `"auto"`, which is resolved for the renderer on loading.
+    pub scale: Option<f32>,
+    /// Border around the diagram, in user units.
+    pub border: Option<u16>,
+    pub add_auto_styles: Option<bool>,
+    pub background: Option<String>,
+    pub seed: Option<u64>,
+    pub loop_limit: Option<u32>,
+    pub var_limit: Option<u32>,
+    pub depth_limit: Option<u32>,
+    pub font_size: Option<f32>,
+    pub font_family: Option<String>,
+    pub theme: Option<String>,
+    /// Style of the root element (default `max-width: 100%; height: auto;`).
+    pub svg_style: Option<String>,
+    /// Scope styles to each diagram's id (default true).
+    pub use_local_styles: Option<bool>,
+    pub debug: Option<bool>,
+}
+
+/// `value` parsed for the setting `key`.
+fn parse_setting<T: std::str::FromStr>(key: &str, value: &str) -> Result<Option<T>, String> {
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("Invalid value '{}' for config setting {}", value, key))
+}
+
+impl TransformOptions {
+    /// Set the option named `key` (as in svgdx's `<config>` element) to `value`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "scale" => self.scale = parse_setting(key, value)?,
+            "border" => self.border = parse_setting(key, value)?,
+            "add-auto-styles" => self.add_auto_styles = parse_setting(key, value)?,
+            "background" => self.background = Some(value.to_string()),
+            "seed" => self.seed = parse_setting(key, value)?,
+            "loop-limit" => self.loop_limit = parse_setting(key, value)?,
+            "var-limit" => self.var_limit = parse_setting(key, value)?,
+            "depth-limit" => self.depth_limit = parse_setting(key, value)?,
+            "font-size" => self.font_size = parse_setting(key, value)?,
+            "font-family" => self.font_family = Some(value.to_string()),
+            "theme" => self.theme = Some(value.to_string()),
+            "svg-style" => self.svg_style = Some(value.to_string()),
+            "use-local-styles" => self.use_local_styles = parse_setting(key, value)?,
+            "debug" => self.debug = parse_setting(key, value)?,
+            _ => return Err(format!("Unknown config setting {}", key)),
+        }
+        Ok(())
+    }
+
+    /// The svgdx configuration for these options.
+    pub fn transform_config(&self) -> Result<svgdx::TransformConfig, String> {
+        let mut cfg = svgdx::TransformConfig {
+            svg_style: Some(
+                self.svg_style
+                    .clone()
+                    .unwrap_or_else(|| "max-width: 100%; height: auto;".to_string()),
+            ),
+            use_local_styles: self.use_local_styles.unwrap_or(true),
+            scale: self.scale.unwrap_or(1.5),
+            ..Default::default()
+        };
+        if let Some(debug) = self.debug {
+            cfg.debug = debug;
+        }
+        if let Some(border) = self.border {
+            cfg.border = border;
+        }
+        if let Some(add_auto_styles) = self.add_auto_styles {
+            cfg.add_auto_styles = add_auto_styles;
+        }
+        if let Some(background) = &self.background {
+            cfg.background = background.clone();
+        }
+        if let Some(seed) = self.seed {
+            cfg.seed = seed;
+        }
+        if let Some(loop_limit) = self.loop_limit {
+            cfg.loop_limit = loop_limit;
+        }
+        if let Some(var_limit) = self.var_limit {
+            cfg.var_limit = var_limit;
+        }
+        if let Some(depth_limit) = self.depth_limit {
+            cfg.depth_limit = depth_limit;
+        }
+        if let Some(font_size) = self.font_size {
+            cfg.font_size = font_size;
+        }
+        if let Some(font_family) = &self.font_family {
+            cfg.font_family = font_family.clone();
+        }
+        if let Some(theme) = &self.theme {
+            cfg.theme = theme.parse().map_err(|e| format!("{e}"))?;
+        }
+        Ok(cfg)
+    }
+}
+
+/// Footer stamped on every diagram, from the `[preprocessor.svgdx.stamp]` table.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Stamp {
+    pub document_id: Option<String>,
+    pub revision: Option<String>,
+    pub date: Option<String>,
+    /// Include a checksum of each diagram's source.
+    pub checksum: bool,
+}
+
+impl Default for Stamp {
+    fn default() -> Self {
+        Self {
+            document_id: None,
+            revision: None,
+            date: None,
+            checksum: true,
+        }
+    }
+}
+
+impl Stamp {
+    /// The footer text for a diagram with the given source `checksum`.
+    pub fn text(&self, checksum: &str) -> String {
+        let revision = self.revision.as_ref().map(|rev| format!("Rev {rev}"));
+        [self.document_id.clone(), revision, self.date.clone()]
+            .into_iter()
+            .flatten()
+            .chain(self.checksum.then(|| checksum.to_string()))
+            .collect::<Vec<_>>()
+            .join(" \u{b7} ")
+    }
+}
+
+/// A setting given either for all renderers, or as a table keyed by renderer name.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum PerRenderer<T> {
+    All(T),
+    ByRenderer(HashMap<String, T>),
+}
+
+impl<T: Default> Default for PerRenderer<T> {
+    fn default() -> Self {
+        Self::All(T::default())
+    }
+}
+
+impl<T: Copy + Default> PerRenderer<T> {
+    /// The value for the given renderer, falling back to the default if not listed.
+    pub fn get(&self, renderer: &str) -> T {
+        match self {
+            Self::All(value) => *value,
+            Self::ByRenderer(values) => values.get(renderer).copied().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SvgdxConfig {
+    /// The renderer this book is being preprocessed for.
+    #[serde(skip)]
+    pub renderer: String,
+    /// The book's source directory.
+    #[serde(skip)]
+    pub src_dir: PathBuf,
+    pub isolation: Isolation,
+    /// Address space limit for worker processes, in MiB.
+    pub worker_memory_limit: u64,
+    /// CPU time limit for worker processes, in seconds.
+    pub worker_cpu_limit: u64,
+    /// Embed the svgdx version as a comment in each rendered SVG.
+    pub version_comment: bool,
+    pub auto_styles: AutoStyles,
+    pub transform: TransformOptions,
+    /// svgdx theme for a second rendering of each diagram, shown with dark
+    /// mdbook themes.
+    pub dark_theme: Option<String>,
+    /// Class added to the root element of every rendered diagram.
+    pub svg_class: Option<String>,
+    pub sizing: PerRenderer<Sizing>,
+    pub svg_format: SvgFormat,
+    pub output: PerRenderer<Output>,
+    /// Directory (relative to the book source) for diagrams written as files.
+    pub assets_dir: PathBuf,
+    pub external_images: ExternalImages,
+    /// Size in bytes above which images are copied rather than inlined.
+    pub inline_image_limit: usize,
+    /// Meaning of svgdx classes, for blocks with the `legend=auto` option.
+    pub legend: HashMap<String, String>,
+    /// Colour names mapped to the values they should be rendered with.
+    pub palette: HashMap<String, String>,
+    /// Include a high-contrast variant of each diagram, with a toggle to show it.
+    pub high_contrast: bool,
+    /// Minimum stroke width of the high-contrast variant of diagrams.
+    pub high_contrast_stroke: f32,
+    /// Describe diagrams for screen readers with ARIA attributes and
+    /// `<title>` and `<desc>` elements.
+    pub accessibility: bool,
+    /// Warn of references within diagrams which don't resolve.
+    pub check_links: bool,
+    /// Dash patterns (or `"hatch"`) applied to elements by class name.
+    pub patterns: BTreeMap<String, String>,
+    /// File (relative to the book root) recording diagram hashes between builds.
+    pub manifest: Option<PathBuf>,
+    /// Process chapters on multiple threads.
+    pub parallel: bool,
+    /// Report the time spent in each phase of processing each chapter.
+    pub timings: bool,
+    /// Directory (relative to the book root) in which rendered diagrams are cached.
+    pub cache_dir: Option<PathBuf>,
+    /// Prefix marking source lines to be hidden from the displayed XML.
+    pub hidden_line_marker: Option<String>,
+    /// Directories (relative to the book source) shown as appendix chapters.
+    pub appendices: Vec<PathBuf>,
+    /// File (relative to the book source) of svgdx elements included at the
+    /// start of every diagram.
+    pub preamble: Option<PathBuf>,
+    /// Content of the `preamble` file.
+    #[serde(skip)]
+    pub preamble_source: String,
+    pub entities: Entities,
+    pub source_layout: SourceLayout,
+    /// Show `svgdx` blocks as tabs with their source, as `source-layout = "tabs"`
+    /// does for `svgdx-xml` blocks.
+    pub source_tabs: bool,
+    pub wrap: Wrap,
+    pub wrapper_style: WrapperStyle,
+    pub figure_numbers: FigureNumbers,
+    /// Width to height ratio at or above which a hint to rotate the device
+    /// is shown to readers with narrow screens.
+    pub rotate_hint: Option<f32>,
+    /// CSS height above which diagrams are shown in a scrollable container.
+    pub max_height: Option<String>,
+    /// Show a button copying the rendered SVG's source beside each diagram.
+    pub copy_svg: bool,
+    /// Use a diagram's top-level `<title>` as its caption and alt text, if
+    /// the block has neither.
+    pub title_caption: bool,
+    /// Size text as if every diagram were rendered with the book's scale.
+    pub harmonize_text: bool,
+    /// Prefix element ids in each diagram with a token unique to it.
+    pub namespace_ids: bool,
+    /// Include each diagram's source and transform options after it, for
+    /// re-rendering by client-side svgdx.
+    pub attach_source: bool,
+    /// Describe how each block was rendered with `data-` attributes on its wrapper.
+    pub data_attributes: bool,
+    pub profile: Profile,
+    /// Text overlaid on `status=draft` diagrams in draft builds.
+    pub draft_banner: String,
+    pub stamp: Option<Stamp>,
+    /// Renderers for which the book is passed through unprocessed.
+    pub skip_renderers: Vec<String>,
+    /// Renderers for which diagrams are rasterized to PNG files.
+    pub raster_renderers: Vec<String>,
+    /// Resolution of rasterized diagrams.
+    pub raster_dpi: u32,
+    /// resvg-compatible command used to rasterize diagrams.
+    pub raster_command: String,
+    /// Renderers for which diagrams are checked but the book is left unchanged.
+    pub validate_renderers: Vec<String>,
+    /// Fail the build if any diagram can't be rendered.
+    pub fail_on_error: bool,
+}
+
+impl Default for SvgdxConfig {
+    fn default() -> Self {
+        Self {
+            renderer: String::new(),
+            src_dir: PathBuf::new(),
+            isolation: Isolation::default(),
+            worker_memory_limit: 1024,
+            worker_cpu_limit: 60,
+            version_comment: false,
+            auto_styles: AutoStyles::default(),
+            svg_class: None,
+            transform: TransformOptions::default(),
+            dark_theme: None,
+            sizing: PerRenderer::default(),
+            svg_format: SvgFormat::default(),
+            output: PerRenderer::default(),
+            assets_dir: PathBuf::from("svgdx-assets"),
+            external_images: ExternalImages::default(),
+            inline_image_limit: 64 * 1024,
+            legend: HashMap::new(),
+            palette: HashMap::new(),
+            high_contrast: false,
+            high_contrast_stroke: 1.0,
+            accessibility: false,
+            check_links: false,
+            patterns: BTreeMap::new(),
+            manifest: None,
+            parallel: true,
+            timings: false,
+            cache_dir: None,
+            hidden_line_marker: None,
+            appendices: Vec::new(),
+            preamble: None,
+            preamble_source: String::new(),
+            entities: Entities::default(),
+            source_layout: SourceLayout::default(),
+            source_tabs: false,
+            wrap: Wrap::default(),
+            wrapper_style: WrapperStyle::default(),
+            figure_numbers: FigureNumbers::default(),
+            rotate_hint: None,
+            max_height: None,
+            copy_svg: false,
+            title_caption: false,
+            harmonize_text: false,
+            namespace_ids: false,
+            attach_source: false,
+            data_attributes: false,
+            profile: Profile::default(),
+            draft_banner: "DRAFT".to_string(),
+            stamp: None,
+            skip_renderers: vec!["test".to_string()],
+            raster_renderers: Vec::new(),
+            raster_dpi: 96,
+            raster_command: "resvg".to_string(),
+            validate_renderers: Vec::new(),
+            fail_on_error: false,
+        }
+    }
+}
+
+impl SvgdxConfig {
+    /// The scale used for `scale = "auto"` with the current renderer.
+    ///
+    /// Diagrams shown as responsive inline SVG shrink to fit the page anyway,
+    /// so use one mm per user unit, which keeps text close to the size of the
+    /// surrounding prose. Rasterized or fixed-size diagrams (e.g. for print)
+    /// keep their physical size regardless of the raster DPI, so use the usual
+    /// 1.5mm.
+    pub fn auto_scale(&self) -> f32 {
+        let fixed = self.raster_renderers.contains(&self.renderer)
+            || self.sizing.get(&self.renderer) == Sizing::Fixed;
+        if fixed {
+            1.5
+        } else {
+            1.0
+        }
+    }
+
+    pub fn from_context(ctx: &PreprocessorContext) -> Result<Self, Error> {
+        Self::from_config(&ctx.config, &ctx.root, &ctx.renderer)
+    }
+
+    /// Configuration for the book at `root` with the given `config`, when
+    /// preprocessing for `renderer`.
+    pub fn from_config(config: &Config, root: &Path, renderer: &str) -> Result<Self, Error> {
+        const SCALE_KEY: &str = "preprocessor.svgdx.transform.scale";
+        let auto_scale = config.get(SCALE_KEY).and_then(|v| v.as_str()) == Some("auto");
+        let mut cfg: Self = if auto_scale {
+            // Replaced by the resolved scale once the renderer's options are known.
+            let mut config = config.clone();
+            config.set(SCALE_KEY, 1.0)?;
+            config.get_deserialized_opt("preprocessor.svgdx")?
+        } else {
+            config.get_deserialized_opt("preprocessor.svgdx")?
+        }
+        .unwrap_or_default();
+        cfg.renderer = renderer.to_string();
+        if auto_scale {
+            cfg.transform.scale = Some(cfg.auto_scale());
+        }
+        cfg.src_dir = root.join(&config.book.src);
+        cfg.cache_dir = cfg.cache_dir.map(|dir| root.join(dir));
+        if let Some(preamble) = &cfg.preamble {
+            let path = cfg.src_dir.join(preamble);
+            cfg.preamble_source = read_source(&path).map_err(|e| {
+                Error::new(e).context(format!("Could not read preamble {}", path.display()))
+            })?;
+        }
+        if cfg.worker_cpu_limit == 0 {
+            return Err(Error::msg("worker-cpu-limit must be at least 1 second"));
+        }
+        cfg.transform.transform_config().map_err(Error::msg)?;
+        if let Some(theme) = &cfg.dark_theme {
+            let dark = TransformOptions {
+                theme: Some(theme.clone()),
+                ..Default::default()
+            };
+            dark.transform_config()
+                .map_err(|e| Error::msg(format!("Invalid dark-theme: {e}")))?;
+        }
+        Ok(cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_isolation() {
+        let cfg: SvgdxConfig = toml::from_str(
+            r#"
+            command = "mdbook-svgdx"
+            isolation = "process"
+            worker-memory-limit = 256
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.isolation, Isolation::Process);
+        assert_eq!(cfg.worker_memory_limit, 256);
+        assert_eq!(cfg.worker_cpu_limit, 60);
+    }
+
+    #[test]
+    fn parse_profile() {
+        let cfg: SvgdxConfig = toml::from_str(r#"profile = "draft""#).unwrap();
+        assert_eq!(cfg.profile, Profile::Draft);
+        assert_eq!(SvgdxConfig::default().profile, Profile::Release);
+    }
+
+    #[test]
+    fn parse_wrapper_style() {
+        let parse = |value: &str| {
+            toml::from_str::<SvgdxConfig>(&format!("wrapper-style = {:?}", value))
+                .map(|cfg| cfg.wrapper_style)
+        };
+        assert_eq!(parse("none").unwrap(), WrapperStyle::None);
+        assert_eq!(parse("flex").unwrap(), WrapperStyle::Flex);
+        assert_eq!(
+            parse("custom: display: grid;").unwrap(),
+            WrapperStyle::Custom("display: grid;".to_string())
+        );
+        assert!(parse("grid").is_err());
+    }
+
+    #[test]
+    fn parse_transform() {
+        let cfg: SvgdxConfig = toml::from_str(
+            r#"
+            [transform]
+            scale = 2.0
+            theme = "bold"
+            "#,
+        )
+        .unwrap();
+        let transform = cfg.transform.transform_config().unwrap();
+        assert_eq!(transform.scale, 2.0);
+        assert!(transform.use_local_styles);
+        assert_eq!(transform.font_size, 3.0);
+
+        let cfg: SvgdxConfig = toml::from_str("transform = { theme = \"plaid\" }").unwrap();
+        assert!(cfg.transform.transform_config().is_err());
+        assert!(toml::from_str::<SvgdxConfig>("transform = { scael = 2 }").is_err());
+
+        let mut transform = cfg.transform;
+        transform.set("font-size", "4").unwrap();
+        assert_eq!(transform.font_size, Some(4.0));
+        assert!(transform.set("font-size", "big").is_err());
+        assert!(transform.set("scael", "2").is_err());
+    }
+
+    #[test]
+    fn parse_stamp() {
+        let cfg: SvgdxConfig = toml::from_str(
+            r#"
+            [stamp]
+            document-id = "DOC-42"
+            revision = "B"
+            "#,
+        )
+        .unwrap();
+        let stamp = cfg.stamp.unwrap();
+        assert_eq!(
+            stamp.text("0123abcd"),
+            "DOC-42 \u{b7} Rev B \u{b7} 0123abcd"
+        );
+        let stamp = Stamp {
+            checksum: false,
+            ..stamp
+        };
+        assert_eq!(stamp.text("0123abcd"), "DOC-42 \u{b7} Rev B");
+        assert_eq!(SvgdxConfig::default().stamp, None);
+    }
+
+    #[test]
+    fn auto_scale() {
+        let config = |svgdx: &str| -> Config {
+            format!(
+                "[preprocessor.svgdx]\n{svgdx}\n[preprocessor.svgdx.transform]\nscale = \"auto\""
+            )
+            .parse()
+            .unwrap()
+        };
+        let scale = |config: &Config, renderer: &str| {
+            SvgdxConfig::from_config(config, Path::new("."), renderer)
+                .unwrap()
+                .transform
+                .scale
+        };
+        let book = config(
+            r#"raster-renderers = ["pdf"]
+sizing = { epub = "fixed" }"#,
+        );
+        assert_eq!(scale(&book, "html"), Some(1.0));
+        assert_eq!(scale(&book, "pdf"), Some(1.5));
+        assert_eq!(scale(&book, "epub"), Some(1.5));
+
+        let book: Config = "[preprocessor.svgdx.transform]\nscale = 2.5"
+            .parse()
+            .unwrap();
+        assert_eq!(scale(&book, "html"), Some(2.5));
+    }
+
+    #[test]
+    fn parse_output() {
+        let cfg = SvgdxConfig::default();
+        assert_eq!(cfg.output.get("html"), Output::Inline);
+        assert_eq!(cfg.output.get("markdown"), Output::Inline);
+
+        let cfg: SvgdxConfig = toml::from_str(r#"output = "file""#).unwrap();
+        assert_eq!(cfg.output.get("markdown"), Output::File);
+
+        let cfg: SvgdxConfig =
+            toml::from_str("[output]\nmarkdown = \"markdown\"\nepub = \"file\"").unwrap();
+        assert_eq!(cfg.output.get("html"), Output::Inline);
+        assert_eq!(cfg.output.get("markdown"), Output::Markdown);
+        assert_eq!(cfg.output.get("epub"), Output::File);
+    }
+
+    #[test]
+    fn parse_sizing() {
+        let cfg: SvgdxConfig = toml::from_str(r#"sizing = "viewbox""#).unwrap();
+        assert_eq!(cfg.sizing.get("html"), Sizing::Viewbox);
+
+        let cfg: SvgdxConfig = toml::from_str(r#"sizing = { pdf = "fixed" }"#).unwrap();
+        assert_eq!(cfg.sizing.get("pdf"), Sizing::Fixed);
+        assert_eq!(cfg.sizing.get("html"), Sizing::Responsive);
+    }
+}
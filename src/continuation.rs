@@ -0,0 +1,41 @@
+//! Diagrams built up by `svgdx-continue` blocks.
+//!
+//! An `svgdx-continue` block adds its content to the document of the diagram
+//! before it in the chapter, and the combined document is rendered, so that
+//! each step of a tutorial shows the cumulative result. A continuation may
+//! itself be continued.
+
+use crate::preamble::with_preamble;
+
+/// `previous` with `source` inserted as the last content of its root `<svg>`
+/// element.
+pub(crate) fn extend_diagram(previous: &str, source: &str) -> String {
+    match previous.rfind("</svg>") {
+        Some(end) => format!("{}{}{}", &previous[..end], source, &previous[end..]),
+        // e.g. an empty `<svg/>` root element.
+        None => with_preamble(previous, source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagram_extended() {
+        let previous = "<svg>\n<rect id=\"a\" wh=\"5\"/>\n</svg>\n";
+        let extended = extend_diagram(previous, "<circle xy=\"#a|h\" r=\"2\"/>\n");
+        assert_eq!(
+            extended,
+            "<svg>\n<rect id=\"a\" wh=\"5\"/>\n<circle xy=\"#a|h\" r=\"2\"/>\n</svg>\n"
+        );
+        assert_eq!(
+            extend_diagram(&extended, "<text>x</text>\n"),
+            "<svg>\n<rect id=\"a\" wh=\"5\"/>\n<circle xy=\"#a|h\" r=\"2\"/>\n<text>x</text>\n</svg>\n"
+        );
+        assert_eq!(
+            extend_diagram("<svg/>", "<rect wh=\"5\"/>\n"),
+            "<svg>\n<rect wh=\"5\"/>\n</svg>"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Checks of a book's svgdx setup, for the `doctor` subcommand.
+//!
+//! Each check reports what it found and, if it failed, how to fix it, so
+//! problems can be found before a build fails or shows errors in place of
+//! diagrams.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use mdbook::Config;
+use semver::{Version, VersionReq};
+
+use crate::batch::render_blocks;
+use crate::config::{ExternalImages, Isolation, Output, SvgdxConfig};
+
+/// Rendered to check the configured backend works.
+const SAMPLE: &str = r#"<svg><rect wh="20 5" text="svgdx"/></svg>"#;
+
+/// The outcome of one check.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    /// What was found.
+    pub detail: String,
+    /// How to fix a failed check.
+    pub fix: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    pub(crate) fn fail(
+        name: &'static str,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.passed { "ok" } else { "FAIL" };
+        write!(f, "{:<6} {}: {}", status, self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n       fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that files can be written in `dir` (from the option `name`),
+/// creating it if necessary.
+fn check_writable(name: &'static str, dir: &Path) -> Check {
+    let probe = dir.join(".svgdx-doctor");
+    let result = fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, ""))
+        .and_then(|_| fs::remove_file(&probe));
+    match result {
+        Ok(()) => Check::pass(name, format!("{} is writable", dir.display())),
+        Err(e) => Check::fail(
+            name,
+            format!("{} isn't writable: {}", dir.display(), e),
+            format!(
+                "Create {} with write permission, or change the `{}` option",
+                dir.display(),
+                name
+            ),
+        ),
+    }
+}
+
+/// Check the version of mdbook on the `PATH` matches the one built against.
+fn check_mdbook() -> Check {
+    let name = "mdbook";
+    let fix = format!(
+        "Install a compatible mdbook with `cargo install mdbook --version '{}'`",
+        mdbook::MDBOOK_VERSION
+    );
+    let output = match Command::new("mdbook").arg("--version").output() {
+        Ok(output) => output,
+        Err(e) => return Check::fail(name, format!("couldn't run mdbook: {}", e), fix),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.trim().trim_start_matches("mdbook").trim();
+    let parsed = Version::parse(version.trim_start_matches('v'));
+    let required = VersionReq::parse(mdbook::MDBOOK_VERSION).expect("valid mdbook version");
+    match parsed {
+        Ok(parsed) if required.matches(&parsed) => Check::pass(name, version),
+        _ => Check::fail(
+            name,
+            format!(
+                "found {}, but built against {}",
+                version,
+                mdbook::MDBOOK_VERSION
+            ),
+            fix,
+        ),
+    }
+}
+
+/// Check the svgdx setup of the book at `root`.
+pub fn diagnose(root: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let config = match Config::from_disk(root.join("book.toml")) {
+        Ok(config) => config,
+        Err(e) => {
+            checks.push(Check::fail(
+                "book.toml",
+                format!("{:#}", e),
+                "Run in the book's root directory, or give it as an argument",
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::pass(
+        "book.toml",
+        root.join("book.toml").display().to_string(),
+    ));
+    if config.get("preprocessor.svgdx").is_some() {
+        checks.push(Check::pass(
+            "preprocessor",
+            "[preprocessor.svgdx] is present",
+        ));
+    } else {
+        checks.push(Check::fail(
+            "preprocessor",
+            "[preprocessor.svgdx] is missing, so diagrams won't be rendered",
+            "Add a [preprocessor.svgdx] table to book.toml",
+        ));
+    }
+    let cfg = match SvgdxConfig::from_config(&config, root, "html") {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            checks.push(Check::fail(
+                "configuration",
+                format!("{:#}", e),
+                "Correct the [preprocessor.svgdx] options in book.toml",
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::pass("configuration", "options are valid"));
+    checks.push(Check::pass("svgdx", svgdx::VERSION));
+    checks.push(check_mdbook());
+
+    let backend = match cfg.isolation {
+        Isolation::None => "in-process",
+        Isolation::Process => "worker process",
+    };
+    checks.push(match render_blocks(&[SAMPLE], &cfg).pop() {
+        Some(Ok(_)) => Check::pass("backend", format!("{} rendering works", backend)),
+        Some(Err(e)) => Check::fail(
+            "backend",
+            format!("{} rendering failed: {}", backend, e),
+            match cfg.isolation {
+                Isolation::Process => {
+                    "Check this executable can be run as a worker, and that \
+                     `worker-memory-limit` and `worker-cpu-limit` aren't too low"
+                }
+                Isolation::None => "Check the [preprocessor.svgdx.transform] options",
+            },
+        ),
+        None => Check::fail("backend", "no output", "Report this as a bug"),
+    });
+
+    if !cfg.raster_renderers.is_empty() {
+        let command = &cfg.raster_command;
+        checks.push(match Command::new(command).arg("--version").output() {
+            Ok(output) if output.status.success() => Check::pass(
+                "raster-command",
+                String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            ),
+            _ => Check::fail(
+                "raster-command",
+                format!("couldn't run {}", command),
+                "Install resvg (e.g. with `cargo install resvg`), or set `raster-command`",
+            ),
+        });
+    }
+
+    if cfg.src_dir.is_dir() {
+        checks.push(Check::pass("src", cfg.src_dir.display().to_string()));
+    } else {
+        checks.push(Check::fail(
+            "src",
+            format!("{} doesn't exist", cfg.src_dir.display()),
+            "Create the book's source directory, or correct `book.src` in book.toml",
+        ));
+    }
+    if let Some(dir) = &cfg.cache_dir {
+        checks.push(check_writable("cache-dir", dir));
+    }
+    if cfg.output.get(&cfg.renderer) != Output::Inline
+        || cfg.external_images == ExternalImages::Copy
+    {
+        let dir = cfg.src_dir.join(&cfg.assets_dir);
+        checks.push(check_writable("assets-dir", &dir));
+    }
+    for dir in &cfg.appendices {
+        let path = cfg.src_dir.join(dir);
+        if path.is_dir() {
+            checks.push(Check::pass("appendices", path.display().to_string()));
+        } else {
+            checks.push(Check::fail(
+                "appendices",
+                format!("{} doesn't exist", path.display()),
+                format!("Create {}, or remove it from `appendices`", path.display()),
+            ));
+        }
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check<'a>(checks: &'a [Check], name: &str) -> &'a Check {
+        checks.iter().find(|check| check.name == name).unwrap()
+    }
+
+    #[test]
+    fn diagnose_book() {
+        let root = std::env::temp_dir().join("mdbook-svgdx-doctor-test");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(
+            root.join("book.toml"),
+            "[preprocessor.svgdx]\ncache-dir = \"cache\"\nappendices = [\"missing\"]\n",
+        )
+        .unwrap();
+        let checks = diagnose(&root);
+        for name in [
+            "book.toml",
+            "preprocessor",
+            "configuration",
+            "backend",
+            "src",
+            "cache-dir",
+        ] {
+            assert!(check(&checks, name).passed, "{}", check(&checks, name));
+        }
+        let appendices = check(&checks, "appendices");
+        assert!(!appendices.passed);
+        assert!(appendices.to_string().contains("\n       fix: Create "));
+
+        fs::write(
+            root.join("book.toml"),
+            "[preprocessor.svgdx.transform]\ntheme = \"no-such-theme\"\n",
+        )
+        .unwrap();
+        let checks = diagnose(&root);
+        assert_eq!(checks.len(), 3);
+        assert!(!check(&checks, "configuration").passed);
+    }
+}
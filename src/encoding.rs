@@ -0,0 +1,94 @@
+//! Normalization of chapter and diagram sources.
+//!
+//! Files saved by Windows tooling often start with a byte order mark and use
+//! CRLF line endings. A BOM before a fence stops it being recognised, and
+//! carriage returns end up in diagram text, so both are removed before
+//! parsing. Chapters without svgdx content are left as they were.
+//!
+//! Files which aren't valid UTF-8 are reported with the position of the first
+//! invalid byte, rather than being skipped or garbled.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BOM: char = '\u{feff}';
+
+/// `content` without byte order marks and with `\n` line endings, or `None`
+/// if it has neither.
+///
+/// Byte order marks are removed throughout, as included files may bring
+/// their own into the middle of a chapter.
+pub(crate) fn normalize(content: &str) -> Option<String> {
+    if !content.contains(['\r', BOM]) {
+        return None;
+    }
+    Some(
+        content
+            .replace("\r\n", "\n")
+            .replace('\r', "\n")
+            .replace(BOM, ""),
+    )
+}
+
+/// Describe where `bytes` first isn't valid UTF-8, e.g. "line 3, column 7".
+fn invalid_position(bytes: &[u8], valid_up_to: usize) -> String {
+    // The bytes up to the error are valid, so can be decoded to count chars.
+    let valid = String::from_utf8_lossy(&bytes[..valid_up_to]);
+    let line = valid.matches('\n').count() + 1;
+    let column = valid
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    format!("line {}, column {}", line, column)
+}
+
+/// Read the text file at `path`, normalized as by [`normalize`].
+///
+/// Invalid UTF-8 gives an error of kind [`io::ErrorKind::InvalidData`]
+/// giving the position of the first invalid byte.
+pub fn read_source(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(normalize(&text).unwrap_or(text)),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} isn't valid UTF-8 at {}; save it with UTF-8 encoding",
+                path.display(),
+                invalid_position(e.as_bytes(), e.utf8_error().valid_up_to())
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn normalize_sources() {
+        assert_eq!(normalize("```svgdx\n<svg/>\n```\n"), None);
+        assert_eq!(
+            normalize("\u{feff}```svgdx\r\n<svg/>\r```\r\n").as_deref(),
+            Some("```svgdx\n<svg/>\n```\n")
+        );
+
+        let dir = std::env::temp_dir().join("mdbook-svgdx-encoding-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bom.svgdx");
+        fs::write(&path, b"\xef\xbb\xbf<svg>\r\n</svg>\r\n").unwrap();
+        assert_eq!(read_source(&path).unwrap(), "<svg>\n</svg>\n");
+
+        let path = dir.join("latin1.svgdx");
+        fs::write(&path, b"<svg>\r\n<text>caf\xe9</text>\r\n</svg>\r\n").unwrap();
+        let e = read_source(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_contains!(e.to_string(), "isn't valid UTF-8 at line 2, column 10");
+    }
+}
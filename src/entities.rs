@@ -0,0 +1,128 @@
+//! Handling of DOCTYPE declarations and entity references in diagram sources,
+//! as set by the `entities` option.
+//!
+//! Whether svgdx expands, keeps or rejects these varies between versions, and
+//! a DOCTYPE's internal subset can define entities expanding to arbitrary
+//! content. They can be passed through unchanged, stripped, or rejected with
+//! an error. The five predefined entities (`&amp;` etc.) and character
+//! references (`&#60;`) are always allowed, as is anything within comments
+//! or CDATA sections.
+
+use std::ops::Range;
+
+const PREDEFINED: [&str; 5] = ["amp", "lt", "gt", "quot", "apos"];
+
+/// A DOCTYPE declaration or entity reference in a diagram source.
+struct Entity {
+    range: Range<usize>,
+    description: String,
+}
+
+/// End of the DOCTYPE declaration starting `s`, including any internal subset.
+fn doctype_len(s: &str) -> usize {
+    let subset_end = match (s.find('['), s.find('>')) {
+        (Some(open), Some(close)) if open < close => s[open..].find(']').map(|i| open + i),
+        _ => None,
+    };
+    let from = subset_end.unwrap_or(0);
+    s[from..].find('>').map_or(s.len(), |i| from + i + 1)
+}
+
+/// The DOCTYPE declarations and references to undefined entities in `source`.
+fn entities(source: &str) -> Vec<Entity> {
+    let mut found = Vec::new();
+    let mut idx = 0;
+    while let Some(offset) = source[idx..].find(['<', '&']) {
+        let start = idx + offset;
+        let rest = &source[start..];
+        idx = start + 1;
+        if let Some(end) = [("<!--", "-->"), ("<![CDATA[", "]]>")]
+            .iter()
+            .find_map(|(open, end)| rest.starts_with(open).then_some(end))
+        {
+            idx = rest
+                .find(end)
+                .map_or(source.len(), |i| start + i + end.len());
+        } else if rest.starts_with("<!DOCTYPE") {
+            idx = start + doctype_len(rest);
+            found.push(Entity {
+                range: start..idx,
+                description: "a DOCTYPE declaration".to_string(),
+            });
+        } else if rest.starts_with('&') {
+            let Some(len) = rest.find(';') else {
+                continue;
+            };
+            let name = &rest[1..len];
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+            if valid_name && !PREDEFINED.contains(&name) {
+                idx = start + len + 1;
+                found.push(Entity {
+                    range: start..idx,
+                    description: format!("the undefined entity '&{};'", name),
+                });
+            }
+        }
+    }
+    found
+}
+
+/// An error naming the first DOCTYPE declaration or undefined entity in
+/// `source`, if any.
+pub(crate) fn reject_entities(source: &str) -> Result<(), String> {
+    match entities(source).first() {
+        Some(entity) => Err(format!(
+            "Diagram contains {}, which isn't allowed (see the `entities` option)",
+            entity.description
+        )),
+        None => Ok(()),
+    }
+}
+
+/// `source` without DOCTYPE declarations and undefined entity references.
+pub(crate) fn strip_entities(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut offset = 0;
+    for entity in entities(source) {
+        stripped.push_str(&source[offset..entity.range.start]);
+        offset = entity.range.end;
+    }
+    stripped.push_str(&source[offset..]);
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"<!DOCTYPE svg [ <!ENTITY big "<rect/>"> ]>
+<svg><!-- &note; --><text>&big; &amp; &#60; AT&T; a & b</text><style><![CDATA[&x;]]></style></svg>"#;
+
+    #[test]
+    fn entities_stripped() {
+        assert_eq!(
+            strip_entities(SOURCE),
+            "\n<svg><!-- &note; --><text> &amp; &#60; AT a & b</text><style><![CDATA[&x;]]></style></svg>"
+        );
+        assert_eq!(strip_entities("<svg>&lt;</svg>"), "<svg>&lt;</svg>");
+    }
+
+    #[test]
+    fn entities_rejected() {
+        assert_eq!(
+            reject_entities(SOURCE),
+            Err("Diagram contains a DOCTYPE declaration, which isn't allowed (see the `entities` option)".to_string())
+        );
+        assert_eq!(
+            reject_entities("<svg><text>&nbsp;</text></svg>"),
+            Err("Diagram contains the undefined entity '&nbsp;', which isn't allowed (see the `entities` option)".to_string())
+        );
+        assert_eq!(
+            reject_entities("<svg><text>&amp;&#x3c;</text></svg>"),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Parsing of fenced code block info strings.
+//!
+//! The first token of the info string is the block type (e.g. `svgdx-xml`),
+//! and may be followed by `key=value` options. Values containing whitespace
+//! may be double-quoted, and a bare `key` is equivalent to `key=true`:
+//!
+//! ~~~markdown
+//! ```svgdx legend=auto caption="Request flow"
+//! ...
+//! ```
+//! ~~~
+
+use std::collections::HashMap;
+
+use crate::config::Profile;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct FenceInfo {
+    /// The info string as written, without surrounding whitespace.
+    pub info: String,
+    pub block_type: String,
+    pub options: HashMap<String, String>,
+    /// Number of the figure, if numbered; assigned during processing.
+    pub figure: Option<String>,
+}
+
+impl FenceInfo {
+    pub fn parse(info: &str) -> Self {
+        let info = info.trim();
+        let (block_type, mut rest) = info.split_once(char::is_whitespace).unwrap_or((info, ""));
+        let mut options = HashMap::new();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            let key_end = rest
+                .find(|c: char| c == '=' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let key = &rest[..key_end];
+            rest = &rest[key_end..];
+            let value = if let Some(after_eq) = rest.strip_prefix('=') {
+                let (value, remaining) = if let Some(quoted) = after_eq.strip_prefix('"') {
+                    quoted.split_once('"').unwrap_or((quoted, ""))
+                } else {
+                    after_eq
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((after_eq, ""))
+                };
+                rest = remaining;
+                value
+            } else {
+                "true"
+            };
+            options.insert(key.to_string(), value.to_string());
+        }
+        Self {
+            info: info.to_string(),
+            block_type: block_type.to_string(),
+            options,
+            figure: None,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.options.get(key).map(String::as_str)
+    }
+
+    /// Whether the block is left out of builds with `profile`, as are
+    /// `status=draft` blocks in release builds.
+    pub fn is_omitted(&self, profile: Profile) -> bool {
+        self.get("status") == Some("draft") && profile == Profile::Release
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_info_string() {
+        let info = FenceInfo::parse(r#"svgdx-xml legend=auto caption="A  diagram" nofig"#);
+        assert_eq!(info.block_type, "svgdx-xml");
+        assert_eq!(info.get("legend"), Some("auto"));
+        assert_eq!(info.get("caption"), Some("A  diagram"));
+        assert_eq!(info.get("nofig"), Some("true"));
+        assert_eq!(info.get("missing"), None);
+
+        let info = FenceInfo::parse("svgdx");
+        assert_eq!(info.block_type, "svgdx");
+        assert!(info.options.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! C interface to the renderer, with the `ffi` feature.
+//!
+//! Tooling not written in Rust can render svgdx sources exactly as a book
+//! would, through the same pipeline as `batch::render_blocks`. Strings are
+//! passed as NUL-terminated UTF-8, and strings returned must be released with
+//! `svgdx_free_string()`. See `include/mdbook_svgdx.h` for the declarations.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::catch_unwind;
+use std::path::Path;
+use std::ptr;
+
+use mdbook::errors::Error;
+use mdbook::Config;
+
+use crate::batch::render_blocks;
+use crate::config::SvgdxConfig;
+
+/// The source was rendered; the output is the SVG.
+pub const SVGDX_OK: c_int = 0;
+/// An argument was null or not valid UTF-8; no output is returned.
+pub const SVGDX_INVALID_ARGUMENT: c_int = 1;
+/// The book's configuration couldn't be loaded; the output is the error.
+pub const SVGDX_CONFIG_ERROR: c_int = 2;
+/// The source couldn't be rendered; the output is the error.
+pub const SVGDX_RENDER_ERROR: c_int = 3;
+/// An internal error occurred; no output is returned.
+pub const SVGDX_INTERNAL_ERROR: c_int = 4;
+
+/// Configuration for the book at `book_dir`, as for the `html` renderer.
+fn book_config(book_dir: &Path) -> Result<SvgdxConfig, Error> {
+    let config = Config::from_disk(book_dir.join("book.toml"))?;
+    SvgdxConfig::from_config(&config, book_dir, "html")
+}
+
+/// The string at `s`, if not null and valid UTF-8.
+///
+/// # Safety
+///
+/// `s` must be null or point to a NUL-terminated string.
+unsafe fn arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Render `source` to SVG as the book in `book_dir` would, or with the
+/// default configuration if `book_dir` is null.
+///
+/// Returns one of the `SVGDX_*` codes. On success `*output` is set to the
+/// SVG, and on configuration or render errors to the error message;
+/// otherwise it is set to null.
+///
+/// # Safety
+///
+/// `book_dir` must be null or point to a NUL-terminated string, `source` must
+/// point to a NUL-terminated string, and `output` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn svgdx_render(
+    book_dir: *const c_char,
+    source: *const c_char,
+    output: *mut *mut c_char,
+) -> c_int {
+    if output.is_null() {
+        return SVGDX_INVALID_ARGUMENT;
+    }
+    *output = ptr::null_mut();
+    let book_dir = if book_dir.is_null() {
+        None
+    } else {
+        match arg(book_dir) {
+            Some(dir) => Some(Path::new(dir)),
+            None => return SVGDX_INVALID_ARGUMENT,
+        }
+    };
+    let Some(source) = arg(source) else {
+        return SVGDX_INVALID_ARGUMENT;
+    };
+    // Panics mustn't unwind into the caller.
+    let result = catch_unwind(|| {
+        let cfg = match book_dir.map(book_config).transpose() {
+            Ok(cfg) => cfg.unwrap_or_default(),
+            Err(e) => return (SVGDX_CONFIG_ERROR, format!("{:#}", e)),
+        };
+        match render_blocks(&[source], &cfg).pop() {
+            Some(Ok(svg)) => (SVGDX_OK, svg),
+            Some(Err(e)) => (SVGDX_RENDER_ERROR, e.to_string()),
+            None => (SVGDX_INTERNAL_ERROR, String::new()),
+        }
+    });
+    match result {
+        Ok((SVGDX_INTERNAL_ERROR, _)) | Err(_) => SVGDX_INTERNAL_ERROR,
+        Ok((code, text)) => match CString::new(text) {
+            Ok(text) => {
+                *output = text.into_raw();
+                code
+            }
+            Err(_) => SVGDX_INTERNAL_ERROR,
+        },
+    }
+}
+
+/// Release a string returned by `svgdx_render()`.
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by `svgdx_render()` which hasn't
+/// already been released.
+#[no_mangle]
+pub unsafe extern "C" fn svgdx_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    /// Render `source` through the C interface.
+    fn render(book_dir: Option<&CStr>, source: &CStr) -> (c_int, Option<String>) {
+        let mut output = ptr::null_mut();
+        let book_dir = book_dir.map_or(ptr::null(), CStr::as_ptr);
+        unsafe {
+            let code = svgdx_render(book_dir, source.as_ptr(), &mut output);
+            let text =
+                (!output.is_null()).then(|| CStr::from_ptr(output).to_string_lossy().into_owned());
+            svgdx_free_string(output);
+            (code, text)
+        }
+    }
+
+    #[test]
+    fn render_over_c_abi() {
+        let (code, svg) = render(None, c"<svg><rect wh=\"20 5\"/></svg>");
+        assert_eq!(code, SVGDX_OK);
+        assert_contains!(svg.unwrap(), r#"<rect width="20" height="5"/>"#);
+
+        let (code, msg) = render(None, c"<svg><rect xy=\"#missing\"/></svg>");
+        assert_eq!(code, SVGDX_RENDER_ERROR);
+        assert_contains!(msg.unwrap(), "#missing");
+
+        let dir = std::env::temp_dir().join("mdbook-svgdx-ffi-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("book.toml"),
+            "[preprocessor.svgdx.transform]\nscale = \"big\"\n",
+        )
+        .unwrap();
+        let dir = CString::new(dir.to_str().unwrap()).unwrap();
+        let (code, msg) = render(Some(&dir), c"<svg/>");
+        assert_eq!(code, SVGDX_CONFIG_ERROR);
+        assert!(msg.is_some());
+
+        let (code, output) = render(None, c"\xff");
+        assert_eq!((code, output), (SVGDX_INVALID_ARGUMENT, None));
+    }
+}
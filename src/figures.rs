@@ -0,0 +1,256 @@
+//! Figure numbering and cross-references.
+//!
+//! With the `figure-numbers` option, each diagram is numbered within its
+//! chapter (e.g. "Figure 3.2" for the second in chapter 3) or through the whole
+//! book, and the number is shown in its caption. A block's `label=<name>`
+//! option gives it an anchor, and a `{{#svgdxref name}}` directive anywhere in
+//! the book is replaced with a link to it. Prefix the directive with a
+//! backslash to include it literally.
+//!
+//! Figures are collected from every chapter before any is rendered, so
+//! references may be to later chapters.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use mdbook::book::Chapter;
+use pulldown_cmark::{CodeBlockKind::Fenced, Event::Start, Tag};
+
+use crate::config::{FigureNumbers, Profile};
+use crate::encoding::normalize;
+use crate::fence::FenceInfo;
+use crate::{chapter_id, is_svgdx_fence};
+
+const DIRECTIVE: &str = "{{#svgdxref";
+
+/// Whether a fenced block of `block_type` is rendered as a figure.
+pub(crate) fn is_figure(block_type: &str) -> bool {
+    is_svgdx_fence(block_type)
+        || matches!(
+            block_type,
+            "svgdx-render" | "svgdx-continue" | "svgdx-seq" | "svgdx-graph" | "svgdx-template"
+        )
+}
+
+/// A labelled figure.
+struct Label {
+    number: Option<String>,
+    caption: Option<String>,
+    /// Path of the figure's chapter, relative to the book's `src` directory.
+    chapter: Option<PathBuf>,
+}
+
+/// The figures of a book.
+#[derive(Default)]
+pub(crate) struct Figures {
+    /// Numbers of each chapter's figures in order, by chapter id.
+    numbers: HashMap<String, Vec<Option<String>>>,
+    labels: HashMap<String, Label>,
+}
+
+impl Figures {
+    /// Number and label each figure in `chapters` included in builds with
+    /// `profile`.
+    pub fn collect(chapters: &[&Chapter], numbering: FigureNumbers, profile: Profile) -> Self {
+        let mut figures = Self::default();
+        let mut book_count = 0;
+        for chapter in chapters {
+            // e.g. "3." (including the separator), or empty if unnumbered.
+            let section = chapter
+                .number
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            let mut numbers = Vec::new();
+            let normalized = normalize(&chapter.content);
+            let content = normalized.as_deref().unwrap_or(&chapter.content);
+            for ev in mdbook::utils::new_cmark_parser(content, false) {
+                let Start(Tag::CodeBlock(Fenced(info))) = ev else {
+                    continue;
+                };
+                let info = FenceInfo::parse(&info);
+                if !is_figure(&info.block_type) || info.is_omitted(profile) {
+                    continue;
+                }
+                book_count += 1;
+                let number = match numbering {
+                    FigureNumbers::None => None,
+                    FigureNumbers::Chapter => Some(format!("{}{}", section, numbers.len() + 1)),
+                    FigureNumbers::Book => Some(book_count.to_string()),
+                };
+                if let Some(label) = info.get("label") {
+                    let figure = Label {
+                        number: number.clone(),
+                        caption: info.get("caption").map(str::to_owned),
+                        chapter: chapter.path.clone(),
+                    };
+                    if figures.labels.insert(label.to_string(), figure).is_some() {
+                        eprintln!(
+                            "Warning: svgdx figure label '{}' is used more than once",
+                            label
+                        );
+                    }
+                }
+                numbers.push(number);
+            }
+            figures.numbers.insert(chapter_id(chapter), numbers);
+        }
+        figures
+    }
+
+    /// Number of figure `idx` (from zero) in the chapter `chapter_id`.
+    pub fn number(&self, chapter_id: &str, idx: usize) -> Option<String> {
+        self.numbers.get(chapter_id)?.get(idx).cloned().flatten()
+    }
+
+    /// Link to the figure `label` from the chapter at `chapter`, whose path
+    /// to the book root is `path_to_root`.
+    fn link(&self, label: &str, chapter: Option<&PathBuf>, path_to_root: &str) -> Option<String> {
+        let figure = self.labels.get(label)?;
+        let text = match (&figure.number, &figure.caption) {
+            (Some(number), _) => format!("Figure {}", number),
+            (None, Some(caption)) => caption.clone(),
+            (None, None) => label.to_string(),
+        };
+        let page = match &figure.chapter {
+            Some(path) if Some(path) != chapter => format!(
+                "{}{}",
+                path_to_root,
+                path.to_string_lossy().replace('\\', "/")
+            ),
+            _ => String::new(),
+        };
+        Some(format!(
+            "[{}](<{}#{}>)",
+            text.replace('[', "\\[").replace(']', "\\]"),
+            page,
+            anchor_id(label)
+        ))
+    }
+}
+
+/// Id of the anchor for the figure `label`.
+pub(crate) fn anchor_id(label: &str) -> String {
+    format!("svgdx-fig-{}", label)
+}
+
+/// Replace reference directives in the markdown `content` of the chapter at
+/// `chapter`, returning `None` if there are none.
+///
+/// References to unknown labels are reported, and shown as "Figure ??".
+pub(crate) fn resolve_refs(
+    content: &str,
+    figures: &Figures,
+    chapter: Option<&PathBuf>,
+    path_to_root: &str,
+) -> Option<String> {
+    if !content.contains(DIRECTIVE) {
+        return None;
+    }
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find(DIRECTIVE) {
+        let Some(len) = rest[start..].find("}}").map(|end| end + 2) else {
+            break;
+        };
+        let directive = &rest[start..start + len];
+        if let Some(before) = rest[..start].strip_suffix('\\') {
+            output.push_str(before);
+            output.push_str(directive);
+        } else {
+            let label = directive[DIRECTIVE.len()..len - 2].trim();
+            output.push_str(&rest[..start]);
+            match figures.link(label, chapter, path_to_root) {
+                Some(link) => output.push_str(&link),
+                None => {
+                    eprintln!(
+                        "Warning: unknown svgdx figure label '{}' in {}",
+                        label,
+                        chapter.map_or("chapter".into(), |path| path.to_string_lossy())
+                    );
+                    output.push_str("Figure ??");
+                }
+            }
+        }
+        rest = &rest[start + len..];
+    }
+    output.push_str(rest);
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mdbook::book::SectionNumber;
+
+    #[test]
+    fn number_and_reference() {
+        let mut intro = Chapter::new("Intro", String::new(), "intro.md", Vec::new());
+        intro.content =
+            "```svgdx label=a\n<svg/>\n```\n\n```svgdx-part\n<rect/>\n```\n\n```xml\n<svg/>\n```\n"
+                .to_string();
+        let mut detail = Chapter::new("Detail", String::new(), "part/detail.md", Vec::new());
+        detail.number = Some(SectionNumber(vec![2, 1]));
+        detail.content = concat!(
+            "```svgdx-seq\nA -> B\n```\n\n",
+            "```svgdx label=b caption=\"The [b] box\"\n<svg/>\n```\n",
+        )
+        .to_string();
+        let chapters = [&intro, &detail];
+
+        let figures = Figures::collect(&chapters, FigureNumbers::Chapter, Profile::Release);
+        assert_eq!(figures.number("intro.md", 0).as_deref(), Some("1"));
+        assert_eq!(figures.number("intro.md", 1), None);
+        assert_eq!(
+            figures.number("part/detail.md", 1).as_deref(),
+            Some("2.1.2")
+        );
+
+        let content =
+            "See {{#svgdxref b}}, {{#svgdxref a }}, \\{{#svgdxref a}} and {{#svgdxref c}}.";
+        let intro_path = intro.path.as_ref();
+        assert_eq!(
+            resolve_refs(content, &figures, intro_path, "").unwrap(),
+            "See [Figure 2.1.2](<part/detail.md#svgdx-fig-b>), [Figure 1](<#svgdx-fig-a>), \
+             {{#svgdxref a}} and Figure ??."
+        );
+        assert_eq!(
+            resolve_refs("No references", &figures, intro_path, ""),
+            None
+        );
+
+        let figures = Figures::collect(&chapters, FigureNumbers::Book, Profile::Release);
+        assert_eq!(figures.number("part/detail.md", 1).as_deref(), Some("3"));
+
+        let figures = Figures::collect(&chapters, FigureNumbers::None, Profile::Release);
+        assert_eq!(figures.number("part/detail.md", 1), None);
+        assert_eq!(
+            resolve_refs("{{#svgdxref b}}", &figures, detail.path.as_ref(), "../").unwrap(),
+            "[The \\[b\\] box](<#svgdx-fig-b>)"
+        );
+    }
+
+    #[test]
+    fn drafts_unnumbered_in_release() {
+        let content =
+            "```svgdx status=draft label=a\n<svg/>\n```\n\n```svgdx label=b\n<svg/>\n```\n";
+        let chapter = Chapter::new("Intro", content.to_string(), "intro.md", Vec::new());
+        let figures = Figures::collect(&[&chapter], FigureNumbers::Book, Profile::Release);
+        assert_eq!(figures.number("intro.md", 0).as_deref(), Some("1"));
+        assert_eq!(figures.number("intro.md", 1), None);
+        assert_eq!(
+            resolve_refs(
+                "{{#svgdxref a}}, {{#svgdxref b}}",
+                &figures,
+                chapter.path.as_ref(),
+                ""
+            )
+            .unwrap(),
+            "Figure ??, [Figure 1](<#svgdx-fig-b>)"
+        );
+
+        let figures = Figures::collect(&[&chapter], FigureNumbers::Book, Profile::Draft);
+        assert_eq!(figures.number("intro.md", 1).as_deref(), Some("2"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Galleries of diagram files.
+//!
+//! A `{{#svgdx-examples path/}}` directive is replaced with a grid of every
+//! `.xml` file in the given directory (relative to the chapter's source file),
+//! each captioned with its file name. Prefix the directive with a backslash
+//! to include it literally.
+//!
+//! Directories listed in the `appendices` option are also shown as appendix
+//! chapters added to the end of the book, with a heading for each diagram.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use mdbook::book::Chapter;
+
+use crate::{error_html, escape_html};
+
+const DIRECTIVE: &str = "{{#svgdx-examples";
+
+/// Sorted names of the `.xml` files in `dir`.
+fn diagram_files(dir: &Path) -> io::Result<Vec<String>> {
+    let mut files = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".xml"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// HTML for a gallery of the diagrams in `path`, as placeholders to be
+/// filled with the rendered diagrams.
+fn gallery_html(path: &str, chapter_dir: &Path) -> String {
+    let dir = chapter_dir.join(path);
+    let files = match diagram_files(&dir) {
+        Ok(files) => files,
+        Err(e) => return error_html(&format!("Could not read {}: {}", dir.display(), e)),
+    };
+    let figures = files
+        .iter()
+        .map(|name| {
+            let src = Path::new(path).join(name);
+            format!(
+                "<figure><div class=\"svgdx\" data-src=\"{}\"></div><figcaption>{}</figcaption></figure>\n",
+                escape_html(&src.to_string_lossy()),
+                escape_html(name)
+            )
+        })
+        .collect::<String>();
+    format!(
+        "<div class='svgdx-gallery' style='display: grid; \
+         grid-template-columns: repeat(auto-fill, minmax(12em, 1fr)); gap: 1em;'>\n{}</div>",
+        figures
+    )
+}
+
+/// An appendix chapter showing each diagram in `dir`, which is relative to
+/// the book's `src_dir`.
+pub(crate) fn appendix_chapter(dir: &Path, src_dir: &Path) -> io::Result<Chapter> {
+    let title = dir.display().to_string();
+    let mut content = format!("# {}\n\n", title);
+    for name in diagram_files(&src_dir.join(dir))? {
+        content.push_str(&format!(
+            "## {}\n\n<div class=\"svgdx\" data-src=\"{}\"></div>\n\n",
+            name,
+            escape_html(&dir.join(&name).to_string_lossy())
+        ));
+    }
+    let slug = title.trim_matches('/').replace(['/', '\\', ' '], "-");
+    let mut chapter = Chapter::new(
+        &title,
+        content,
+        format!("svgdx-appendix-{}.md", slug),
+        Vec::new(),
+    );
+    // There's no source file, so placeholder paths are relative to `src_dir`.
+    chapter.source_path = None;
+    Ok(chapter)
+}
+
+/// Replace gallery directives in the markdown `content`, returning `None`
+/// if there are none.
+pub(crate) fn expand_galleries(content: &str, chapter_dir: &Path) -> Option<String> {
+    if !content.contains(DIRECTIVE) {
+        return None;
+    }
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find(DIRECTIVE) {
+        let Some(len) = rest[start..].find("}}").map(|end| end + 2) else {
+            break;
+        };
+        let directive = &rest[start..start + len];
+        if let Some(before) = rest[..start].strip_suffix('\\') {
+            output.push_str(before);
+            output.push_str(directive);
+        } else {
+            let path = directive[DIRECTIVE.len()..len - 2].trim();
+            output.push_str(&rest[..start]);
+            // Blank lines ensure the gallery is parsed as an HTML block.
+            output.push_str("\n\n");
+            output.push_str(&gallery_html(path, chapter_dir));
+            output.push_str("\n\n");
+        }
+        rest = &rest[start + len..];
+    }
+    output.push_str(rest);
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn expand_gallery_directive() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-gallery-test");
+        std::fs::create_dir_all(dir.join("examples")).unwrap();
+        for name in ["b.xml", "a.xml", "notes.txt"] {
+            std::fs::write(dir.join("examples").join(name), "<svg/>").unwrap();
+        }
+
+        let content = "Gallery:\n{{#svgdx-examples examples/}}\nand \\{{#svgdx-examples x}}";
+        let output = expand_galleries(content, &dir).unwrap();
+        assert_contains!(output, "<div class='svgdx-gallery'");
+        assert_contains!(
+            output,
+            r#"<figure><div class="svgdx" data-src="examples/a.xml"></div><figcaption>a.xml</figcaption></figure>
+<figure><div class="svgdx" data-src="examples/b.xml"></div>"#
+        );
+        assert!(!output.contains("notes.txt"));
+        assert!(output.ends_with("and {{#svgdx-examples x}}"));
+
+        assert_eq!(expand_galleries("No gallery", &dir), None);
+    }
+
+    #[test]
+    fn appendix_for_directory() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-appendix-test");
+        std::fs::create_dir_all(dir.join("diagrams/net")).unwrap();
+        std::fs::write(dir.join("diagrams/net/lan.xml"), "<svg/>").unwrap();
+
+        let chapter = appendix_chapter(Path::new("diagrams/net"), &dir).unwrap();
+        assert_eq!(chapter.name, "diagrams/net");
+        assert_eq!(
+            chapter.path.as_deref(),
+            Some(Path::new("svgdx-appendix-diagrams-net.md"))
+        );
+        assert_contains!(
+            chapter.content,
+            "## lan.xml\n\n<div class=\"svgdx\" data-src=\"diagrams/net/lan.xml\"></div>"
+        );
+        assert!(appendix_chapter(Path::new("missing"), &dir).is_err());
+    }
+}
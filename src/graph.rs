@@ -0,0 +1,165 @@
+//! Flowchart shorthand, expanded to svgdx XML with a simple layered layout.
+//!
+//! `svgdx-graph` blocks contain edges in the same `from -> to: label` form as
+//! `svgdx-seq` blocks, or a bare node name to add an unconnected node:
+//!
+//! ```text
+//! Start -> Check: input
+//! Check -> Done: valid
+//! Check --> Start: retry
+//! ```
+//!
+//! Each node is placed in the layer below all nodes with edges leading to it,
+//! ignoring edges which would form a cycle.
+
+use crate::escape_html;
+use crate::seq::{name_index, parse_arrow};
+
+const NODE_WIDTH: f32 = 30.;
+const NODE_HEIGHT: f32 = 10.;
+const LAYER_SPACING: f32 = 25.;
+const NODE_SPACING: f32 = 40.;
+
+struct Edge {
+    from: usize,
+    to: usize,
+    dashed: bool,
+    label: String,
+}
+
+/// Mark edges leading back to a node currently being visited, which would
+/// otherwise form a cycle when assigning layers.
+fn mark_back_edges(node: usize, edges: &[Edge], state: &mut [u8], back: &mut [bool]) {
+    state[node] = 1;
+    for (idx, edge) in edges.iter().enumerate().filter(|(_, e)| e.from == node) {
+        match state[edge.to] {
+            0 => mark_back_edges(edge.to, edges, state, back),
+            1 => back[idx] = true,
+            _ => {}
+        }
+    }
+    state[node] = 2;
+}
+
+/// Layer of each node: the length of the longest path to it.
+fn layers(count: usize, edges: &[Edge]) -> Vec<usize> {
+    let mut state = vec![0; count];
+    let mut back = vec![false; edges.len()];
+    for node in 0..count {
+        if state[node] == 0 {
+            mark_back_edges(node, edges, &mut state, &mut back);
+        }
+    }
+    let mut layer = vec![0; count];
+    // Without back edges the graph is acyclic, so this settles within
+    // `count` passes.
+    for _ in 0..count {
+        let mut changed = false;
+        for (edge, _) in edges.iter().zip(&back).filter(|(_, back)| !**back) {
+            if edge.from != edge.to && layer[edge.to] <= layer[edge.from] {
+                layer[edge.to] = layer[edge.from] + 1;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    layer
+}
+
+/// Expand flowchart shorthand to svgdx XML.
+pub(crate) fn expand_graph(source: &str) -> Result<String, String> {
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_arrow(line) {
+            Some(Ok(arrow)) => edges.push(Edge {
+                from: name_index(&mut nodes, arrow.from),
+                to: name_index(&mut nodes, arrow.to),
+                dashed: arrow.dashed,
+                label: arrow.label.to_string(),
+            }),
+            Some(Err(e)) => return Err(format!("svgdx-graph line {}: {}", idx + 1, e)),
+            None => {
+                name_index(&mut nodes, line);
+            }
+        }
+    }
+
+    let layer = layers(nodes.len(), &edges);
+    let mut xml = String::from("<svg>\n");
+    let mut placed = vec![0; nodes.len()];
+    for (node, name) in nodes.iter().enumerate() {
+        let in_layer = layer.iter().filter(|l| **l == layer[node]).count();
+        let position = placed[layer[node]];
+        placed[layer[node]] += 1;
+        // Centre each layer horizontally.
+        let x = (position as f32 - (in_layer - 1) as f32 / 2.) * NODE_SPACING - NODE_WIDTH / 2.;
+        let y = layer[node] as f32 * LAYER_SPACING;
+        xml.push_str(&format!(
+            "  <rect id=\"n{node}\" x=\"{x}\" y=\"{y}\" wh=\"{NODE_WIDTH} {NODE_HEIGHT}\" text=\"{}\"/>\n",
+            escape_html(name)
+        ));
+    }
+    for edge in &edges {
+        let class = if edge.dashed {
+            "d-arrow d-dash"
+        } else {
+            "d-arrow"
+        };
+        let label = escape_html(&edge.label);
+        if edge.from == edge.to {
+            xml.push_str(&format!(
+                "  <line start=\"#n{}@r\" end=\"#n{}@b\" class=\"{class}\" text=\"{label}\"/>\n",
+                edge.from, edge.to
+            ));
+        } else {
+            xml.push_str(&format!(
+                "  <line start=\"#n{}\" end=\"#n{}\" class=\"{class}\" text=\"{label}\"/>\n",
+                edge.from, edge.to
+            ));
+        }
+    }
+    xml.push_str("</svg>\n");
+    Ok(xml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn layered_layout() {
+        let xml =
+            expand_graph("A -> B\nA -> C: yes\nB -> D\nC -> D\nD --> A: again\nLonely\n").unwrap();
+        // B and C share the second layer, below A
+        assert_contains!(xml, r#"<rect id="n0" x="-35" y="0" "#);
+        assert_contains!(xml, r#"<rect id="n1" x="-35" y="25" "#);
+        assert_contains!(xml, r#"<rect id="n2" x="5" y="25" "#);
+        assert_contains!(xml, r#"<rect id="n3" x="-15" y="50" "#);
+        // Unconnected nodes stay in the first layer
+        assert_contains!(
+            xml,
+            r#"<rect id="n4" x="5" y="0" wh="30 10" text="Lonely"/>"#
+        );
+        assert_contains!(
+            xml,
+            r##"<line start="#n3" end="#n0" class="d-arrow d-dash" text="again"/>"##
+        );
+    }
+
+    #[test]
+    fn reject_missing_names() {
+        assert_eq!(
+            expand_graph("A ->").unwrap_err(),
+            "svgdx-graph line 1: missing name in 'A ->'"
+        );
+    }
+}
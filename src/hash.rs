@@ -0,0 +1,26 @@
+//! Stable content hashing.
+//!
+//! Hashes are persisted between builds (e.g. in the figure manifest), so must
+//! not depend on the Rust version or process like `std::hash` does.
+
+/// 64-bit FNV-1a hash of `data`, as 16 hex digits.
+pub(crate) fn content_hash(data: impl AsRef<[u8]>) -> String {
+    let hash = data
+        .as_ref()
+        .iter()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_reference_values() {
+        assert_eq!(content_hash(""), "cbf29ce484222325");
+        assert_eq!(content_hash("a"), "af63dc4c8601ec8c");
+    }
+}
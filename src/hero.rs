@@ -0,0 +1,80 @@
+//! Full-width "hero" diagrams at the top of chapters.
+//!
+//! A chapter starting with front matter containing a `svgdx-hero` key shows
+//! the referenced diagram file (relative to the chapter's source file) above
+//! the chapter content:
+//!
+//! ```text
+//! ---
+//! svgdx-hero: diagrams/overview.xml
+//! ---
+//! ```
+//!
+//! The key is removed from the front matter, which is dropped entirely if no
+//! other keys remain.
+
+use crate::escape_html;
+
+const KEY: &str = "svgdx-hero:";
+
+/// Styles for the hero, using the current mdbook theme's colours.
+const HERO_STYLE: &str = "<style>\
+.svgdx-hero { background: var(--quote-bg); padding: 1em; margin-bottom: 1em; } \
+.svgdx-hero svg { width: 100%; height: auto; }\
+</style>";
+
+/// The chapter `content` with a hero placeholder after any front matter,
+/// or `None` if the front matter has no `svgdx-hero` key.
+pub(crate) fn insert_hero(content: &str) -> Option<String> {
+    let body = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))?;
+    let (front_matter, rest) = body
+        .split_once("\n---\n")
+        .or_else(|| body.split_once("\r\n---\r\n"))?;
+    let path = front_matter
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(KEY))?
+        .trim();
+    let remaining = front_matter
+        .lines()
+        .filter(|line| !line.trim().starts_with(KEY))
+        .collect::<Vec<_>>();
+
+    let mut output = String::with_capacity(content.len());
+    if !remaining.is_empty() {
+        output.push_str(&format!("---\n{}\n---\n", remaining.join("\n")));
+    }
+    output.push_str(&format!(
+        "\n{}\n<div class=\"svgdx svgdx-hero\" data-src=\"{}\"></div>\n\n",
+        HERO_STYLE,
+        escape_html(path)
+    ));
+    output.push_str(rest);
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn hero_after_front_matter() {
+        let content = "---\nsvgdx-hero: img/hero.xml\n---\n# Title\n";
+        let output = insert_hero(content).unwrap();
+        assert!(output.starts_with("\n<style>"));
+        assert_contains!(
+            output,
+            "<div class=\"svgdx svgdx-hero\" data-src=\"img/hero.xml\"></div>\n\n# Title\n"
+        );
+
+        let content = "---\ntitle: Intro\nsvgdx-hero: hero.xml\n---\n# Title\n";
+        let output = insert_hero(content).unwrap();
+        assert!(output.starts_with("---\ntitle: Intro\n---\n\n<style>"));
+
+        assert_eq!(insert_hero("---\ntitle: Intro\n---\n# Title\n"), None);
+        assert_eq!(insert_hero("# svgdx-hero: x\n"), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Processing of svgdx diagrams within raw HTML in chapters.
+//!
+//! Books mixing hand-written HTML with markdown may use placeholder elements
+//! such as `<div class="svgdx" data-src="diagrams/arch.xml"></div>`, which are
+//! filled with the diagram rendered from the referenced file. The path is
+//! relative to the chapter's source file.
+//!
+//! Fenced code blocks nested within raw HTML (e.g. a `<details>` element) are
+//! not parsed as markdown, so svgdx code may also be given as HTML code
+//! blocks such as `<pre><code class="language-svgdx">...</code></pre>`.
+
+use crate::{error_html, escape_html, is_svgdx_fence, unhide_lines, Deferred, Renderer};
+
+/// Value of the attribute `name` within a single start tag.
+fn tag_attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    ['"', '\''].into_iter().find_map(|quote| {
+        let needle = format!(" {}={}", name, quote);
+        let start = tag.find(&needle)? + needle.len();
+        tag[start..].split(quote).next()
+    })
+}
+
+/// Replace any svgdx placeholder elements in `html` with rendered diagrams,
+/// returning `None` if there are none.
+pub(crate) fn fill_placeholders(html: &str, renderer: &mut Renderer) -> Option<String> {
+    let mut output = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<div") {
+        let Some(tag_len) = rest[start..].find('>').map(|end| end + 1) else {
+            break;
+        };
+        let tag = &rest[start..start + tag_len];
+        output.push_str(&rest[..start + tag_len]);
+        rest = &rest[start + tag_len..];
+
+        let is_svgdx = tag_attr(tag, "class")
+            .is_some_and(|classes| classes.split_whitespace().any(|c| c == "svgdx"));
+        let Some(src) = tag_attr(tag, "data-src").filter(|_| is_svgdx) else {
+            continue;
+        };
+        let svg = match renderer.read_src(src) {
+            Ok(source) => renderer.defer(Deferred::Diagram(source)),
+            Err(e) => error_html(&e),
+        };
+        output.push_str(&svg);
+    }
+    if output.is_empty() {
+        return None;
+    }
+    output.push_str(rest);
+    Some(output)
+}
+
+fn unescape_html(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Replace any `<pre><code class="language-svgdx">` blocks in `html` with
+/// rendered diagrams, returning `None` if there are none.
+pub(crate) fn render_code_islands(html: &str, renderer: &mut Renderer) -> Option<String> {
+    const OPEN: &str = "<pre><code class=\"language-";
+    const CLOSE: &str = "</code></pre>";
+
+    let mut output = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find(OPEN) {
+        let after_open = &rest[start + OPEN.len()..];
+        let Some((block_type, after_type)) = after_open.split_once('"') else {
+            break;
+        };
+        let code_start = after_type.find('>').map(|i| i + 1);
+        let code_end = after_type.find(CLOSE);
+        let (Some(code_start), Some(code_end)) = (code_start, code_end) else {
+            break;
+        };
+        let block_end = start + OPEN.len() + block_type.len() + 1 + code_end + CLOSE.len();
+        if !is_svgdx_fence(block_type) || code_start > code_end {
+            output.push_str(&rest[..block_end]);
+            rest = &rest[block_end..];
+            continue;
+        }
+        let escaped_source = &after_type[code_start..code_end];
+        let mut source = unescape_html(escaped_source);
+        let mut hidelines = String::new();
+        if let Some(marker) = &renderer.cfg.hidden_line_marker {
+            source = unhide_lines(&source, marker);
+            hidelines = format!(" hidelines={}", escape_html(marker));
+        }
+        let xml = format!(
+            "<pre><code class=\"language-xml{}\">{}</code></pre>",
+            hidelines, escaped_source
+        );
+
+        output.push_str(&rest[..start]);
+        output.push_str(&format!("<div class='{}'>\n", escape_html(block_type)));
+        if block_type.starts_with("xml-svgdx") {
+            output.push_str(&xml);
+        }
+        output.push_str(&renderer.defer(Deferred::Diagram(source)));
+        if block_type.starts_with("svgdx-xml") {
+            output.push_str(&xml);
+        }
+        output.push_str("\n</div>");
+        rest = &rest[block_end..];
+    }
+    if output.is_empty() {
+        return None;
+    }
+    output.push_str(rest);
+    Some(output)
+}
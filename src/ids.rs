@@ -0,0 +1,103 @@
+//! Detection of element ids defined by more than one diagram in a chapter.
+//!
+//! Inline diagrams share the page's id namespace, so with `namespace-ids`
+//! disabled (e.g. so that links can target elements of diagrams), an id used
+//! in two diagrams refers to whichever comes first. Sources are scanned for
+//! `id` attributes before rendering, so ids generated by svgdx (e.g. within
+//! loops) aren't seen, and elements of `<specs>` templates are ignored.
+
+use std::collections::HashMap;
+
+/// Ids defined in svgdx `source`, with the (0-based) line of each.
+fn source_ids(source: &str) -> Vec<(String, usize)> {
+    let mut ids = Vec::new();
+    let mut in_specs = false;
+    for (idx, line) in source.lines().enumerate() {
+        let mut rest = line;
+        loop {
+            let specs = rest.find("<specs").filter(|_| !in_specs);
+            let end_specs = rest.find("</specs>").filter(|_| in_specs);
+            let id = rest.find(" id=").filter(|_| !in_specs);
+            let next = [specs, end_specs, id].into_iter().flatten().min();
+            match next {
+                Some(pos) if Some(pos) == specs => {
+                    in_specs = !rest[pos..]
+                        .split('>')
+                        .next()
+                        .unwrap_or_default()
+                        .ends_with('/');
+                    rest = &rest[pos + 1..];
+                }
+                Some(pos) if Some(pos) == end_specs => {
+                    in_specs = false;
+                    rest = &rest[pos + 1..];
+                }
+                Some(pos) => {
+                    rest = &rest[pos + " id=".len()..];
+                    let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'')
+                    else {
+                        continue;
+                    };
+                    if let Some((id, _)) = rest[1..].split_once(quote) {
+                        ids.push((id.to_string(), idx));
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+    ids
+}
+
+/// Ids defined by the diagrams of a chapter so far.
+#[derive(Debug, Default)]
+pub(crate) struct ChapterIds {
+    /// The line of each id, and of the opening fence of the block defining it.
+    ids: HashMap<String, (usize, usize)>,
+}
+
+impl ChapterIds {
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+
+    /// Record the ids of the block whose opening fence is at `line` (1-based)
+    /// with the given `source`, describing those already defined by earlier
+    /// blocks, e.g. "'a' (line 12; also line 4, in the block at line 3)".
+    pub fn check(&mut self, source: &str, line: usize) -> Vec<String> {
+        let mut duplicates = Vec::new();
+        for (id, idx) in source_ids(source) {
+            let id_line = line + 1 + idx;
+            match self.ids.get(&id) {
+                Some(&(first, block)) if block != line => duplicates.push(format!(
+                    "'{}' (line {}; also line {}, in the block at line {})",
+                    id, id_line, first, block
+                )),
+                Some(_) => {}
+                None => {
+                    self.ids.insert(id, (id_line, line));
+                }
+            }
+        }
+        duplicates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_ids_reported() {
+        let mut ids = ChapterIds::default();
+        let first = "<svg>\n<rect id=\"a\" wh=\"5\"/>\n<specs><rect id='box'/></specs>\n</svg>\n";
+        assert!(ids.check(first, 3).is_empty());
+        let second = "<svg>\n<specs>\n<g id=\"box\"/>\n</specs>\n<circle id='b' r='1'/><rect id=\"a\"/>\n</svg>\n";
+        assert_eq!(
+            ids.check(second, 10),
+            vec!["'a' (line 15; also line 5, in the block at line 3)"]
+        );
+        ids.clear();
+        assert!(ids.check(second, 10).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! External images referenced by diagrams.
+//!
+//! `<image href="...">` elements referring to files relative to the chapter
+//! break if the built book is moved away from its source, so with the
+//! `external-images` option such files are either inlined as `data:` URIs or
+//! copied to the assets directory with the reference rewritten. Only files
+//! within the book's source directory are used, so that a diagram can't
+//! publish other files readable by the build.
+
+use std::fs;
+use std::path::Path;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::hash::content_hash;
+
+/// MIME type of an image file, from its extension.
+fn mime_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        _ => return None,
+    })
+}
+
+/// Whether `href` refers to a local file, rather than e.g. a URL or fragment.
+fn is_local(href: &str) -> bool {
+    !(href.is_empty()
+        || href.starts_with('#')
+        || href.starts_with('/')
+        || href.contains("://")
+        || href.starts_with("data:"))
+}
+
+/// `data:` URI for the image file at `path`.
+pub(crate) fn data_uri(path: &Path, data: &[u8]) -> Option<String> {
+    Some(format!(
+        "data:{};base64,{}",
+        mime_type(path)?,
+        BASE64_STANDARD.encode(data)
+    ))
+}
+
+/// Name for a copy of the image file at `path` with contents `data`,
+/// unique to its content.
+pub(crate) fn asset_name(path: &Path, data: &[u8]) -> String {
+    let hash = content_hash(data);
+    match path.extension() {
+        Some(ext) => format!("{}.{}", hash, ext.to_string_lossy()),
+        None => hash,
+    }
+}
+
+/// An `<image>` reference which couldn't be rewritten.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ImageWarning {
+    /// The file couldn't be read.
+    Unreadable(String),
+    /// The file is outside the book's source directory.
+    OutsideSource(String),
+}
+
+/// The contents of the file at `path`, if it is within `root`.
+fn read_within(path: &Path, root: &Path) -> Result<Vec<u8>, ImageWarning> {
+    let unreadable = |e: std::io::Error| {
+        ImageWarning::Unreadable(format!("couldn't read image {}: {}", path.display(), e))
+    };
+    let canonical = path.canonicalize().map_err(unreadable)?;
+    if !root
+        .canonicalize()
+        .is_ok_and(|root| canonical.starts_with(root))
+    {
+        return Err(ImageWarning::OutsideSource(format!(
+            "image {} is outside the book's source directory",
+            path.display()
+        )));
+    }
+    fs::read(&canonical).map_err(unreadable)
+}
+
+/// Replace the references of `<image>` elements in `svg` to local files,
+/// relative to `dir`, with the result of `rewrite(path, contents)`.
+///
+/// References are left unchanged where `rewrite` returns `None`, and for
+/// files which can't be read or aren't within `root`, which are reported in
+/// the returned warnings.
+pub(crate) fn rewrite_images(
+    svg: &str,
+    dir: &Path,
+    root: &Path,
+    mut rewrite: impl FnMut(&Path, &[u8]) -> Option<String>,
+) -> (String, Vec<ImageWarning>) {
+    let mut output = String::with_capacity(svg.len());
+    let mut warnings = Vec::new();
+    let mut rest = svg;
+    while let Some(start) = rest.find("<image ") {
+        let Some(tag_len) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start..start + tag_len];
+        let href = [" href=\"", " xlink:href=\""].iter().find_map(|needle| {
+            let value_start = tag.find(needle)? + needle.len();
+            let value_len = tag[value_start..].find('"')?;
+            Some(start + value_start..start + value_start + value_len)
+        });
+        let Some(href) = href.filter(|href| is_local(&rest[href.clone()])) else {
+            output.push_str(&rest[..start + tag_len]);
+            rest = &rest[start + tag_len..];
+            continue;
+        };
+        let path = dir.join(&rest[href.clone()]);
+        output.push_str(&rest[..href.start]);
+        match read_within(&path, root) {
+            Ok(data) => match rewrite(&path, &data) {
+                Some(new_href) => output.push_str(&new_href),
+                None => output.push_str(&rest[href.clone()]),
+            },
+            Err(warning) => {
+                warnings.push(warning);
+                output.push_str(&rest[href.clone()]);
+            }
+        }
+        rest = &rest[href.end..];
+    }
+    output.push_str(rest);
+    (output, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_local_images() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-images-test");
+        fs::create_dir_all(dir.join("img")).unwrap();
+        fs::write(dir.join("img/a.png"), b"PNG").unwrap();
+
+        let svg = r#"<svg><image href="img/a.png"/><image xlink:href="https://example.com/b.png"/><image href="missing.png"/></svg>"#;
+        let (output, warnings) = rewrite_images(svg, &dir, &dir, data_uri);
+        assert_eq!(
+            output,
+            r#"<svg><image href="data:image/png;base64,UE5H"/><image xlink:href="https://example.com/b.png"/><image href="missing.png"/></svg>"#
+        );
+        assert!(
+            matches!(&warnings[..], [ImageWarning::Unreadable(w)] if w.contains("missing.png"))
+        );
+
+        assert_eq!(
+            asset_name(Path::new("img/a.png"), b"PNG"),
+            format!("{}.png", content_hash("PNG"))
+        );
+    }
+
+    #[test]
+    fn images_outside_source_kept() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-images-outside-test");
+        fs::create_dir_all(dir.join("src/part")).unwrap();
+        fs::write(dir.join("secret.png"), b"PNG").unwrap();
+
+        let svg = r#"<svg><image href="../../secret.png"/></svg>"#;
+        let (output, warnings) =
+            rewrite_images(svg, &dir.join("src/part"), &dir.join("src"), data_uri);
+        assert_eq!(output, svg);
+        assert!(matches!(
+            &warnings[..],
+            [ImageWarning::OutsideSource(w)] if w.contains("secret.png")
+        ));
+    }
+}
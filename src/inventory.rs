@@ -0,0 +1,142 @@
+//! Machine-readable inventory of the svgdx blocks in a book.
+//!
+//! Each block is listed with its location, fence options and a hash of its
+//! (normalized) source, for tooling tracking diagram ownership and review.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use mdbook::book::Book;
+use mdbook::errors::Error;
+use mdbook::{BookItem, MDBook};
+use pulldown_cmark::{CodeBlockKind::Fenced, Event, Tag, TagEnd};
+use serde::Serialize;
+
+use crate::encoding::normalize;
+use crate::fence::FenceInfo;
+use crate::hash::content_hash;
+use crate::{is_svgdx_fence, normalized_source};
+
+#[derive(Debug, Serialize)]
+pub struct InventoryEntry {
+    /// Chapter source path, relative to the book's `src` directory.
+    pub chapter: String,
+    /// Line number of the opening fence.
+    pub line: usize,
+    pub block_type: String,
+    pub attributes: BTreeMap<String, String>,
+    pub caption: Option<String>,
+    pub owner: Option<String>,
+    pub status: Option<String>,
+    /// Hash of the block's source, ignoring insignificant whitespace.
+    pub hash: String,
+}
+
+/// The svgdx blocks of every chapter in `book`, in book order.
+pub fn inventory(book: &Book) -> Vec<InventoryEntry> {
+    let mut entries = Vec::new();
+    for item in book.iter() {
+        let BookItem::Chapter(chapter) = item else {
+            continue;
+        };
+        let path = chapter
+            .source_path
+            .as_ref()
+            .or(chapter.path.as_ref())
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| chapter.name.clone());
+        let mut block: Option<(FenceInfo, usize, String)> = None;
+        let normalized = normalize(&chapter.content);
+        let content = normalized.as_deref().unwrap_or(&chapter.content);
+        let parser = mdbook::utils::new_cmark_parser(content, false);
+        for (ev, range) in parser.into_offset_iter() {
+            match ev {
+                Event::Start(Tag::CodeBlock(Fenced(info))) => {
+                    let info = FenceInfo::parse(&info);
+                    let extended = matches!(
+                        info.block_type.as_str(),
+                        "svgdx-render" | "svgdx-seq" | "svgdx-graph"
+                    );
+                    if is_svgdx_fence(&info.block_type) || extended {
+                        let line = content[..range.start].lines().count() + 1;
+                        block = Some((info, line, String::new()));
+                    }
+                }
+                Event::Text(text) => {
+                    if let Some((_, _, source)) = &mut block {
+                        source.push_str(&text);
+                    }
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    if let Some((info, line, source)) = block.take() {
+                        entries.push(InventoryEntry {
+                            chapter: path.clone(),
+                            line,
+                            caption: info.get("caption").map(str::to_owned),
+                            owner: info.get("owner").map(str::to_owned),
+                            status: info.get("status").map(str::to_owned),
+                            block_type: info.block_type,
+                            attributes: info.options.into_iter().collect(),
+                            hash: content_hash(normalized_source(&source)),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    entries
+}
+
+/// JSON inventory of the book at `root`.
+pub fn book_inventory(root: &Path) -> Result<String, Error> {
+    let md = MDBook::load(root)?;
+    Ok(serde_json::to_string_pretty(&inventory(&md.book))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mdbook::book::Chapter;
+
+    #[test]
+    fn list_blocks_with_location() {
+        let content = r#"# Title
+
+```svgdx caption="A box" owner=ops
+<svg>
+  <rect wh="20 5"/>
+</svg>
+```
+
+```xml
+<not-svgdx/>
+```
+
+```svgdx-xml
+<svg>
+    <rect wh="20 5"/>   
+</svg>
+```
+"#;
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Intro",
+            content.to_string(),
+            "intro.md",
+            Vec::new(),
+        ));
+        let entries = inventory(&book);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].chapter, "intro.md");
+        assert_eq!(entries[0].line, 3);
+        assert_eq!(entries[0].caption.as_deref(), Some("A box"));
+        assert_eq!(entries[0].owner.as_deref(), Some("ops"));
+        assert_eq!(entries[0].status, None);
+        assert_eq!(entries[1].line, 13);
+        assert_eq!(entries[1].block_type, "svgdx-xml");
+        // Whitespace differences don't affect the hash
+        assert_eq!(entries[0].hash, entries[1].hash);
+    }
+}
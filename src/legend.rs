@@ -0,0 +1,100 @@
+//! Legends listing the meaning of classes used in a diagram.
+//!
+//! Blocks with the `legend=auto` option are scanned for classes listed in the
+//! `[preprocessor.svgdx.legend]` table of `book.toml`, and a legend with a
+//! swatch and description for each is emitted beneath the diagram.
+
+use std::collections::HashMap;
+
+use crate::escape_html;
+use crate::svg::remove_root_attr;
+
+/// Classes used in the svgdx `source`, in order of first appearance.
+fn source_classes(source: &str) -> Vec<&str> {
+    let mut classes = Vec::new();
+    for (idx, attr) in source.match_indices("class=") {
+        let rest = &source[idx + attr.len()..];
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        let Some((value, _)) = rest[1..].split_once(quote) else {
+            continue;
+        };
+        for class in value.split_whitespace() {
+            if !classes.contains(&class) {
+                classes.push(class);
+            }
+        }
+    }
+    classes
+}
+
+/// A small square styled with the given class, rendered via svgdx so it
+/// matches the appearance of the class in diagrams.
+fn swatch(class: &str) -> String {
+    let cfg = svgdx::TransformConfig {
+        border: 1,
+        use_local_styles: true,
+        svg_style: Some("width: 1em; height: 1em; vertical-align: middle;".to_string()),
+        ..Default::default()
+    };
+    let source = format!(
+        r#"<svg><rect wh="4" class="{}"/></svg>"#,
+        escape_html(class)
+    );
+    svgdx::transform_str(source, &cfg)
+        .map(|svg| {
+            let svg = remove_root_attr(&remove_root_attr(&svg, "width"), "height");
+            svg.lines().map(str::trim).collect()
+        })
+        .unwrap_or_default()
+}
+
+/// HTML legend for the classes of `source` which have a meaning in `legend`,
+/// or `None` if there are no such classes.
+pub(crate) fn legend_html(source: &str, legend: &HashMap<String, String>) -> Option<String> {
+    let items = source_classes(source)
+        .into_iter()
+        .filter_map(|class| {
+            legend
+                .get(class)
+                .map(|meaning| format!("<li>{} {}</li>\n", swatch(class), escape_html(meaning)))
+        })
+        .collect::<String>();
+    if items.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "<ul class='svgdx-legend' style='list-style: none;'>\n{}</ul>\n",
+        items
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::{assert_contains, assert_not_contains};
+
+    #[test]
+    fn legend_lists_known_classes() {
+        let source = r#"<svg>
+  <rect wh="4" class="d-red big"/>
+  <rect wh="4" class='d-blu d-red'/>
+  <rect wh="4" class="d-green"/>
+</svg>"#;
+        assert_eq!(source_classes(source), ["d-red", "big", "d-blu", "d-green"]);
+
+        let legend = HashMap::from([
+            ("d-blu".to_string(), "Success".to_string()),
+            ("d-red".to_string(), "Failure & retry".to_string()),
+            ("d-yellow".to_string(), "Unused".to_string()),
+        ]);
+        let html = legend_html(source, &legend).unwrap();
+        assert_contains!(html, "Failure &amp; retry</li>");
+        assert_not_contains!(html, "Unused");
+        assert!(html.find("Failure").unwrap() < html.find("Success").unwrap());
+
+        assert_eq!(legend_html("<svg/>", &legend), None);
+    }
+}
@@ -19,23 +19,126 @@
 //! this is heavily based on, see the
 //! [preprocessor developer docs](https://rust-lang.github.io/mdBook/for_developers/preprocessors.html)
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
 use mdbook::book::{Book, Chapter};
 use mdbook::errors::Error;
 use mdbook::preprocess::{Preprocessor, PreprocessorContext};
 use mdbook::BookItem;
 
-use pulldown_cmark::CodeBlockKind;
 use pulldown_cmark::{
     CodeBlockKind::Fenced,
-    CowStr::Borrowed,
-    Event,
     Event::{End, Html, Start, Text},
     Tag, TagEnd,
 };
-use pulldown_cmark_to_cmark::cmark;
+
+mod assets;
+pub mod batch;
+mod block_config;
+mod cache;
+pub mod config;
+mod continuation;
+pub mod doctor;
+pub mod encoding;
+mod entities;
+mod fence;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod figures;
+mod gallery;
+mod graph;
+mod hash;
+mod hero;
+mod html;
+mod ids;
+mod images;
+pub mod inventory;
+mod legend;
+mod links;
+mod manifest;
+mod palette;
+mod preamble;
+pub mod preview;
+mod qr;
+pub mod selftest;
+mod seq;
+pub mod slides;
+mod svg;
+mod template;
+mod timings;
+mod whitespace;
+pub mod worker;
+
+use assets::{figure_hash, img_element, write_figure, write_raster};
+use block_config::{overridden_settings, take_config_elements};
+use cache::{cache_key, cached, write_atomically};
+use config::{
+    AutoStyles, Entities, ExternalImages, Isolation, Output, Profile, Sizing, SourceLayout,
+    SvgFormat, SvgdxConfig, TransformOptions, Wrap, WrapperStyle,
+};
+use continuation::extend_diagram;
+use encoding::{normalize, read_source};
+use entities::{reject_entities, strip_entities};
+use fence::FenceInfo;
+use figures::{anchor_id, is_figure, resolve_refs, Figures};
+use gallery::{appendix_chapter, expand_galleries};
+use graph::expand_graph;
+use hash::content_hash;
+use hero::insert_hero;
+use html::{fill_placeholders, render_code_islands};
+use ids::ChapterIds;
+use images::{asset_name, data_uri, rewrite_images, ImageWarning};
+use legend::legend_html;
+use links::dead_links;
+use manifest::{update_manifest, Manifest};
+use palette::off_palette;
+use preamble::with_preamble;
+use qr::{draw_qr_codes, expand_qr};
+use seq::expand_seq;
+use svg::{
+    add_accessibility, add_draft_banner, add_footer, add_high_contrast_style, add_pattern_styles,
+    add_root_class, add_version_comment, encode_blank_lines, harmonize_text_size, namespace_ids,
+    remove_root_attr, root_tag, root_title, scope_author_styles, scope_styles, set_style_id,
+    strip_styles, unescape_foreign_text, view_box,
+};
+use template::Table;
+use timings::{timings_table, Timings};
+use whitespace::{minify, pretty_print, without_layout};
+use worker::render_isolated;
 
 pub struct SvgdxProc;
 
+/// Why preprocessing failed, so that a broken diagram can be told apart from
+/// a misconfigured book.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The `[preprocessor.svgdx]` configuration is invalid.
+    Config(String),
+    /// Diagrams failed to render, with `fail-on-error` or when validating.
+    Render(String),
+    /// A file couldn't be read or written.
+    Io(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Config(msg) | Self::Render(msg) | Self::Io(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
 impl Preprocessor for SvgdxProc {
     fn name(&self) -> &str {
         "svgdx"
@@ -46,142 +149,3039 @@ impl Preprocessor for SvgdxProc {
         renderer != "not-supported"
     }
 
-    fn run(&self, _: &PreprocessorContext, book: Book) -> Result<Book, Error> {
-        let mut book = book;
-        book.for_each_mut(|item| {
-            if let BookItem::Chapter(chapter) = item {
-                if let Ok(processed) = codeblock_parser(chapter) {
-                    chapter.content = processed;
-                }
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book, Error> {
+        let cfg = SvgdxConfig::from_context(ctx)
+            .map_err(|e| BuildError::Config(format!("Invalid svgdx configuration: {:#}", e)))?;
+        if cfg.skip_renderers.contains(&ctx.renderer) {
+            return Ok(book);
+        }
+        // When only validating, diagrams are rendered from a copy of the book
+        // so that the book itself is passed through unchanged.
+        let validate = cfg.validate_renderers.contains(&ctx.renderer);
+        let mut processed = book.clone();
+        for dir in &cfg.appendices {
+            let chapter = appendix_chapter(dir, &cfg.src_dir).map_err(|e| {
+                BuildError::Io(format!("Could not read appendix {}: {}", dir.display(), e))
+            })?;
+            processed.push_item(chapter);
+        }
+        let chapters = processed
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => Some(chapter),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let (contents, renderer) = process_chapters(&chapters, cfg);
+        let mut contents = contents.into_iter();
+        for_each_chapter_mut(&mut processed.sections, &mut |chapter| {
+            if let Some(Some(content)) = contents.next() {
+                chapter.content = content;
             }
         });
-        Ok(book)
+        if renderer.cfg.timings {
+            eprint!(
+                "svgdx timings (ms):\n{}",
+                timings_table(&renderer.chapter_timings)
+            );
+        }
+        if renderer.cfg.fail_on_error && !renderer.errors.is_empty() {
+            return Err(BuildError::Render(format!(
+                "svgdx diagrams failed to render:\n{}",
+                renderer.errors.join("\n")
+            ))
+            .into());
+        }
+        if validate {
+            if !renderer.failures.is_empty() {
+                return Err(BuildError::Render(format!(
+                    "svgdx diagrams failed to render in: {}",
+                    renderer.failures.join(", ")
+                ))
+                .into());
+            }
+            return Ok(book);
+        }
+        if let Some(path) = &renderer.cfg.manifest {
+            let path = ctx.root.join(path);
+            update_manifest(&path, &renderer.manifest).map_err(|e| {
+                BuildError::Io(format!(
+                    "Could not write manifest {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
+        Ok(processed)
+    }
+}
+
+/// Apply `process` to each of `items` with a renderer for `cfg`, returning
+/// the results in order, and the renderers used.
+///
+/// Unless the `parallel` option is disabled, items are shared between a
+/// thread per CPU, each with its own renderer.
+fn process_parallel<T: Sync, R: Send>(
+    items: &[T],
+    cfg: &SvgdxConfig,
+    process: impl Fn(&mut Renderer, &T) -> R + Sync,
+) -> (Vec<R>, Vec<Renderer>) {
+    let threads = if cfg.parallel {
+        thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(items.len())
+    } else {
+        1
+    };
+    let next = AtomicUsize::new(0);
+    let work = |mut renderer: Renderer| {
+        let mut results = Vec::new();
+        loop {
+            let idx = next.fetch_add(1, Ordering::Relaxed);
+            let Some(item) = items.get(idx) else {
+                break;
+            };
+            results.push((idx, process(&mut renderer, item)));
+        }
+        (results, renderer)
+    };
+    let outputs = if threads > 1 {
+        thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|_| {
+                    let renderer = Renderer::new(cfg.clone());
+                    scope.spawn(|| work(renderer))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("svgdx processing panicked"))
+                .collect::<Vec<_>>()
+        })
+    } else {
+        vec![work(Renderer::new(cfg.clone()))]
+    };
+
+    let mut ordered = items.iter().map(|_| None).collect::<Vec<_>>();
+    let mut renderers = Vec::new();
+    for (results, renderer) in outputs {
+        for (idx, result) in results {
+            ordered[idx] = Some(result);
+        }
+        renderers.push(renderer);
+    }
+    let results = ordered
+        .into_iter()
+        .map(|result| result.expect("every item processed"))
+        .collect();
+    (results, renderers)
+}
+
+/// Process each of `chapters`, returning their new content (`None` if it
+/// couldn't be generated) and a renderer holding the combined failures and
+/// manifest.
+/// Call `f` for each chapter in `items` in the order of `Book::iter`, with
+/// each chapter before its sub-chapters.
+fn for_each_chapter_mut(items: &mut [BookItem], f: &mut impl FnMut(&mut Chapter)) {
+    for item in items {
+        if let BookItem::Chapter(chapter) = item {
+            f(chapter);
+            for_each_chapter_mut(&mut chapter.sub_items, f);
+        }
+    }
+}
+
+fn process_chapters(chapters: &[&Chapter], cfg: SvgdxConfig) -> (Vec<Option<String>>, Renderer) {
+    // Figures are numbered before any chapter is processed, so references to
+    // them can be resolved in any chapter.
+    let figures = Arc::new(Figures::collect(chapters, cfg.figure_numbers, cfg.profile));
+    let (results, renderers) = process_parallel(chapters, &cfg, |renderer, chapter| {
+        renderer.figures = Arc::clone(&figures);
+        let failures = renderer.failures.len();
+        let errors = renderer.errors.len();
+        let content = codeblock_parser(chapter, renderer).ok();
+        let failed = renderer.failures.len() > failures;
+        let timings = std::mem::take(&mut renderer.timings);
+        (content, failed, renderer.errors.split_off(errors), timings)
+    });
+
+    let mut combined = Renderer::new(cfg);
+    for renderer in renderers {
+        combined.manifest.extend(renderer.manifest);
+    }
+    let mut contents = Vec::with_capacity(chapters.len());
+    for (chapter, (content, failed, errors, timings)) in chapters.iter().zip(results) {
+        contents.push(content);
+        combined
+            .chapter_timings
+            .push((chapter.name.clone(), timings));
+        combined.errors.extend(errors);
+        if failed && !combined.failures.contains(&chapter.name) {
+            combined.failures.push(chapter.name.clone());
+        }
+    }
+    (contents, combined)
+}
+
+/// Path identifying `chapter`, relative to the book's `src` directory.
+fn chapter_id(chapter: &Chapter) -> String {
+    chapter
+        .source_path
+        .as_ref()
+        .or(chapter.path.as_ref())
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| chapter.name.clone())
+}
+
+/// Returns true if the fenced code block type is one handled by this preprocessor.
+fn is_svgdx_fence(block_type: &str) -> bool {
+    matches!(
+        block_type,
+        "svgdx" | "svgdx-xml" | "xml-svgdx" | "svgdx-xml-inline" | "xml-svgdx-inline"
+    )
+}
+
+/// Minimal escaping for text content and attribute values in generated HTML.
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// A hidden element holding the svgdx `source` of the preceding diagram and
+/// the `transform` options it was rendered with, for client-side svgdx to
+/// re-render it (e.g. on theme changes).
+///
+/// Both are attributes, with newlines encoded so the output has no blank lines.
+fn source_element(source: &str, transform: &TransformOptions) -> String {
+    let attr = |s: &str| escape_html(s).replace('\n', "&#10;");
+    // Unset options are omitted, leaving svgdx's defaults.
+    let mut options = serde_json::to_value(transform).unwrap_or_default();
+    if let Some(options) = options.as_object_mut() {
+        options.retain(|_, value| !value.is_null());
     }
+    format!(
+        "<div hidden class='svgdx-source' data-transform='{}' data-source='{}'></div>\n",
+        attr(&options.to_string()),
+        attr(source)
+    )
 }
 
-fn inject_xml(events: &mut Vec<Event>, content: &str) {
-    events.push(Html("\n\n<div>\n".into()));
-    events.push(Start(Tag::CodeBlock(CodeBlockKind::Fenced("xml".into()))));
-    events.push(Text(content.to_owned().into()));
-    events.push(End(TagEnd::CodeBlock));
-    events.push(Html("\n</div>\n".into()));
+/// A badge showing the `status` and `owner` options of a block, if given.
+fn badge_html(info: &FenceInfo) -> Option<String> {
+    let status = info.get("status").map(|status| status.to_uppercase());
+    let owner = info.get("owner").map(|owner| format!("owner: {}", owner));
+    let text = [status, owner].into_iter().flatten().collect::<Vec<_>>();
+    if text.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "<div class='svgdx-badge' style='font-size: 0.75em; opacity: 0.7;'>{}</div>\n",
+        escape_html(&text.join(" · "))
+    ))
+}
+
+/// Styles for `source-layout = "tabs"`, showing the panel for the checked
+/// tab. Tabs are reordered below their panels as an accordion on narrow screens.
+const TAB_STYLE: &str = "<style>\
+.svgdx-tabs { display: flex; flex-wrap: wrap; } \
+.svgdx-tabs > input { display: none; } \
+.svgdx-tabs > label { cursor: pointer; padding: 0.25em 1em; border-bottom: 2px solid transparent; } \
+.svgdx-tabs > input:checked + label { border-bottom-color: currentColor; } \
+.svgdx-tabs > div { display: none; flex-basis: 100%; order: 1; } \
+.svgdx-tabs > input:nth-of-type(1):checked ~ div:nth-of-type(1), \
+.svgdx-tabs > input:nth-of-type(2):checked ~ div:nth-of-type(2) { display: block; } \
+@media (max-width: 600px) { \
+.svgdx-tabs > label { flex-basis: 100%; box-sizing: border-box; border: 1px solid; } \
+.svgdx-tabs > label:nth-of-type(1) { order: 1; } \
+.svgdx-tabs > div:nth-of-type(1) { order: 2; } \
+.svgdx-tabs > label:nth-of-type(2) { order: 3; } \
+.svgdx-tabs > div:nth-of-type(2) { order: 4; } \
+}</style>\n";
+
+/// Styles for the toggle shown with height-constrained diagrams.
+const EXPAND_STYLE: &str = "<style>\
+.svgdx-expand { display: none; } \
+.svgdx-expand + label { cursor: pointer; font-size: 0.8em; } \
+.svgdx-expand + label::before { content: 'Expand'; } \
+.svgdx-expand:checked + label::before { content: 'Collapse'; } \
+.svgdx-expand:checked + label + .svgdx-scroll { max-height: none !important; } \
+</style>\n";
+
+/// Script and styles for the buttons copying the source of a rendered SVG.
+///
+/// Diagrams written to files with `output = "file"` are fetched to be copied.
+const COPY_SCRIPT: &str = "<style>\
+.svgdx-copyable { position: relative; } \
+.svgdx-copy { position: absolute; top: 0; right: 0; font-size: 0.8em; opacity: 0.6; cursor: pointer; } \
+.svgdx-copy:hover { opacity: 1; } \
+</style>\
+<script>function svgdxCopy(button) { \
+const item = button.parentElement.querySelector('svg, img'); \
+const text = item.tagName === 'IMG' ? fetch(item.src).then(r => r.text()) : Promise.resolve(item.outerHTML); \
+text.then(t => navigator.clipboard.writeText(t)).then(() => { \
+button.textContent = 'Copied'; setTimeout(() => { button.textContent = 'Copy SVG'; }, 1500); }); \
+}</script>\n";
+
+/// Inline style of diagram captions.
+const CAPTION_STYLE: &str = "text-align: center; font-style: italic;";
+
+/// Hint for readers of very wide diagrams on narrow screens, following
+/// the diagram or its scroll container.
+const ROTATE_HINT: &str = "<div class='svgdx-rotate-hint'>Rotate your device to see this \
+diagram in more detail, or <button type='button' \
+onclick='this.parentElement.previousElementSibling.requestFullscreen()'>view full screen</button>\
+</div>";
+
+/// Styles showing `ROTATE_HINT` only on narrow portrait screens.
+const ROTATE_HINT_STYLE: &str = "<style>\
+.svgdx-rotate-hint { display: none; font-size: 0.8em; font-style: italic; } \
+@media (max-width: 600px) and (orientation: portrait) { .svgdx-rotate-hint { display: block; } } \
+svg:fullscreen { background: white; } \
+</style>";
+
+/// Styles showing the `dark-theme` variant of each diagram in mdbook's dark
+/// themes, or if the reader prefers a dark colour scheme and no mdbook theme
+/// has been chosen.
+const DARK_THEME_STYLE: &str = "<style>\
+.svgdx-dark { display: none; } \
+html.coal .svgdx-dark, html.navy .svgdx-dark, html.ayu .svgdx-dark { display: inline; } \
+html.coal .svgdx-light, html.navy .svgdx-light, html.ayu .svgdx-light { display: none; } \
+@media (prefers-color-scheme: dark) { \
+html:not(.light):not(.rust) .svgdx-dark { display: inline; } \
+html:not(.light):not(.rust) .svgdx-light { display: none; } \
+}</style>\n";
+
+/// Toggle and script switching between the standard and high-contrast
+/// variants of diagrams, remembering the reader's choice.
+///
+/// Without a choice, the variant follows the `prefers-contrast` setting.
+const HIGH_CONTRAST_TOGGLE: &str = "<button type='button' class='svgdx-contrast-toggle' \
+style='font-size: 0.8em;' onclick='svgdxContrast()'>Toggle high-contrast diagrams</button>\
+<script>function svgdxContrast() { \
+const root = document.documentElement; \
+const high = root.classList.contains('svgdx-high-contrast') || \
+(!root.classList.contains('svgdx-standard-contrast') && matchMedia('(prefers-contrast: more)').matches); \
+root.classList.toggle('svgdx-high-contrast', !high); \
+root.classList.toggle('svgdx-standard-contrast', high); \
+localStorage.setItem('svgdx-contrast', high ? 'standard' : 'high'); \
+} \
+const svgdxChoice = localStorage.getItem('svgdx-contrast'); \
+if (svgdxChoice) { document.documentElement.classList.add('svgdx-' + svgdxChoice + '-contrast'); }\
+</script>\n";
+
+/// Markdown for a fenced `xml` code block displaying `source`.
+///
+/// Lines starting with the `hidden` marker are hidden by mdbook's HTML
+/// renderer, with a button to reveal them.
+fn xml_source(source: &str, hidden: Option<&str>) -> String {
+    // The fence must be longer than any run of backticks in the source.
+    let longest_run = source
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let newline = if source.ends_with('\n') { "" } else { "\n" };
+    let hidelines = hidden
+        .map(|marker| format!(" hidelines={}", marker))
+        .unwrap_or_default();
+    format!(
+        "<div>\n\n{}xml{}\n{}{}{}\n\n</div>\n",
+        fence, hidelines, source, newline, fence
+    )
 }
 
-fn inject_svgdx(events: &mut Vec<Event>, content: &str) {
-    events.push(Start(Tag::Paragraph));
-    // Need to avoid blank lines in the rendered SVG, as they can cause
-    // markdown to resume 'normal' md processing, especially when e.g.
-    // indentation can cause an implicit code block to be started.
-    // See https://talk.commonmark.org/t/inline-html-breaks-when-using-indentation/3317
-    // and https://spec.commonmark.org/0.31.2/#html-blocks
-    let svg_output = svgdx_handler(content)
+/// The svgdx `source` with the `marker` removed from hidden lines, leaving
+/// the content used for rendering.
+fn unhide_lines(source: &str, marker: &str) -> String {
+    source
         .lines()
-        .filter(|line| !line.trim().is_empty())
-        .collect::<Vec<_>>()
-        .join("\n");
-    events.push(Html(svg_output.into()));
-    events.push(End(TagEnd::Paragraph));
-}
-
-fn codeblock_parser(chapter: &mut Chapter) -> Result<String, std::fmt::Error> {
-    let md_events = mdbook::utils::new_cmark_parser(&chapter.content, false);
-
-    let mut in_block = None;
-    let mut events = Vec::new();
-    for ev in md_events {
-        match (&mut in_block, ev.clone()) {
-            (None, Start(Tag::CodeBlock(Fenced(Borrowed(block_type)))))
-                if matches!(
-                    block_type,
-                    "svgdx" | "svgdx-xml" | "xml-svgdx" | "svgdx-xml-inline" | "xml-svgdx-inline"
-                ) =>
-            {
-                // surround the whole thing in a div with appropriate class so
-                // we can style it. Note deliberate empty lines here to get
-                // markdown to ignore the fact we've just opened a <div> Html block
-                let style = if block_type.ends_with("-inline") {
-                    "style='display: flex; justify-content: space-around;' "
+        .map(|line| {
+            let indent = line.len() - line.trim_start().len();
+            match line[indent..].strip_prefix(marker) {
+                Some(rest) => format!("{}{}\n", &line[..indent], rest),
+                None => format!("{}\n", line),
+            }
+        })
+        .collect()
+}
+
+/// svgdx content found while parsing a chapter, which is rendered and
+/// substituted for its token once the chapter has been serialized.
+enum Deferred {
+    /// A fenced svgdx code block.
+    Fence(FenceInfo, String),
+    /// Consecutive fenced svgdx code blocks sharing a `group` option.
+    Group(Vec<(FenceInfo, String)>),
+    /// A bare diagram source, e.g. from a placeholder in raw HTML.
+    Diagram(String),
+    /// The source of a `svgdx-defs` block, included in later diagrams.
+    Defs(String),
+    /// An error found while parsing a block, shown in its place.
+    Error(String),
+}
+
+/// Renders svgdx sources according to the book configuration.
+///
+/// Output is memoized by source, so identical blocks (e.g. a legend repeated
+/// in every chapter) are only rendered once per run.
+#[derive(Default)]
+struct Renderer {
+    cfg: SvgdxConfig,
+    memo: HashMap<String, String>,
+    /// Name of the chapter currently being processed, for diagnostics.
+    chapter: String,
+    /// Path of the chapter currently being processed, identifying its figures.
+    chapter_path: String,
+    /// Directory containing the current chapter's source file.
+    chapter_dir: PathBuf,
+    /// Relative path from the current chapter's output to the book root.
+    path_to_root: String,
+    /// Number of diagrams so far in the current chapter.
+    block_count: usize,
+    /// Numbers and labels of the figures in the book.
+    figures: Arc<Figures>,
+    /// Number of figures so far in the current chapter.
+    figure_count: usize,
+    /// Number of SVG renderings of each source so far in the current chapter,
+    /// by source hash, distinguishing the ids of each.
+    render_counts: HashMap<String, usize>,
+    /// Element ids defined by diagrams in the current chapter.
+    ids: ChapterIds,
+    /// Content of the `svgdx-defs` blocks so far in the current chapter.
+    chapter_defs: String,
+    /// Source of the current chapter's latest diagram, which an
+    /// `svgdx-continue` block extends.
+    previous: Option<String>,
+    /// Hash of each rendered diagram in the book.
+    manifest: Manifest,
+    /// svgdx content in the current chapter, awaiting rendering and substitution.
+    deferred: Vec<Deferred>,
+    /// Chapters containing diagrams which failed to render.
+    failures: Vec<String>,
+    /// Location and message of each diagram which failed to render.
+    errors: Vec<String>,
+    /// Shared styles already included in the current chapter.
+    styles_emitted: Vec<&'static str>,
+    /// Sources of `svgdx-part` blocks awaiting the next `svgdx-render` block.
+    parts: String,
+    /// Distinguishes tokens for deferred output from any chapter content.
+    token_nonce: String,
+    /// Time spent in each phase of processing the current chapter.
+    timings: Timings,
+    /// Name and timings of each chapter processed.
+    chapter_timings: Vec<(String, Timings)>,
+}
+
+impl Renderer {
+    fn new(cfg: SvgdxConfig) -> Self {
+        Self {
+            cfg,
+            ..Default::default()
+        }
+    }
+
+    fn start_chapter(&mut self, chapter: &Chapter) {
+        self.chapter.clone_from(&chapter.name);
+        self.chapter_path = chapter_id(chapter);
+        self.block_count = 0;
+        self.figure_count = 0;
+        self.render_counts.clear();
+        self.ids.clear();
+        self.chapter_defs.clear();
+        self.previous = None;
+        self.deferred.clear();
+        self.parts.clear();
+        self.styles_emitted.clear();
+        self.timings = Timings::default();
+        self.token_nonce = content_hash(&chapter.content);
+        self.path_to_root = chapter
+            .path
+            .as_ref()
+            .map(mdbook::utils::fs::path_to_root)
+            .unwrap_or_default();
+        self.chapter_dir = match chapter.source_path.as_ref().and_then(|p| p.parent()) {
+            Some(dir) => self.cfg.src_dir.join(dir),
+            None => self.cfg.src_dir.clone(),
+        };
+    }
+
+    /// Contents of the diagram file `src`, relative to the current chapter.
+    fn read_src(&self, src: &str) -> Result<String, String> {
+        let path = self.chapter_dir.join(src);
+        read_source(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))
+    }
+
+    /// Combine the accumulated `svgdx-part` sources with the `source` of a
+    /// `svgdx-render` block, adding a root `<svg>` element if needed.
+    fn take_parts(&mut self, source: &str) -> String {
+        let combined = std::mem::take(&mut self.parts) + source;
+        if combined.trim_start().starts_with("<svg") {
+            combined
+        } else {
+            format!("<svg>\n{}</svg>\n", combined)
+        }
+    }
+
+    /// Render `source` for inclusion in markdown, recording it as a figure.
+    ///
+    /// `transform` replaces the book's transform options, if given.
+    fn render_block(&mut self, source: &str, transform: Option<TransformOptions>) -> String {
+        let (_, settings) = take_config_elements(source);
+        let overridden = overridden_settings(&self.cfg.transform, &settings);
+        if !overridden.is_empty() {
+            eprintln!(
+                "Warning: svgdx diagram in chapter '{}' overrides book transform options with <config>: {}",
+                self.chapter,
+                overridden.join(", ")
+            );
+        }
+        let svg = self.render_figure(source, transform);
+        if root_tag(&svg).is_none() {
+            self.record_error(&error_message(&svg));
+        }
+        if self.cfg.check_links {
+            let dead = dead_links(&svg, &self.chapter_dir, &self.cfg.src_dir);
+            if !dead.is_empty() {
+                eprintln!(
+                    "Warning: svgdx diagram in chapter '{}' has references which don't resolve: {}",
+                    self.chapter,
+                    dead.join(", ")
+                );
+            }
+        }
+        self.record_figure(&svg);
+        svg
+    }
+
+    /// Record `msg` as the error of the current block, failing the build with
+    /// `fail-on-error` or for a `validate-renderers` renderer.
+    fn record_error(&mut self, msg: &str) {
+        self.errors.push(format!(
+            "chapter '{}', block {}: {}",
+            self.chapter,
+            self.block_count + 1,
+            msg
+        ));
+        if !self.failures.contains(&self.chapter) {
+            self.failures.push(self.chapter.clone());
+        }
+    }
+
+    /// Record `msg` as the error of the current block, returning the HTML block
+    /// shown in its place.
+    fn block_error(&mut self, msg: &str) -> String {
+        self.record_error(msg);
+        format!("\n\n{}\n\n", error_html(msg))
+    }
+
+    /// Render `source` for inclusion in markdown.
+    fn render_figure(&mut self, source: &str, transform: Option<TransformOptions>) -> String {
+        // Need to avoid blank lines in the rendered SVG, as they can cause
+        // markdown to resume 'normal' md processing, especially when e.g.
+        // indentation can cause an implicit code block to be started.
+        // See https://talk.commonmark.org/t/inline-html-breaks-when-using-indentation/3317
+        // and https://spec.commonmark.org/0.31.2/#html-blocks
+        let format = self.cfg.svg_format;
+        let svg = self.render(source, transform);
+        let svg = match format {
+            _ if root_tag(svg).is_none() => encode_blank_lines(svg),
+            SvgFormat::Lines => encode_blank_lines(svg),
+            SvgFormat::Minified => minify(svg),
+            SvgFormat::Pretty => encode_blank_lines(&pretty_print(svg)),
+        };
+        let svg = if root_tag(&svg).is_some() {
+            // Renderings are memoized, so each use of one is given its own
+            // root id. This is derived from the chapter and source rather than
+            // the position, so ids (and so asset names and manifest hashes)
+            // only change when the diagram does.
+            let hash = content_hash(format!(
+                "{}\n{}",
+                self.chapter_path,
+                normalized_source(source)
+            ));
+            let count = self.render_counts.entry(hash.clone()).or_default();
+            *count += 1;
+            let id = format!("svgdx-{}-{}", &hash[..8], count);
+            let svg = set_style_id(svg, &id);
+            if self.cfg.namespace_ids {
+                namespace_ids(svg, &format!("{}-", id))
+            } else {
+                svg
+            }
+        } else {
+            svg
+        };
+        let mut svg = self.embed_images(svg);
+        if let Some(stamp) = &self.cfg.stamp {
+            let checksum = &content_hash(normalized_source(source))[..8];
+            svg = add_footer(svg, &stamp.text(checksum));
+        }
+        svg
+    }
+
+    /// The transform options for the `dark-theme` variant of a diagram
+    /// rendered with `transform`, if the option is set.
+    fn dark_transform(&self, transform: Option<&TransformOptions>) -> Option<TransformOptions> {
+        let theme = self.cfg.dark_theme.clone()?;
+        Some(TransformOptions {
+            theme: Some(theme),
+            ..transform.unwrap_or(&self.cfg.transform).clone()
+        })
+    }
+
+    /// Render `source` and any `dark-theme` variant of it, each passed through
+    /// `finish`, with the variants shown according to the reader's theme.
+    fn render_themed(
+        &mut self,
+        source: &str,
+        transform: Option<TransformOptions>,
+        dark: Option<TransformOptions>,
+        mut finish: impl FnMut(&Self, String) -> String,
+    ) -> String {
+        let svg = self.render_block(source, transform);
+        // Only inline diagrams are affected by the page's classes.
+        let toggle = if self.cfg.high_contrast && self.output_mode() == Output::Inline {
+            self.shared_style(HIGH_CONTRAST_TOGGLE)
+        } else {
+            ""
+        };
+        let Some(dark) = dark else {
+            return format!("{}{}", toggle, finish(self, svg));
+        };
+        let dark = self.render_figure(source, Some(dark));
+        let light = finish(self, add_root_class(svg, "svgdx-light"));
+        let dark = finish(self, add_root_class(dark, "svgdx-dark"));
+        format!(
+            "{}{}{}\n{}",
+            toggle,
+            self.shared_style(DARK_THEME_STYLE),
+            light,
+            dark
+        )
+    }
+
+    /// How diagrams are output for the current renderer.
+    fn output_mode(&self) -> Output {
+        self.cfg.output.get(&self.cfg.renderer)
+    }
+
+    /// Path from the current chapter to the asset file `name`.
+    fn asset_src(&self, name: &str) -> String {
+        format!(
+            "{}{}/{}",
+            self.path_to_root,
+            self.cfg.assets_dir.to_string_lossy().replace('\\', "/"),
+            name
+        )
+    }
+
+    /// `svg` with references to local image files inlined or copied, as set
+    /// by the `external-images` option.
+    fn embed_images(&self, svg: String) -> String {
+        if self.cfg.external_images == ExternalImages::Keep {
+            return svg;
+        }
+        let (svg, warnings) =
+            rewrite_images(&svg, &self.chapter_dir, &self.cfg.src_dir, |path, data| {
+                // Diagrams shown with `<img>` can't load other files, so images
+                // must always be inlined.
+                let inline = self.output_mode() != Output::Inline
+                    || (self.cfg.external_images == ExternalImages::Inline
+                        && data.len() <= self.cfg.inline_image_limit);
+                if let Some(uri) = data_uri(path, data).filter(|_| inline) {
+                    return Some(uri);
+                }
+                let name = asset_name(path, data);
+                let dest = self.cfg.src_dir.join(&self.cfg.assets_dir).join(&name);
+                if !dest.exists() {
+                    if let Err(e) = write_atomically(&dest, data) {
+                        eprintln!(
+                            "Warning: couldn't copy image {} to {}: {}",
+                            path.display(),
+                            dest.display(),
+                            e
+                        );
+                        return None;
+                    }
+                }
+                Some(self.asset_src(&name))
+            });
+        for warning in warnings {
+            let warning = match warning {
+                // Missing images are reported as dead links when checking them.
+                ImageWarning::Unreadable(_) if self.cfg.check_links => continue,
+                ImageWarning::Unreadable(warning) | ImageWarning::OutsideSource(warning) => warning,
+            };
+            eprintln!(
+                "Warning: svgdx diagram in chapter '{}' {}",
+                self.chapter, warning
+            );
+        }
+        svg
+    }
+
+    /// `svg` described for screen readers with the accessible name `label`,
+    /// unless disabled.
+    fn accessible(&self, svg: String, label: Option<&str>) -> String {
+        if self.cfg.accessibility {
+            add_accessibility(svg, label)
+        } else {
+            svg
+        }
+    }
+
+    /// `svg` in the configured output form: either unchanged, or as an `<img>`
+    /// element showing it (or a PNG rendering of it, for the renderers listed
+    /// in `raster-renderers`) from a file in the assets directory.
+    fn output(&self, svg: String, alt: &str) -> String {
+        if root_tag(&svg).is_none() {
+            return svg;
+        }
+        let dir = self.cfg.src_dir.join(&self.cfg.assets_dir);
+        if self.cfg.raster_renderers.contains(&self.cfg.renderer) {
+            let cfg = &self.cfg;
+            match write_raster(&dir, &svg, &cfg.raster_command, cfg.raster_dpi) {
+                Ok(name) => return img_element(&svg, &self.asset_src(&name), alt),
+                Err(e) => eprintln!(
+                    "Warning: couldn't rasterize svgdx diagram in chapter '{}', so it is shown as SVG: {}",
+                    self.chapter, e
+                ),
+            }
+        }
+        if self.output_mode() == Output::Inline {
+            return svg;
+        }
+        match write_figure(&dir, &svg) {
+            Ok(name) => img_element(&svg, &self.asset_src(&name), alt),
+            Err(e) => {
+                eprintln!(
+                    "Warning: couldn't write svgdx diagram to {}: {}",
+                    dir.display(),
+                    e
+                );
+                svg
+            }
+        }
+    }
+
+    /// Render a fenced svgdx block, with its wrapper and any source display.
+    ///
+    /// The output is a self-contained HTML block, with a blank line only
+    /// around the markdown code block displaying the XML source.
+    fn render_fence(&mut self, info: &FenceInfo, source: &str) -> String {
+        if info.is_omitted(self.cfg.profile) {
+            return String::new();
+        }
+        let is_draft = info.get("status") == Some("draft");
+        let data = self.data_attributes(info, source);
+        let original = source;
+        let expanded;
+        let shorthand = match info.block_type.as_str() {
+            "svgdx-seq" => Some(expand_seq(source)),
+            "svgdx-graph" => Some(expand_graph(source)),
+            _ => None,
+        };
+        let source = match shorthand {
+            Some(Ok(xml)) => {
+                expanded = xml;
+                expanded.as_str()
+            }
+            Some(Err(e)) => return self.block_error(&e),
+            None => source,
+        };
+        let marker = self.cfg.hidden_line_marker.clone();
+        let xml = xml_source(source, marker.as_deref());
+        let source = match &marker {
+            Some(marker) => unhide_lines(source, marker),
+            None => source.to_string(),
+        };
+        let transform = match self.block_transform(info) {
+            Ok(transform) => transform,
+            Err(e) => return self.block_error(&e),
+        };
+        if self.output_mode() == Output::Markdown {
+            return self.markdown_fence(info, original, &source, transform);
+        }
+        let dark = match info.get("theme") {
+            // A block's own theme applies whatever the reader's theme.
+            Some(_) => None,
+            None => self.dark_transform(transform.as_ref()),
+        };
+        let attach_source = match info.get("attach-source") {
+            Some(value) => value == "true",
+            None => self.cfg.attach_source,
+        };
+        let attached = attach_source
+            .then(|| source_element(&source, transform.as_ref().unwrap_or(&self.cfg.transform)));
+        let title_caption = match info.get("title-caption") {
+            Some(value) => value == "true",
+            None => self.cfg.title_caption,
+        };
+        let mut title = None;
+        let mut is_wide = false;
+        let svg = self.render_themed(&source, transform, dark, |renderer, svg| {
+            let svg = if is_draft {
+                add_draft_banner(svg, &renderer.cfg.draft_banner)
+            } else {
+                svg
+            };
+            is_wide |= renderer
+                .cfg
+                .rotate_hint
+                .zip(view_box(&svg))
+                .is_some_and(|(ratio, [_, _, w, h])| w >= h * ratio);
+            let svg = if info.get("overflow") == Some("scroll") {
+                remove_root_attr(&svg, "style")
+            } else {
+                svg
+            };
+            if title_caption && title.is_none() {
+                title = root_title(&svg);
+            }
+            let label = info.get("alt").or(info.get("caption")).or(title.as_deref());
+            let svg = renderer.accessible(svg, label);
+            renderer.output(svg, label.unwrap_or_default())
+        });
+        let mut figure = self.scroll_container(svg, info);
+        if is_wide {
+            figure = format!(
+                "{}{}\n{}",
+                self.shared_style(ROTATE_HINT_STYLE),
+                figure,
+                ROTATE_HINT
+            );
+        }
+        figure.push('\n');
+        if let Some(attached) = attached {
+            figure.push_str(&attached);
+        }
+        let copy_svg = match info.get("copy-svg") {
+            Some(value) => value == "true",
+            None => self.cfg.copy_svg,
+        };
+        if copy_svg {
+            figure = format!(
+                "<div class='svgdx-copyable'>\n{}{}<button type='button' class='svgdx-copy' \
+                 title='Copy SVG source' onclick='svgdxCopy(this)'>Copy SVG</button>\n</div>\n",
+                self.shared_style(COPY_SCRIPT),
+                figure
+            );
+        }
+        let wrap = match info.get("wrap") {
+            Some("div") => Wrap::Div,
+            Some("paragraph") => Wrap::Paragraph,
+            Some("figure") => Wrap::Figure,
+            Some("none") => Wrap::None,
+            _ => self.cfg.wrap,
+        };
+        // Captions shown beneath the figure; a `<figure>` has a `<figcaption>`.
+        let mut notes = String::new();
+        let caption = match (&info.figure, info.get("caption").or(title.as_deref())) {
+            (Some(number), Some(caption)) => {
+                Some(format!("Figure {}: {}", number, escape_html(caption)))
+            }
+            (Some(number), None) => Some(format!("Figure {}", number)),
+            (None, caption) => caption.map(escape_html),
+        };
+        if let Some(caption) = caption.as_ref().filter(|_| wrap != Wrap::Figure) {
+            notes.push_str(&format!(
+                "<div class='svgdx-caption' style='{}'>{}</div>\n",
+                CAPTION_STYLE, caption
+            ));
+        }
+        if self.cfg.profile == Profile::Draft {
+            if let Some(badge) = badge_html(info) {
+                notes.push_str(&badge);
+            }
+        }
+        if info.get("legend") == Some("auto") {
+            if let Some(legend) = legend_html(&source, &self.cfg.legend) {
+                notes.push_str(&legend);
+            }
+        }
+
+        // Labelled figures are anchored for references.
+        let anchor = info
+            .get("label")
+            .map(|label| format!("id='{}' ", escape_html(&anchor_id(label))))
+            .unwrap_or_default();
+        let block_type = &info.block_type;
+        let source_first = block_type.starts_with("xml-svgdx");
+        let with_source = source_first || block_type.starts_with("svgdx-xml");
+        let layout = match info.get("source-layout") {
+            Some("stacked") => SourceLayout::Stacked,
+            Some("tabs") => SourceLayout::Tabs,
+            Some("collapsed") => SourceLayout::Collapsed,
+            _ => self.cfg.source_layout,
+        };
+        let source_tabs = match info.get("source-tabs") {
+            Some(value) => value == "true",
+            None => self.cfg.source_tabs,
+        };
+        let tabbed = if with_source {
+            !block_type.ends_with("-inline") && layout == SourceLayout::Tabs
+        } else {
+            block_type == "svgdx" && source_tabs
+        };
+        if tabbed {
+            figure.push_str(&notes);
+            let tabs = self.render_tabs(block_type, &data, &figure, &xml, source_first);
+            if anchor.is_empty() {
+                return tabs;
+            }
+            return format!("\n\n<div {}></div>{}", anchor.trim_end(), tabs);
+        }
+
+        let xml = if layout == SourceLayout::Collapsed {
+            format!(
+                "<details class='svgdx-source'>\n<summary>Show source</summary>\n{}</details>\n",
+                xml
+            )
+        } else {
+            xml
+        };
+        let mut html = String::from("\n\n");
+        match wrap {
+            Wrap::Div | Wrap::Figure => {
+                let style = block_type
+                    .ends_with("-inline")
+                    .then(|| {
+                        self.wrapper_style(
+                            "display: flex; flex-wrap: wrap; justify-content: space-around;",
+                        )
+                    })
+                    .flatten()
+                    .map(|css| format!("style='{}' ", css))
+                    .unwrap_or_default();
+                let element = if wrap == Wrap::Figure {
+                    "figure"
                 } else {
-                    ""
+                    "div"
                 };
-                events.push(Html(
-                    format!("\n\n<div {}class='{}'>\n", style, block_type).into(),
+                html.push_str(&format!(
+                    "<{} {}{}class='{}'{}>\n",
+                    element, anchor, style, block_type, data
+                ));
+            }
+            // A paragraph can't contain the source listing, so only wraps the figure.
+            Wrap::Paragraph => {
+                figure = format!(
+                    "<p {}class='{}'{}>\n{}</p>\n",
+                    anchor, block_type, data, figure
+                )
+            }
+            Wrap::None if !anchor.is_empty() => {
+                html.push_str(&format!("<div {}></div>\n", anchor.trim_end()))
+            }
+            Wrap::None => {}
+        }
+        if source_first {
+            // Display the XML input prior to the rendered SVG output.
+            html.push_str(&xml);
+        }
+        html.push_str(&figure);
+        html.push_str(&notes);
+        if with_source && !source_first {
+            // Display the XML input after the rendered SVG output.
+            html.push_str(&xml);
+        }
+        match wrap {
+            Wrap::Div => html.push_str("</div>"),
+            Wrap::Figure => {
+                if let Some(caption) = caption {
+                    html.push_str(&format!(
+                        "<figcaption style='{}'>{}</figcaption>\n",
+                        CAPTION_STYLE, caption
+                    ));
+                }
+                html.push_str("</figure>");
+            }
+            _ => {}
+        }
+        html.push_str("\n\n");
+        html
+    }
+
+    /// The block as markdown for `output = "markdown"`: the `original` fence,
+    /// followed by an image link to the diagram rendered from `source`.
+    fn markdown_fence(
+        &mut self,
+        info: &FenceInfo,
+        original: &str,
+        source: &str,
+        transform: Option<TransformOptions>,
+    ) -> String {
+        // The fence must be longer than any run of backticks in the block.
+        let longest = original
+            .split(|c| c != '`')
+            .map(str::len)
+            .max()
+            .unwrap_or_default();
+        let fence = "`".repeat(longest.max(2) + 1);
+        let newline = if original.ends_with('\n') { "" } else { "\n" };
+        let mut markdown = format!(
+            "\n\n{}{}\n{}{}{}\n\n",
+            fence, info.info, original, newline, fence
+        );
+        let svg = self.render_block(source, transform);
+        if root_tag(&svg).is_none() {
+            markdown.push_str(&svg);
+            markdown.push_str("\n\n");
+            return markdown;
+        }
+        let dir = self.cfg.src_dir.join(&self.cfg.assets_dir);
+        match write_figure(&dir, &svg) {
+            Ok(name) => {
+                let alt = info.get("alt").or(info.get("caption")).unwrap_or_default();
+                markdown.push_str(&format!(
+                    "![{}]({})\n\n",
+                    alt.replace('[', "\\[").replace(']', "\\]"),
+                    self.asset_src(&name)
                 ));
-                in_block = Some(block_type.to_string());
             }
-            (Some(block_type), Text(content)) => {
-                if block_type.starts_with("xml-svgdx") {
-                    // Special case this fence type to display the XML input
-                    // prior to the rendered SVG output.
-                    inject_xml(&mut events, &content);
+            Err(e) => eprintln!(
+                "Warning: couldn't write svgdx diagram to {}: {}",
+                dir.display(),
+                e
+            ),
+        }
+        markdown
+    }
+
+    /// The book's transform options with the `scale` and `theme` options of
+    /// the block applied, or `None` if it has neither.
+    fn block_transform(&self, info: &FenceInfo) -> Result<Option<TransformOptions>, String> {
+        if info.get("scale").is_none() && info.get("theme").is_none() {
+            return Ok(None);
+        }
+        let mut transform = self.cfg.transform.clone();
+        if let Some(scale) = info.get("scale") {
+            let scale = match scale {
+                "auto" => self.cfg.auto_scale(),
+                _ => scale
+                    .parse()
+                    .map_err(|_| format!("Invalid scale '{}'", scale))?,
+            };
+            transform.scale = Some(scale);
+        }
+        if let Some(theme) = info.get("theme") {
+            transform.theme = Some(theme.to_string());
+        }
+        Ok(Some(transform))
+    }
+
+    /// The given `style`, unless already included in the current chapter.
+    ///
+    /// Styles apply to the whole page, so are only needed once.
+    fn shared_style(&mut self, style: &'static str) -> &'static str {
+        if self.styles_emitted.contains(&style) {
+            return "";
+        }
+        self.styles_emitted.push(style);
+        style
+    }
+
+    /// Place `svg` in a scrollable container if its height is constrained or
+    /// the block has the `overflow=scroll` option.
+    ///
+    /// Height-constrained diagrams have a toggle to show them at full size,
+    /// while `overflow=scroll` keeps the diagram at its natural size rather
+    /// than shrinking it to fit the page width.
+    fn scroll_container(&mut self, svg: String, info: &FenceInfo) -> String {
+        let max_height = info
+            .get("max-height")
+            .or(self.cfg.max_height.as_deref())
+            .filter(|h| *h != "none")
+            .map(escape_html);
+        let scroll = info.get("overflow") == Some("scroll");
+        match max_height {
+            Some(max_height) => {
+                let id = format!("svgdx-{}-{}-expand", self.token_nonce, self.block_count);
+                format!(
+                    "{}<input type='checkbox' class='svgdx-expand' id='{id}'><label for='{id}'></label>\n\
+                     <div class='svgdx-scroll' style='max-height: {}; overflow: auto;'>\n{}\n</div>",
+                    self.shared_style(EXPAND_STYLE),
+                    max_height,
+                    svg
+                )
+            }
+            None if scroll => format!(
+                "<div class='svgdx-scroll' style='overflow-x: auto;'>\n{}\n</div>",
+                svg
+            ),
+            None => svg,
+        }
+    }
+
+    /// Render a figure and its XML source as tabs, or as an accordion on
+    /// narrow screens, using radio buttons so no script is required.
+    fn render_tabs(
+        &mut self,
+        block_type: &str,
+        data: &str,
+        figure: &str,
+        xml: &str,
+        source_first: bool,
+    ) -> String {
+        let name = format!("svgdx-{}-{}", self.token_nonce, self.block_count);
+        let mut html = format!("\n\n<div class='{} svgdx-tabs'{}>\n", block_type, data);
+        html.push_str(self.shared_style(TAB_STYLE));
+        let diagram_tab = format!(
+            "<input type='radio' name='{name}' id='{name}-svg' checked>\
+             <label for='{name}-svg'>Diagram</label>\n"
+        );
+        let source_tab = format!(
+            "<input type='radio' name='{name}' id='{name}-xml'>\
+             <label for='{name}-xml'>Source</label>\n"
+        );
+        let diagram_panel = format!("<div class='svgdx-panel'>\n{}</div>\n", figure);
+        let source_panel = format!("<div class='svgdx-panel'>\n{}</div>\n", xml);
+        if source_first {
+            html.push_str(&source_tab);
+            html.push_str(&diagram_tab);
+            html.push_str(&source_panel);
+            html.push_str(&diagram_panel);
+        } else {
+            html.push_str(&diagram_tab);
+            html.push_str(&source_tab);
+            html.push_str(&diagram_panel);
+            html.push_str(&source_panel);
+        }
+        html.push_str("</div>\n\n");
+        html
+    }
+
+    fn token_prefix(&self) -> String {
+        format!("<!--svgdx-output:{}:", self.token_nonce)
+    }
+
+    /// Add a fenced block to the previously deferred item, forming a group.
+    fn join_group(&mut self, info: FenceInfo, source: String) {
+        match self.deferred.pop() {
+            Some(Deferred::Fence(first_info, first_source)) => {
+                self.deferred.push(Deferred::Group(vec![
+                    (first_info, first_source),
+                    (info, source),
+                ]))
+            }
+            Some(Deferred::Group(mut blocks)) => {
+                blocks.push((info, source));
+                self.deferred.push(Deferred::Group(blocks));
+            }
+            other => {
+                self.deferred.extend(other);
+                self.deferred.push(Deferred::Fence(info, source));
+            }
+        }
+    }
+
+    /// `data-` attributes (each with a leading space) describing how the block
+    /// with `info` and `source` is rendered, if the `data-attributes` option
+    /// is set.
+    ///
+    /// The hash is of the normalized source, as listed in the inventory.
+    fn data_attributes(&self, info: &FenceInfo, source: &str) -> String {
+        if !self.cfg.data_attributes {
+            return String::new();
+        }
+        let mut attrs = format!(
+            " data-fence='{}' data-hash='{}'",
+            escape_html(&info.block_type),
+            content_hash(normalized_source(source))
+        );
+        let scale = info.get("scale").map(str::to_string).or(self
+            .cfg
+            .transform
+            .scale
+            .map(|scale| scale.to_string()));
+        if let Some(scale) = scale {
+            attrs.push_str(&format!(" data-scale='{}'", escape_html(&scale)));
+        }
+        if let Some(theme) = info.get("theme").or(self.cfg.transform.theme.as_deref()) {
+            attrs.push_str(&format!(" data-theme='{}'", escape_html(theme)));
+        }
+        attrs
+    }
+
+    /// The inline style (escaped for an attribute) of an element laying out
+    /// diagrams, given its `flex` style, according to the `wrapper-style`
+    /// option.
+    fn wrapper_style(&self, flex: &str) -> Option<String> {
+        match &self.cfg.wrapper_style {
+            WrapperStyle::None => None,
+            WrapperStyle::Flex => Some(flex.to_string()),
+            WrapperStyle::Custom(css) => Some(escape_html(css)),
+        }
+    }
+
+    /// Render a group of fenced blocks side by side in a single flex row.
+    fn render_group(&mut self, blocks: &[(FenceInfo, String)]) -> String {
+        let style = self.wrapper_style(
+            "display: flex; flex-wrap: wrap; justify-content: space-around; align-items: center;",
+        );
+        let mut html = format!(
+            "\n\n<div class='svgdx-group'{}>\n",
+            style
+                .map(|css| format!(" style='{}'", css))
+                .unwrap_or_default()
+        );
+        for (info, source) in blocks {
+            html.push_str(self.render_fence(info, source).trim_matches('\n'));
+            html.push('\n');
+        }
+        html.push_str("</div>\n\n");
+        html
+    }
+
+    /// Store `item` for later rendering, returning the token standing in for it.
+    ///
+    /// Tokens are replaced with the rendered output by `substitute_deferred()`
+    /// once the whole chapter has been processed, so that blocks (e.g. grouped
+    /// fences) can still be added to the deferred item.
+    fn defer(&mut self, item: Deferred) -> String {
+        self.deferred.push(item);
+        format!("{}{}-->", self.token_prefix(), self.deferred.len() - 1)
+    }
+
+    /// Replace tokens from `defer()` in `markdown` with the rendered output.
+    ///
+    /// Items are rendered in document order, so figures are numbered as read.
+    fn substitute_deferred(&mut self, markdown: &str) -> String {
+        let prefix = self.token_prefix();
+        let deferred = std::mem::take(&mut self.deferred);
+        let mut output = String::with_capacity(markdown.len());
+        let mut rest = markdown;
+        while let Some(start) = rest.find(&prefix) {
+            output.push_str(&rest[..start]);
+            let token = &rest[start + prefix.len()..];
+            let item = token.split_once("-->").and_then(|(idx, remaining)| {
+                let item = deferred.get(idx.parse::<usize>().ok()?)?;
+                Some((item, remaining))
+            });
+            match item {
+                Some((item, remaining)) => {
+                    let html = match item {
+                        Deferred::Fence(info, source) => self.render_fence(info, source),
+                        Deferred::Group(blocks) => self.render_group(blocks),
+                        Deferred::Diagram(source) => {
+                            let dark = self.dark_transform(None);
+                            self.render_themed(source, None, dark, |renderer, svg| {
+                                renderer.output(renderer.accessible(svg, None), "")
+                            })
+                        }
+                        Deferred::Defs(source) => {
+                            self.chapter_defs.push_str(source);
+                            String::new()
+                        }
+                        Deferred::Error(msg) => self.block_error(msg),
+                    };
+                    // Blocks within a list or blockquote must continue it on
+                    // each line; diagrams within HTML are left as rendered.
+                    let container = match item {
+                        Deferred::Diagram(_) => "",
+                        _ => line_prefix(&output, output.len()),
+                    };
+                    let html = continue_lines(&html, container);
+                    output.push_str(&html);
+                    rest = remaining;
                 }
-                inject_svgdx(&mut events, &content);
-                if block_type.starts_with("svgdx-xml") {
-                    // Special case this fence type to display the XML input
-                    // prior to the rendered SVG output.
-                    inject_xml(&mut events, &content);
+                None => {
+                    output.push_str(&prefix);
+                    rest = token;
                 }
             }
-            (Some(_), End(TagEnd::CodeBlock)) => {
-                events.push(Html("</div>".into()));
-                in_block = None;
+        }
+        output.push_str(rest);
+        output
+    }
+
+    /// Record a rendered diagram in the manifest, if enabled.
+    fn record_figure(&mut self, svg: &str) {
+        self.block_count += 1;
+        if self.cfg.manifest.is_some() {
+            self.manifest.insert(
+                format!("{}#{}", self.chapter_path, self.block_count),
+                figure_hash(svg),
+            );
+        }
+    }
+
+    fn render(&mut self, source: &str, transform: Option<TransformOptions>) -> &str {
+        let preambled;
+        let source = if self.cfg.preamble_source.is_empty() && self.chapter_defs.is_empty() {
+            source
+        } else {
+            let preamble = format!("{}{}", self.cfg.preamble_source, self.chapter_defs);
+            preambled = with_preamble(source, &preamble);
+            preambled.as_str()
+        };
+        let mut config_error = None;
+        let without_entities;
+        let source = match self.cfg.entities {
+            Entities::Pass => source,
+            Entities::Strip => {
+                without_entities = strip_entities(source);
+                without_entities.as_str()
+            }
+            Entities::Reject => {
+                config_error = reject_entities(source).err();
+                source
+            }
+        };
+        let (stripped, settings) = take_config_elements(source);
+        let (source, transform) = if settings.is_empty() {
+            (source, transform)
+        } else {
+            let mut merged = transform.unwrap_or_else(|| self.cfg.transform.clone());
+            for (key, value) in &settings {
+                if let Err(e) = merged.set(key, value) {
+                    config_error.get_or_insert(e);
+                }
             }
-            _ => events.push(ev),
+            (stripped.as_str(), Some(merged))
+        };
+        let cfg = &self.cfg;
+        let chapter = &self.chapter;
+        let failures = &mut self.failures;
+        let transform_time = &mut self.timings.transform;
+        let key = match &transform {
+            Some(transform) => format!(
+                "{}\n{}",
+                serde_json::to_string(transform).unwrap_or_default(),
+                normalized_source(source)
+            ),
+            None => normalized_source(source),
+        };
+        let transform = transform.as_ref().unwrap_or(&cfg.transform);
+        let svg = self.memo.entry(key).or_insert_with(|| {
+            if let Some(e) = config_error {
+                return error_html(&e);
+            }
+            let render = || match expand_qr(source) {
+                Ok(source) => match cfg.isolation {
+                    Isolation::None => svgdx_handler(&source, transform),
+                    Isolation::Process => render_isolated(&source, cfg, transform),
+                },
+                Err(e) => error_html(&e),
+            };
+            let start = Instant::now();
+            let svg = match &cfg.cache_dir {
+                Some(dir) => cached(dir, &cache_key(source, transform), render),
+                None => render(),
+            };
+            *transform_time += start.elapsed();
+            if !cfg.palette.is_empty() {
+                let unknown = off_palette(&svg, &cfg.palette);
+                if !unknown.is_empty() {
+                    eprintln!(
+                        "Warning: svgdx diagram in chapter '{}' uses colours outside the palette: {}",
+                        chapter,
+                        unknown.join(", ")
+                    );
+                }
+            }
+            postprocess(svg, cfg)
+        });
+        // Anything other than an SVG document is an error message.
+        if root_tag(svg).is_none() && !failures.contains(chapter) {
+            failures.push(chapter.clone());
         }
+        svg
     }
-    let mut buf = String::new();
-    cmark(&mut events.iter(), &mut buf)?;
-    Ok(buf)
 }
 
-fn svgdx_handler(s: &str) -> String {
-    let cfg = svgdx::TransformConfig {
-        svg_style: Some("max-width: 100%; height: auto;".to_string()),
-        use_local_styles: true,
-        scale: 1.5,
-        ..Default::default()
+/// The svgdx `source` without whitespace between its elements or within
+/// their tags.
+///
+/// Used as the memoization and cache key so that trivially reformatted copies
+/// of a diagram share a single rendering. Text, attribute values and the
+/// content of elements such as `<style>` and `<foreignObject>` are kept as
+/// they are, as are comments, since svgdx may include them in its output.
+fn normalized_source(source: &str) -> String {
+    without_layout(source)
+}
+
+/// Apply book-level adjustments to rendered svgdx output.
+fn postprocess(svg: String, cfg: &SvgdxConfig) -> String {
+    let svg = unescape_foreign_text(svg);
+    let svg = draw_qr_codes(svg);
+    let svg = if cfg.harmonize_text {
+        harmonize_text_size(svg, cfg.transform.scale.unwrap_or(1.5))
+    } else {
+        svg
     };
-    svgdx::transform_str(s.to_string(), &cfg).unwrap_or_else(|e| {
-        format!(
-            r#"<div style="color: red; border: 5px double red; padding: 1em;">{}</div>"#,
-            e.to_string().replace('\n', "<br/>")
-        )
-    })
+    let svg = match cfg.auto_styles {
+        AutoStyles::Nested => scope_author_styles(svg),
+        AutoStyles::Scoped => scope_styles(svg),
+        AutoStyles::None => strip_styles(svg),
+    };
+    let svg = if cfg.palette.is_empty() {
+        svg
+    } else {
+        palette::remap(&svg, &cfg.palette)
+    };
+    let svg = match cfg.sizing.get(&cfg.renderer) {
+        Sizing::Responsive => svg,
+        Sizing::Viewbox => remove_root_attr(&remove_root_attr(&svg, "width"), "height"),
+        Sizing::Fixed => remove_root_attr(&svg, "style"),
+    };
+    let svg = if cfg.patterns.is_empty() {
+        svg
+    } else {
+        add_pattern_styles(svg, &cfg.patterns)
+    };
+    let svg = if cfg.high_contrast {
+        add_high_contrast_style(svg, cfg.high_contrast_stroke)
+    } else {
+        svg
+    };
+    let svg = match &cfg.svg_class {
+        Some(class) => add_root_class(svg, class),
+        None => svg,
+    };
+    if cfg.version_comment {
+        add_version_comment(svg)
+    } else {
+        svg
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+const TEMPLATE_WITHOUT_TABLE: &str = "A svgdx-template block must be followed by a table";
+const CONTINUE_WITHOUT_DIAGRAM: &str = "A svgdx-continue block must follow a svgdx diagram";
 
-    use assertables::assert_contains;
+/// The text preceding `offset` on its line of `text`.
+pub(crate) fn line_prefix(text: &str, offset: usize) -> &str {
+    let line_start = text[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+    &text[line_start..offset]
+}
 
-    #[test]
-    fn process_basic_svgdx() {
-        let content = r##"
-Some **markdown** text
+/// `text` with each line after the first indented to continue the markdown
+/// containers (e.g. list items or blockquotes) opened by `prefix`.
+fn continue_lines<'a>(text: &'a str, prefix: &str) -> Cow<'a, str> {
+    if prefix.is_empty() {
+        return Cow::Borrowed(text);
+    }
+    // List markers are replaced by spaces; blockquote markers are repeated.
+    let continuation = prefix
+        .chars()
+        .map(|c| {
+            if c == '>' || c.is_whitespace() {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect::<String>();
+    // A list item can't start with blank lines.
+    let text = if continuation == prefix {
+        text
+    } else {
+        text.trim_start_matches('\n')
+    };
+    let (body, trailing) = match text.strip_suffix('\n') {
+        Some(body) => (body, "\n"),
+        None => (text, ""),
+    };
+    Cow::Owned(format!(
+        "{}{}",
+        body.replace('\n', &format!("\n{}", continuation)),
+        trailing
+    ))
+}
 
-```svgdx
-<svg>
-  <rect wh="20 5"/>
-</svg>
-```
-"##;
+/// Text built from `source` with ranges of it replaced.
+struct Spliced<'a> {
+    source: &'a str,
+    output: String,
+    /// Offset up to which `source` has been copied to `output`.
+    copied: usize,
+    /// Whether any range has been replaced.
+    replaced: bool,
+}
 
-        let expected1 = r##"Some **markdown** text
+impl<'a> Spliced<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            output: String::with_capacity(source.len()),
+            copied: 0,
+            replaced: false,
+        }
+    }
 
-<div class='svgdx'>
+    /// Replace `range` of the source, which must follow any previously
+    /// replaced range, with `text`.
+    fn replace(&mut self, mut range: Range<usize>, text: &str) {
+        self.output.push_str(&self.source[self.copied..range.start]);
+        let prefix = line_prefix(self.source, range.start);
+        self.output.push_str(&continue_lines(text, prefix));
+        // Blocks within containers may not include their final line break,
+        // which is part of the replacement (or the rendered output a token
+        // stands in for) instead.
+        let at_line_end =
+            !self.source[..range.end].ends_with('\n') && self.source[range.end..].starts_with('\n');
+        if !text.is_empty() && at_line_end {
+            range.end += 1;
+        }
+        self.copied = range.end;
+        self.replaced = true;
+    }
 
+    fn finish(mut self) -> String {
+        self.output.push_str(&self.source[self.copied..]);
+        self.output
+    }
+}
 
-<svg "##;
-        let expected2 = r##"
-  <rect width="20" height="5"/>
-</svg></div>"##;
-        let mut chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
-        let result = codeblock_parser(&mut chapter).unwrap();
-        assert_contains!(result, expected1);
-        assert_contains!(result, expected2);
+fn codeblock_parser(chapter: &Chapter, renderer: &mut Renderer) -> Result<String, std::fmt::Error> {
+    let start = Instant::now();
+    renderer.start_chapter(chapter);
+    let normalized = normalize(&chapter.content);
+    let content = normalized.as_deref().unwrap_or(&chapter.content);
+    let hero = insert_hero(content);
+    let content = hero.as_deref().unwrap_or(content);
+    let galleries = expand_galleries(content, &renderer.chapter_dir);
+    let content = galleries.as_deref().unwrap_or(content);
+    let refs = resolve_refs(
+        content,
+        &renderer.figures,
+        chapter.path.as_ref(),
+        &renderer.path_to_root,
+    );
+    let content = refs.as_deref().unwrap_or(content);
+    let md_events = mdbook::utils::new_cmark_parser(content, false).into_offset_iter();
 
-        let mut z = Book::new();
-        z.push_item(chapter);
+    // Rather than serializing the parsed markdown, which would reformat it,
+    // svgdx content is replaced in place in the chapter's text, leaving
+    // everything else unchanged. Events are processed as they are parsed
+    // rather than collected first, keeping memory use bounded for very large
+    // chapters. Phase one replaces svgdx content with tokens, which are
+    // substituted with the rendered output once the chapter is complete.
+    let mut output = Spliced::new(content);
+    let mut in_block: Option<(FenceInfo, String, Range<usize>)> = None;
+    let mut html_block: Option<(String, Range<usize>)> = None;
+    // Group name of the preceding event, if it was a grouped svgdx block.
+    let mut group: Option<String> = None;
+    // A `svgdx-template` block, awaiting the table which follows it.
+    let mut template: Option<(FenceInfo, String, Range<usize>)> = None;
+    let mut table: Option<Table> = None;
+    for (ev, range) in md_events {
+        if let Some(records) = table.as_mut() {
+            if records.push(&ev) {
+                let records = table.take().expect("in table");
+                let (mut info, source, fence) = template.take().expect("template for table");
+                info.block_type = "svgdx".to_string();
+                let source = records.instantiate(&source);
+                output.replace(fence, &renderer.defer(Deferred::Fence(info, source)));
+            }
+            continue;
+        }
+        if template.is_some() {
+            if matches!(ev, Start(Tag::Table(_))) {
+                let mut records = Table::default();
+                records.push(&ev);
+                table = Some(records);
+                continue;
+            }
+            let (_, _, fence) = template.take().expect("template");
+            let error = Deferred::Error(TEMPLATE_WITHOUT_TABLE.to_string());
+            output.replace(fence, &renderer.defer(error));
+        }
+        match (&mut in_block, &ev) {
+            (None, Start(Tag::CodeBlock(Fenced(info_string)))) => {
+                let info = FenceInfo::parse(info_string);
+                if is_figure(&info.block_type)
+                    || matches!(info.block_type.as_str(), "svgdx-part" | "svgdx-defs")
+                {
+                    in_block = Some((info, String::new(), range));
+                    continue;
+                }
+            }
+            (Some((_, source, _)), Text(content)) => {
+                source.push_str(content);
+                continue;
+            }
+            (Some(_), End(TagEnd::CodeBlock)) => {
+                let (mut info, mut source, fence) = in_block.take().expect("in svgdx block");
+                // Numbered as by `Figures::collect()`.
+                if is_figure(&info.block_type) && !info.is_omitted(renderer.cfg.profile) {
+                    let idx = renderer.figure_count;
+                    renderer.figure_count += 1;
+                    info.figure = renderer.figures.number(&renderer.chapter_path, idx);
+                }
+                if let Some(src) = info.get("src") {
+                    let loaded = if source.trim().is_empty() {
+                        renderer.read_src(src)
+                    } else {
+                        Err(format!("A block with src=\"{}\" must be empty", src))
+                    };
+                    match loaded {
+                        Ok(contents) => source = contents,
+                        Err(e) => {
+                            output.replace(fence, &renderer.defer(Deferred::Error(e)));
+                            group = None;
+                            continue;
+                        }
+                    }
+                }
+                if !renderer.cfg.namespace_ids && info.block_type != "svgdx-defs" {
+                    let line = content[..fence.start].matches('\n').count() + 1;
+                    let duplicates = renderer.ids.check(&source, line);
+                    if !duplicates.is_empty() {
+                        eprintln!(
+                            "Warning: svgdx diagram at line {} of chapter '{}' defines ids already defined by another diagram: {}",
+                            line,
+                            renderer.chapter,
+                            duplicates.join(", ")
+                        );
+                    }
+                }
+                match info.block_type.as_str() {
+                    "svgdx-part" => {
+                        // Shown as source, and rendered at the next `svgdx-render`.
+                        // Only the fence's info string is changed.
+                        renderer.parts.push_str(&source);
+                        let lang = match &renderer.cfg.hidden_line_marker {
+                            Some(marker) => format!("xml hidelines={}", marker),
+                            None => "xml".to_string(),
+                        };
+                        let fence_line = &content[fence.clone()];
+                        let marker_len = fence_line
+                            .find(|c| c != '`' && c != '~')
+                            .unwrap_or(fence_line.len());
+                        let line_len = fence_line.find('\n').map_or(fence_line.len(), |n| n + 1);
+                        let marker = &fence_line[..marker_len];
+                        output.replace(
+                            fence.start..fence.start + line_len,
+                            &format!("{}{}\n", marker, lang),
+                        );
+                        group = None;
+                        continue;
+                    }
+                    "svgdx-defs" => {
+                        // Not shown, but included in the chapter's later diagrams.
+                        output.replace(fence, &renderer.defer(Deferred::Defs(source)));
+                        group = None;
+                        continue;
+                    }
+                    "svgdx-render" => {
+                        source = renderer.take_parts(&source);
+                        info.block_type = "svgdx".to_string();
+                    }
+                    "svgdx-continue" => match &renderer.previous {
+                        Some(previous) => {
+                            source = extend_diagram(previous, &source);
+                            info.block_type = "svgdx".to_string();
+                        }
+                        None => {
+                            let error = Deferred::Error(CONTINUE_WITHOUT_DIAGRAM.to_string());
+                            output.replace(fence, &renderer.defer(error));
+                            group = None;
+                            continue;
+                        }
+                    },
+                    "svgdx-template" => {
+                        // Rendered once the following table has been read.
+                        template = Some((info, source, fence));
+                        group = None;
+                        continue;
+                    }
+                    _ => {}
+                }
+                if is_svgdx_fence(&info.block_type) {
+                    renderer.previous = Some(source.clone());
+                }
+                let name = info.get("group").map(str::to_owned);
+                if name.is_some() && name == group {
+                    renderer.join_group(info, source);
+                    output.replace(fence, "");
+                } else {
+                    output.replace(fence, &renderer.defer(Deferred::Fence(info, source)));
+                }
+                group = name;
+                continue;
+            }
+            // HTML blocks are parsed line by line, but are buffered so that
+            // embedded svgdx content spanning multiple lines can be found.
+            (None, Start(Tag::HtmlBlock)) => {
+                html_block = Some((String::new(), range));
+            }
+            (None, Html(html)) if html_block.is_some() => {
+                html_block.as_mut().expect("in HTML block").0.push_str(html);
+            }
+            (None, End(TagEnd::HtmlBlock)) => {
+                let (mut html, block) = html_block.take().unwrap_or_default();
+                let mut changed = false;
+                if let Some(filled) = fill_placeholders(&html, renderer) {
+                    html = filled;
+                    changed = true;
+                }
+                if let Some(rendered) = render_code_islands(&html, renderer) {
+                    html = rendered;
+                    changed = true;
+                }
+                if changed {
+                    output.replace(block, &html);
+                }
+            }
+            _ => {}
+        }
+        group = None;
+    }
+    if let Some((_, _, fence)) = template {
+        let error = Deferred::Error(TEMPLATE_WITHOUT_TABLE.to_string());
+        output.replace(fence, &renderer.defer(error));
+    }
+    // Time spent rendering svgdx sources counts as transformation, in
+    // whichever phase it happens.
+    let transformed = renderer.timings.transform;
+    renderer.timings.parse = start.elapsed().saturating_sub(transformed);
+    if !output.replaced && hero.is_none() && galleries.is_none() && refs.is_none() {
+        // Nothing to render, so the chapter is returned as it was, without
+        // normalized line endings.
+        return Ok(chapter.content.clone());
+    }
+    let start = Instant::now();
+    let markdown = output.finish();
+    renderer.timings.serialize = start.elapsed();
+    let start = Instant::now();
+    let content = renderer.substitute_deferred(&markdown);
+    renderer.timings.postprocess = start
+        .elapsed()
+        .saturating_sub(renderer.timings.transform - transformed);
+    Ok(content)
+}
+
+fn svgdx_handler(s: &str, options: &TransformOptions) -> String {
+    options
+        .transform_config()
+        .and_then(|cfg| svgdx::transform_str(s.to_string(), &cfg).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| error_html(&e))
+}
+
+/// Format an error message for display in place of a diagram.
+fn error_html(msg: &str) -> String {
+    format!(
+        r#"<div style="color: red; border: 5px double red; padding: 1em;">{}</div>"#,
+        msg.replace('\n', "<br/>")
+    )
+}
+
+/// The message shown by `html` from `error_html()`.
+fn error_message(html: &str) -> String {
+    html.strip_prefix(r#"<div style="color: red; border: 5px double red; padding: 1em;">"#)
+        .and_then(|msg| msg.strip_suffix("</div>"))
+        .unwrap_or(html)
+        .replace("<br/>", "\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn process_basic_svgdx() {
+        let content = r##"
+Some **markdown** text
+
+```svgdx
+<svg>
+  <rect wh="20 5"/>
+</svg>
+```
+"##;
+
+        let expected1 = r##"Some **markdown** text
+
+"##;
+        let expected_div = r##"
+<div class='svgdx'>
+<svg "##;
+        let expected2 = r##"
+  <rect width="20" height="5"/>
+</svg>
+</div>"##;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert_contains!(result, expected1);
+        assert_contains!(result, expected_div);
+        assert_contains!(result, expected2);
+
+        let mut z = Book::new();
+        z.push_item(chapter);
+    }
+
+    #[test]
+    fn repeated_blocks_rendered_once() {
+        let content = r##"
+```svgdx
+<svg><rect wh="20 5"/></svg>
+```
+
+```svgdx
+<svg><rect wh="20 5"/></svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("<rect").count(), 2);
+        assert_eq!(renderer.memo.len(), 1);
+    }
+
+    #[test]
+    fn fence_options_and_legend() {
+        let content = r##"
+```svgdx legend=auto
+<svg><rect wh="20 5" class="d-red"/></svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer
+            .cfg
+            .legend
+            .insert("d-red".to_string(), "Failure".to_string());
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "<div class='svgdx'>");
+        assert_contains!(result, "<ul class='svgdx-legend'");
+        assert_contains!(result, " Failure</li>");
+    }
+
+    #[test]
+    fn consecutive_blocks_grouped() {
+        let content = r##"
+```svgdx group=a
+<svg><rect wh="1"/></svg>
+```
+```svgdx-xml group=a
+<svg><rect wh="2"/></svg>
+```
+
+```svgdx group=a
+<svg><rect wh="3"/></svg>
+```
+
+Text
+
+```svgdx group=a
+<svg><rect wh="4"/></svg>
+```
+"##;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert_eq!(result.matches("<div class='svgdx-group'").count(), 1);
+        let (group, rest) = result.split_once("Text").unwrap();
+        assert_eq!(group.matches("<svg ").count(), 3);
+        assert_contains!(group, "</svg>\n</div>\n<div class='svgdx-xml'>\n<svg ");
+        assert_eq!(rest.matches("<svg ").count(), 1);
+    }
+
+    #[test]
+    fn wrapper_styles() {
+        let content = "```svgdx-xml-inline\n<svg/>\n```\n```svgdx group=a\n<svg/>\n```\n```svgdx group=a\n<svg/>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("style='display: flex;").count(), 2);
+
+        renderer.cfg.wrapper_style = WrapperStyle::None;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains("display: flex"));
+        assert_contains!(result, "<div class='svgdx-xml-inline'>");
+        assert_contains!(result, "<div class='svgdx-group'>");
+
+        renderer.cfg.wrapper_style = WrapperStyle::Custom("gap: 1em;".to_string());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "<div style='gap: 1em;' class='svgdx-xml-inline'>");
+        assert_contains!(result, "<div class='svgdx-group' style='gap: 1em;'>");
+    }
+
+    #[test]
+    fn literate_parts_rendered_together() {
+        let content = r##"
+First a box:
+
+```svgdx-part
+<rect id="a" wh="20 5"/>
+```
+
+Then a circle beside it:
+
+```svgdx-part
+<circle xy="^:h 5" r="3"/>
+```
+
+```svgdx-render
+```
+"##;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        renderer.cfg.namespace_ids = true;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "```xml\n<rect id=\"a\" wh=\"20 5\"/>\n```");
+        assert_eq!(result.matches("<svg ").count(), 1);
+        assert_contains!(result, "<div class='svgdx'>\n<svg ");
+        assert_contains!(result, r#"-1-a" width="20" height="5"/>"#);
+        assert_contains!(result, "<circle ");
+    }
+
+    #[test]
+    fn ids_unique_per_diagram() {
+        let block = "```svgdx\n<svg><rect id=\"a\" wh=\"5\"/><line xy=\"#a\" dxy=\"5\" class=\"d-arrow\"/></svg>\n```\n\n";
+        let chapter = Chapter::new("test", block.repeat(2), "ch.md", Vec::new());
+        let mut renderer = Renderer::default();
+        renderer.cfg.namespace_ids = true;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        let source =
+            "<svg><rect id=\"a\" wh=\"5\"/><line xy=\"#a\" dxy=\"5\" class=\"d-arrow\"/></svg>\n";
+        let hash = content_hash(format!("ch.md\n{}", normalized_source(source)));
+        let prefix = format!("svgdx-{}", &hash[..8]);
+        for n in 1..=2 {
+            assert_contains!(result, &format!("<marker id=\"{prefix}-{n}-d-arrow\""));
+            assert_contains!(result, &format!("url(#{prefix}-{n}-d-arrow)"));
+            assert_contains!(result, &format!("<svg id=\"{prefix}-{n}\""));
+        }
+
+        renderer.cfg.namespace_ids = false;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "<marker id=\"d-arrow\"");
+        for n in 1..=2 {
+            assert_contains!(result, &format!("<svg id=\"{prefix}-{n}\""));
+        }
+    }
+
+    #[test]
+    fn ids_stable_across_prose_edits() {
+        let block = "```svgdx\n<svg><line xy=\"0\" dxy=\"5\" class=\"d-arrow\"/></svg>\n```\n";
+        let manifest = |content: String| {
+            let chapter = Chapter::new("test", content, "ch.md", Vec::new());
+            let mut renderer = Renderer::default();
+            renderer.cfg.manifest = Some(PathBuf::from("manifest.json"));
+            codeblock_parser(&chapter, &mut renderer).unwrap();
+            renderer.manifest
+        };
+        assert_eq!(
+            manifest(block.to_string()),
+            manifest(format!("Some prose.\n\n{}", block))
+        );
+    }
+
+    #[test]
+    fn chapter_defs_in_later_blocks() {
+        let content = "```svgdx\n<svg><reuse href=\"#box\"/></svg>\n```\n\n\
+                       ```svgdx-defs\n<specs><rect id=\"box\" wh=\"20 5\"/></specs>\n```\n\n\
+                       ```svgdx\n<svg><reuse href=\"#box\"/></svg>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        renderer.cfg.preamble_source = "<var size=\"5\"/>\n".to_string();
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        // The first block precedes the definition, so fails to render.
+        assert_eq!(result.matches("<svg ").count(), 1);
+        assert_contains!(result, r#"<rect width="20" height="5" class="box"/>"#);
+        assert!(!result.contains("specs"));
+
+        let chapter = Chapter::new(
+            "next",
+            content.replace("```svgdx-defs", "```xml"),
+            ".",
+            Vec::new(),
+        );
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains(r#"class="box""#));
+    }
+
+    #[test]
+    fn continue_extends_previous_diagram() {
+        let content = "```svgdx-continue\n<circle r=\"1\"/>\n```\n\n\
+                       ```svgdx\n<svg><rect id=\"a\" wh=\"5\"/></svg>\n```\n\n\
+                       ```svgdx-continue\n<circle xy=\"#a|h\" r=\"2\"/>\n```\n\n\
+                       ```svgdx-continue\n<text xy=\"#a\">x</text>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, CONTINUE_WITHOUT_DIAGRAM);
+        assert_eq!(result.matches("<svg ").count(), 3);
+        assert_eq!(result.matches("<rect ").count(), 3);
+        assert_eq!(result.matches("<circle ").count(), 2);
+        assert_eq!(result.matches("<text ").count(), 1);
+    }
+
+    #[test]
+    fn preamble_in_every_block() {
+        let content = "```svgdx\n<svg><reuse href=\"#box\"/></svg>\n```\n\n```svgdx\n<svg/>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        renderer.cfg.preamble_source =
+            "<specs><rect id=\"box\" wh=\"20 5\" class=\"shared\"/></specs>\n".to_string();
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(
+            result,
+            r#"<rect width="20" height="5" class="shared box"/>"#
+        );
+        assert_eq!(result.matches("<svg ").count(), 2);
+        assert!(!result.contains("<specs>"));
+    }
+
+    #[test]
+    fn chapters_without_diagrams_unchanged() {
+        let content = "# Title\n\n| a | b |\n|---|:-:|\n| 1 | 2 |\n\nText[^note] with \\*escapes\\*.\n\n[^note]: A footnote.\n\n```rust\nfn main() {}\n```\n";
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        assert_eq!(codeblock_parser(&chapter, &mut renderer).unwrap(), content);
+
+        let crlf = format!("\u{feff}{}", content.replace('\n', "\r\n"));
+        let chapter = Chapter::new("test", crlf.clone(), ".", Vec::new());
+        assert_eq!(codeblock_parser(&chapter, &mut renderer).unwrap(), crlf);
+
+        let content = format!("{content}\n```svgdx\n<svg/>\n```\n");
+        let chapter = Chapter::new("test", content.clone(), ".", Vec::new());
+        assert_ne!(codeblock_parser(&chapter, &mut renderer).unwrap(), content);
+    }
+
+    #[test]
+    fn windows_sources() {
+        let content =
+            "\u{feff}```svgdx\r\n<svg>\r\n<rect wh=\"20 5\" text=\"a\"/>\r\n</svg>\r\n```\r\n";
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "<div class='svgdx'>");
+        assert!(!result.contains(['\r', '\u{feff}']));
+        assert!(renderer.errors.is_empty());
+
+        let dir = std::env::temp_dir().join("mdbook-svgdx-encoding-src-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("latin1.svgdx"), b"<svg>\xe9</svg>").unwrap();
+        renderer.cfg.src_dir = dir;
+        let content = "```svgdx src=latin1.svgdx\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), "a.md", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "isn't valid UTF-8 at line 1, column 6");
+    }
+
+    #[test]
+    fn render_failures_recorded() {
+        let content = "```svgdx\n<svg><rect xy=\"^:h\"/></svg>\n```\n";
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("broken", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "color: red");
+        assert_eq!(renderer.failures, ["broken"]);
+        assert_eq!(renderer.errors.len(), 1);
+        assert!(renderer.errors[0].starts_with("chapter 'broken', block 1: "));
+        assert!(!renderer.errors[0].contains("<div"));
+    }
+
+    #[test]
+    fn block_errors_recorded() {
+        let cases = [
+            (
+                "scale",
+                "```svgdx scale=big\n<svg/>\n```\n",
+                "Invalid scale 'big'",
+            ),
+            ("seq", "```svgdx-seq\nClient\n```\n", "svgdx-seq line 1"),
+            ("graph", "```svgdx-graph\n-> B\n```\n", "svgdx-graph line 1"),
+            ("src", "```svgdx src=missing.svgdx\n```\n", "Could not read"),
+            (
+                "template",
+                "```svgdx-template\n<svg/>\n```\n\nText\n",
+                TEMPLATE_WITHOUT_TABLE,
+            ),
+            (
+                "last-template",
+                "```svgdx-template\n<svg/>\n```\n",
+                TEMPLATE_WITHOUT_TABLE,
+            ),
+            (
+                "continue",
+                "```svgdx-continue\n<rect/>\n```\n",
+                CONTINUE_WITHOUT_DIAGRAM,
+            ),
+        ];
+        for (name, content, message) in cases {
+            let mut renderer = Renderer::default();
+            let chapter = Chapter::new(name, content.to_owned(), "a.md", Vec::new());
+            let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+            assert_contains!(result, "color: red");
+            assert_eq!(renderer.failures, [name]);
+            assert_eq!(renderer.errors.len(), 1, "{}", name);
+            let prefix = format!("chapter '{}', block 1: ", name);
+            assert!(renderer.errors[0].starts_with(&prefix), "{}", name);
+            assert_contains!(renderer.errors[0], message);
+        }
+    }
+
+    #[test]
+    fn build_error_kinds() {
+        let context = |cfg: &str| -> PreprocessorContext {
+            serde_json::from_value(serde_json::json!({
+                "root": std::env::temp_dir(),
+                "config": {"preprocessor": {"svgdx": serde_json::from_str::<serde_json::Value>(cfg).unwrap()}},
+                "renderer": "html",
+                "mdbook_version": mdbook::MDBOOK_VERSION,
+            }))
+            .unwrap()
+        };
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "broken",
+            "```svgdx\n<svg><rect xy=\"^:h\"/></svg>\n```\n".to_string(),
+            "broken.md",
+            Vec::new(),
+        ));
+        let kind = |cfg: &str| {
+            let e = SvgdxProc.run(&context(cfg), book.clone()).unwrap_err();
+            match e.downcast_ref::<BuildError>().unwrap() {
+                BuildError::Config(_) => "config",
+                BuildError::Render(_) => "render",
+                BuildError::Io(_) => "io",
+            }
+        };
+        assert_eq!(kind(r#"{"wrap": "bogus"}"#), "config");
+        assert_eq!(kind(r#"{"fail-on-error": true}"#), "render");
+        assert_eq!(kind(r#"{"appendices": ["no-such-dir"]}"#), "io");
+        assert_eq!(kind(r#"{"preamble": "no-such-file.xml"}"#), "config");
+        assert_eq!(kind(r#"{"worker-cpu-limit": 0}"#), "config");
+    }
+
+    #[test]
+    fn nested_chapters_keep_content() {
+        let ctx: PreprocessorContext = serde_json::from_value(serde_json::json!({
+            "root": std::env::temp_dir(),
+            "config": {"preprocessor": {"svgdx": {}}},
+            "renderer": "html",
+            "mdbook_version": mdbook::MDBOOK_VERSION,
+        }))
+        .unwrap();
+        let block =
+            |id: &str| format!("```svgdx\n<svg><rect id=\"{}\" wh=\"5\"/></svg>\n```\n", id);
+        let mut parent = Chapter::new("Parent", block("parent"), "parent.md", Vec::new());
+        let child = Chapter::new(
+            "Child",
+            block("child"),
+            "child.md",
+            vec!["Parent".to_string()],
+        );
+        parent.sub_items.push(BookItem::Chapter(child));
+        let mut book = Book::new();
+        book.push_item(parent);
+        book.push_item(Chapter::new("Last", block("last"), "last.md", Vec::new()));
+
+        let book = SvgdxProc.run(&ctx, book).unwrap();
+        let contents: Vec<_> = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => Some((chapter.name.as_str(), &chapter.content)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(contents.len(), 3);
+        for (name, content) in contents {
+            let id = format!(r#"<rect id="{}""#, name.to_lowercase());
+            assert_contains!(content, &id);
+        }
+    }
+
+    #[test]
+    fn draft_badge() {
+        let content = "```svgdx status=review owner=\"Docs team\"\n<svg/>\n```\n";
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains("svgdx-badge"));
+
+        renderer.cfg.profile = Profile::Draft;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, ">REVIEW · owner: Docs team</div>");
+        assert_eq!(badge_html(&FenceInfo::parse("svgdx")), None);
+    }
+
+    #[test]
+    fn configurable_wrapping() {
+        let content = "```svgdx\n<svg/>\n```\n\n```svgdx wrap=div\n<svg><rect/></svg>\n```\n";
+        let mut renderer = Renderer::default();
+        renderer.cfg.wrap = Wrap::Paragraph;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "\n\n<p class='svgdx'>\n<svg ");
+        assert_contains!(result, "\n\n<div class='svgdx'>\n<svg ");
+
+        let content = "```svgdx-xml wrap=none\n<svg/>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(result.starts_with("\n\n<svg "));
+        assert!(!result.contains("class='svgdx-xml'"));
+
+        let content = "```svgdx wrap=figure caption=\"A <box>\"\n<svg><rect/></svg>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "\n\n<figure class='svgdx'>\n<svg ");
+        assert_contains!(
+            result,
+            "</svg>\n<figcaption style='text-align: center; font-style: italic;'>A &lt;box&gt;</figcaption>\n</figure>"
+        );
+        assert!(!result.contains("svgdx-caption"));
+    }
+
+    #[test]
+    fn caption_from_title() {
+        let content = "```svgdx\n<svg><title>Web tier</title><rect wh=\"20 5\"/></svg>\n```\n\n\
+                       ```svgdx caption=Given\n<svg><title>Ignored</title><rect wh=\"5\"/></svg>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        renderer.cfg.title_caption = true;
+        renderer.cfg.accessibility = true;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "'>Web tier</div>");
+        assert_contains!(result, r#" aria-label="Web tier">"#);
+        assert_eq!(result.matches("<title>Web tier</title>").count(), 1);
+        assert_contains!(result, "'>Given</div>");
+        assert!(!result.contains("'>Ignored</div>"));
+
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert!(!result.contains("'>Web tier</div>"));
+    }
+
+    #[test]
+    fn accessible_diagrams() {
+        let content =
+            "```svgdx alt=\"Two tiers\" caption=Tiers\n<svg><rect wh=\"20 5\" text=\"Web\"/></svg>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        renderer.cfg.accessibility = true;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, r#" role="img" aria-label="Two tiers">"#);
+        assert_contains!(result, "<title>Two tiers</title><desc>Web</desc>");
+
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert!(!result.contains("role=") && !result.contains("<title>"));
+    }
+
+    #[test]
+    fn numbered_figures() {
+        let mut first = Chapter::new(
+            "First",
+            "See {{#svgdxref flow}}.\n\n```svgdx caption=Boxes\n<svg><rect/></svg>\n```\n"
+                .to_string(),
+            "first.md",
+            Vec::new(),
+        );
+        first.number = Some(mdbook::book::SectionNumber(vec![1]));
+        let mut second = Chapter::new(
+            "Second",
+            "```svgdx label=flow wrap=figure\n<svg><rect/></svg>\n```\n".to_string(),
+            "part/second.md",
+            Vec::new(),
+        );
+        second.number = Some(mdbook::book::SectionNumber(vec![2]));
+        let cfg = SvgdxConfig {
+            figure_numbers: config::FigureNumbers::Chapter,
+            ..Default::default()
+        };
+        let (contents, _) = process_chapters(&[&first, &second], cfg);
+        let first = contents[0].as_ref().unwrap();
+        assert!(first.starts_with("See [Figure 2.1](<part/second.md#svgdx-fig-flow>)."));
+        assert_contains!(first, ">Figure 1.1: Boxes</div>");
+        let second = contents[1].as_ref().unwrap();
+        assert_contains!(second, "<figure id='svgdx-fig-flow' class='svgdx'>");
+        assert_contains!(second, ">Figure 2.1</figcaption>");
+    }
+
+    #[test]
+    fn chapters_processed_in_parallel() {
+        let chapters = (0..20)
+            .map(|idx| {
+                let source = if idx % 7 == 3 {
+                    "<svg><rect xy=\"#missing\"/></svg>".to_string()
+                } else {
+                    format!("<svg><rect wh=\"{} 5\"/></svg>", idx + 1)
+                };
+                let content = format!("# Chapter {idx}\n\n```svgdx\n{source}\n```\n");
+                Chapter::new(
+                    &format!("ch{idx}"),
+                    content,
+                    format!("ch{idx}.md"),
+                    Vec::new(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let chapters = chapters.iter().collect::<Vec<_>>();
+        for parallel in [true, false] {
+            let cfg = SvgdxConfig {
+                parallel,
+                ..Default::default()
+            };
+            let (contents, renderer) = process_chapters(&chapters, cfg);
+            for (idx, content) in contents.iter().enumerate() {
+                assert!(content
+                    .as_ref()
+                    .unwrap()
+                    .starts_with(&format!("# Chapter {idx}\n")));
+            }
+            assert_contains!(contents[1].as_ref().unwrap(), r#"viewBox="-5 -5 12 15""#);
+            assert_eq!(renderer.failures, ["ch3", "ch10", "ch17"]);
+            assert_eq!(renderer.chapter_timings.len(), chapters.len());
+            assert_eq!(renderer.chapter_timings[3].0, "ch3");
+            assert!(renderer
+                .chapter_timings
+                .iter()
+                .any(|(_, timings)| !timings.transform.is_zero()));
+            let errors = renderer
+                .errors
+                .iter()
+                .map(|e| e.split(':').next().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                errors,
+                [
+                    "chapter 'ch3', block 1",
+                    "chapter 'ch10', block 1",
+                    "chapter 'ch17', block 1"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn foreign_object_content_preserved() {
+        let content = r##"
+```svgdx-xml
+<svg>
+  <rect id="a" wh="40 20"/>
+  <foreignObject xy="#a" wh="40 20">
+    <div xmlns="http://www.w3.org/1999/xhtml"><b>Rich</b> &amp; <i>text</i>
+<pre>first
+
+    indented</pre></div>
+  </foreignObject>
+</svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer.cfg.auto_styles = AutoStyles::None;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(
+            result,
+            "<div xmlns=\"http://www.w3.org/1999/xhtml\"><b>Rich</b> &amp; <i>text</i>\n<pre>first\n&#10;    indented</pre></div>"
+        );
+        // The source listing shows the original markup.
+        assert_contains!(
+            result,
+            "<b>Rich</b> &amp; <i>text</i>\n<pre>first\n\n    indented</pre>"
+        );
+    }
+
+    #[test]
+    fn block_transform_options() {
+        let content = r#"
+```svgdx scale=3 caption="A <box>"
+<svg><rect wh="20 5"/></svg>
+```
+
+```svgdx
+<svg><rect wh="20 5"/></svg>
+```
+
+```svgdx scale=big
+<svg><rect wh="20 5"/></svg>
+```
+"#;
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, r#"width="90mm""#);
+        assert_contains!(result, r#"width="45mm""#);
+        assert_contains!(result, ">A &lt;box&gt;</div>");
+        assert_contains!(result, "Invalid scale 'big'");
+        assert_eq!(renderer.memo.len(), 2);
+    }
+
+    #[test]
+    fn stamped_figures() {
+        let content = "```svgdx\n<svg><rect wh=\"20 5\"/></svg>\n```\n";
+        let mut renderer = Renderer::new(SvgdxConfig {
+            stamp: Some(config::Stamp {
+                document_id: Some("DOC-42".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        let checksum = &content_hash(normalized_source("<svg><rect wh=\"20 5\"/></svg>"))[..8];
+        assert_contains!(result, &format!(">DOC-42 \u{b7} {checksum}</text></svg>"));
+    }
+
+    #[test]
+    fn dark_theme_variant() {
+        let content = r#"
+```svgdx
+<svg><rect wh="20 5"/></svg>
+```
+
+```svgdx theme=bold
+<svg><rect wh="20 5"/></svg>
+```
+"#;
+        let mut renderer = Renderer::new(SvgdxConfig {
+            dark_theme: Some("dark".to_string()),
+            ..Default::default()
+        });
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches(".svgdx-dark { display: none; }").count(), 1);
+        assert_eq!(result.matches(r#"<svg class="svgdx-light""#).count(), 1);
+        assert_eq!(result.matches(r#"<svg class="svgdx-dark""#).count(), 1);
+        assert_eq!(result.matches("<svg").count(), 3);
+        assert_eq!(renderer.block_count, 2);
+        assert_eq!(renderer.memo.len(), 3);
+    }
+
+    #[test]
+    fn config_elements_merged() {
+        let content = r#"
+```svgdx
+<svg><config border="0"/><rect wh="20 5"/></svg>
+```
+
+```svgdx scale=4
+<svg><rect wh="20 5"/><config scale="3"/></svg>
+```
+
+```svgdx
+<svg><config scale="x"/><rect wh="20 5"/></svg>
+```
+"#;
+        let mut renderer = Renderer::default();
+        renderer.cfg.transform.scale = Some(2.0);
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, r#"width="40mm" height="10mm""#);
+        assert_contains!(result, r#"width="90mm" height="45mm""#);
+        assert_contains!(result, "Invalid value 'x' for config setting scale");
+    }
+
+    #[test]
+    fn draft_figures_bannered_or_excluded() {
+        let content = "```svgdx status=draft\n<svg><rect wh=\"20 5\"/></svg>\n```\n";
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains("<svg"));
+
+        renderer.cfg.profile = Profile::Draft;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, ">DRAFT</text></svg>");
+    }
+
+    #[test]
+    fn source_tabs() {
+        let content = r##"
+```svgdx-xml
+<svg><rect wh="20 5"/></svg>
+```
+
+```xml-svgdx
+<svg><rect wh="20 5"/></svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer.cfg.source_layout = SourceLayout::Tabs;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("<style>.svgdx-tabs").count(), 1);
+        let (first, second) = result
+            .split_once("<div class='xml-svgdx svgdx-tabs'>")
+            .unwrap();
+        assert_contains!(first, "<div class='svgdx-xml svgdx-tabs'>\n<style>");
+        assert!(first.find("-svg' checked>").unwrap() < first.find("-xml'>").unwrap());
+        assert!(second.find("-xml'>").unwrap() < second.find("-svg' checked>").unwrap());
+        assert_contains!(second, "<div class='svgdx-panel'>\n<div>\n\n```xml\n");
+    }
+
+    #[test]
+    fn source_tabs_option() {
+        let content = r##"
+```svgdx
+<svg><rect wh="20 5"/></svg>
+```
+
+```svgdx source-tabs=false
+<svg><circle r="5"/></svg>
+```
+
+```svgdx-inline
+<svg><rect wh="20 5"/></svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer.cfg.source_tabs = true;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches(" svgdx-tabs'>").count(), 1);
+        let (tabs, rest) = result.split_once("<div class='svgdx'>").unwrap();
+        assert_contains!(tabs, "<div class='svgdx svgdx-tabs'>\n<style>");
+        assert_contains!(tabs, "<label for='svgdx-");
+        assert_contains!(tabs, "```xml\n<svg><rect wh=\"20 5\"/></svg>\n```");
+        assert!(!rest.contains("```xml"));
+    }
+
+    #[test]
+    fn attached_source() {
+        let content = "```svgdx scale=2\n<svg>\n\n<rect wh=\"20 5\" text='a'/>\n</svg>\n```\n\n```svgdx attach-source=false\n<svg/>\n```\n";
+        let mut renderer = Renderer::default();
+        renderer.cfg.attach_source = true;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("class='svgdx-source'").count(), 1);
+        assert_contains!(
+            result,
+            "</svg>\n<div hidden class='svgdx-source' data-transform='{&quot;scale&quot;:2.0}' \
+             data-source='&lt;svg&gt;&#10;&#10;&lt;rect wh=&quot;20 5&quot; text=&#39;a&#39;/&gt;&#10;&lt;/svg&gt;&#10;'></div>\n"
+        );
+    }
+
+    #[test]
+    fn wrapper_data_attributes() {
+        let source = "<svg><rect wh=\"20 5\"/></svg>\n";
+        let content =
+            format!("```svgdx scale=2\n{source}```\n\n```svgdx-xml wrap=paragraph\n<svg/>\n```\n");
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.clone(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains("data-fence"));
+
+        renderer.cfg.data_attributes = true;
+        renderer.cfg.transform.theme = Some("dark".to_string());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(
+            result,
+            &format!(
+                "<div class='svgdx' data-fence='svgdx' data-hash='{}' data-scale='2' data-theme='dark'>",
+                content_hash(normalized_source(source))
+            )
+        );
+        assert_contains!(
+            result,
+            "<p class='svgdx-xml' data-fence='svgdx-xml' data-hash='"
+        );
+    }
+
+    #[test]
+    fn high_contrast_toggle() {
+        let content = "```svgdx\n<svg><rect wh=\"5\"/></svg>\n```\n\n```svgdx\n<svg><circle r=\"2\"/></svg>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let mut renderer = Renderer::default();
+        renderer.cfg.high_contrast = true;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("function svgdxContrast()").count(), 1);
+        assert!(result.matches(":root.svgdx-high-contrast #").count() > 1);
+
+        renderer.cfg.src_dir = std::env::temp_dir().join("mdbook-svgdx-contrast-test");
+        renderer.cfg.output = config::PerRenderer::All(Output::File);
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains("svgdxContrast"));
+    }
+
+    #[test]
+    fn copy_svg_button() {
+        let content = "```svgdx\n<svg/>\n```\n\n```svgdx\n<svg><rect/></svg>\n```\n\n```svgdx copy-svg=false\n<svg/>\n```\n";
+        let mut renderer = Renderer::default();
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains("svgdx-copy"));
+
+        renderer.cfg.copy_svg = true;
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("<div class='svgdx-copyable'>").count(), 2);
+        assert_eq!(result.matches("function svgdxCopy").count(), 1);
+        assert_contains!(
+            result,
+            "onclick='svgdxCopy(this)'>Copy SVG</button>\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn collapsed_source() {
+        let content = r##"
+```svgdx-xml
+<svg><rect wh="20 5"/></svg>
+```
+
+```xml-svgdx source-layout=stacked
+<svg><rect wh="20 5"/></svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer.cfg.source_layout = SourceLayout::Collapsed;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("<details class='svgdx-source'>").count(), 1);
+        assert_contains!(
+            result,
+            "<details class='svgdx-source'>\n<summary>Show source</summary>\n<div>\n\n```xml\n"
+        );
+        assert!(result.find("<svg").unwrap() < result.find("<details").unwrap());
+    }
+
+    #[test]
+    fn max_height_scrolls() {
+        let content = r##"
+```svgdx
+<svg><rect wh="20 5"/></svg>
+```
+
+```svgdx max-height=10em
+<svg><rect wh="20 5"/></svg>
+```
+
+```svgdx max-height=none
+<svg><rect wh="20 5"/></svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer.cfg.max_height = Some("30em".to_string());
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("<style>.svgdx-expand").count(), 1);
+        assert_contains!(result, "style='max-height: 30em; overflow: auto;'>\n<svg ");
+        assert_contains!(result, "style='max-height: 10em; overflow: auto;'>\n<svg ");
+        assert_eq!(result.matches("class='svgdx-scroll'").count(), 2);
+    }
+
+    #[test]
+    fn overflow_scroll_natural_size() {
+        let content = "```svgdx overflow=scroll\n<svg><rect wh=\"200 5\"/></svg>\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert_contains!(
+            result,
+            "<div class='svgdx-scroll' style='overflow-x: auto;'>\n<svg "
+        );
+        assert!(!result.contains("max-width"));
+    }
+
+    #[test]
+    fn rotate_hint_for_wide_diagrams() {
+        let content = r##"
+```svgdx
+<svg><rect wh="200 5"/></svg>
+```
+
+```svgdx
+<svg><rect wh="20 5"/></svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer.cfg.rotate_hint = Some(3.0);
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_eq!(result.matches("<div class='svgdx-rotate-hint'>").count(), 1);
+        assert_contains!(result, "</svg>\n<div class='svgdx-rotate-hint'>");
+    }
+
+    #[test]
+    fn sequence_shorthand() {
+        let content = "```svgdx-seq\nClient -> Server: GET\nServer --> Client\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert_contains!(result, "<div class='svgdx-seq'>\n<svg ");
+        assert_contains!(result, ">GET</text>");
+        assert!(!result.contains("```xml"));
+    }
+
+    #[test]
+    fn graph_shorthand() {
+        let content = "```svgdx-graph\nStart -> End: go\nEnd -> End\n```\n";
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert_contains!(result, "<div class='svgdx-graph'>\n<svg ");
+        assert_contains!(result, ">go</text>");
+        assert!(!result.contains("color: red"));
+    }
+
+    #[test]
+    fn markdown_preserved_around_diagrams() {
+        let content = r##"| a | b |
+|---|:-:|
+| 1 | 2 |
+
+* item  with   odd spacing
+* ```svgdx
+  <svg><rect wh="20 5"/></svg>
+  ```
+
+> ```svgdx
+> <svg><rect wh="10 5"/></svg>
+> ```
+> after
+"##;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert!(
+            result.starts_with("| a | b |\n|---|:-:|\n| 1 | 2 |\n\n* item  with   odd spacing\n")
+        );
+        assert_contains!(result, "* <div class='svgdx'>\n  <svg ");
+        assert_contains!(result, "> <div class='svgdx'>\n> <svg ");
+        assert!(result.ends_with("> </div>\n> \n> after\n"));
+    }
+
+    #[test]
+    fn template_instantiated_from_table() {
+        let content = r##"
+```svgdx-template
+<rect x="{{x}}" wh="30 8" text="{{service}}"/>
+```
+
+| service | x  |
+|---------|----|
+| Web     | 0  |
+| DB      | 40 |
+
+```svgdx-template
+<rect/>
+```
+
+No table here.
+"##;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert_contains!(result, "<div class='svgdx'>\n<svg ");
+        assert_contains!(result, ">Web</text>");
+        assert_contains!(result, ">DB</text>");
+        assert!(result.find("</svg>").unwrap() < result.find("| service |").unwrap());
+        assert_contains!(result, "must be followed by a table");
+    }
+
+    #[test]
+    fn hero_from_front_matter() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-hero-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("hero.xml"), r#"<svg><rect wh="20 5"/></svg>"#).unwrap();
+
+        let content = "---\nsvgdx-hero: hero.xml\n---\n# Title\n";
+        let mut renderer = Renderer::default();
+        renderer.cfg.src_dir = dir;
+        let chapter = Chapter::new("test", content.to_owned(), "ch.md", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(
+            result,
+            r#"<div class="svgdx svgdx-hero" data-src="hero.xml"><svg "#
+        );
+        assert!(!result.contains("---"));
+    }
+
+    #[test]
+    fn normalize_insignificant_whitespace() {
+        let a = "<svg>\n  <rect wh=\"20 5\"/>\n</svg>\n";
+        let b = "<svg>\r\n\n    <rect\n      wh=\"20 5\" />   \r\n</svg>";
+        assert_eq!(normalized_source(a), normalized_source(b));
+        let pre = |indent: &str| {
+            normalized_source(&format!(
+                "<svg>\n<foreignObject><pre>\n{}x</pre></foreignObject>\n</svg>",
+                indent
+            ))
+        };
+        assert_ne!(pre("  "), pre("        "));
+        assert_ne!(
+            normalized_source(a),
+            normalized_source("<svg>\n<!-- a comment -->\n<rect wh=\"20 5\"/>\n</svg>")
+        );
+        assert_ne!(
+            normalized_source(a),
+            normalized_source("<svg><rect wh=\"20  5\"/></svg>")
+        );
+    }
+
+    #[test]
+    fn comments_not_memoized_away() {
+        let block = |comment: &str| {
+            format!(
+                "```svgdx\n<svg>\n<!-- {} -->\n<rect wh=\"5\"/>\n</svg>\n```\n\n",
+                comment
+            )
+        };
+        let content = block("first") + &block("second");
+        let chapter = Chapter::new("test", content, ".", Vec::new());
+        let mut renderer = Renderer::default();
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, "<!-- first -->");
+        assert_contains!(result, "<!-- second -->");
+    }
+
+    #[test]
+    fn fill_html_placeholder() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-placeholder-test");
+        std::fs::create_dir_all(dir.join("diagrams")).unwrap();
+        std::fs::write(
+            dir.join("diagrams/box.xml"),
+            r#"<svg><rect wh="20 5"/></svg>"#,
+        )
+        .unwrap();
+
+        let content = r#"
+<div class="page">
+<div class="svgdx" data-src="diagrams/box.xml"></div>
+<div class="svgdx" data-src="missing.xml"></div>
+</div>
+"#;
+        let mut renderer = Renderer::default();
+        renderer.cfg.src_dir = dir;
+        let chapter = Chapter::new("test", content.to_owned(), "page.md", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(
+            result,
+            r#"<div class="svgdx" data-src="diagrams/box.xml"><svg "#
+        );
+        assert_contains!(result, r#"<rect width="20" height="5"/>"#);
+        assert_contains!(result, "Could not read");
+    }
+
+    #[test]
+    fn markdown_output() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-markdown-output-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let content = "# Boxes\n\n```svgdx alt=\"A [box]\"\n<svg><rect wh=\"20 5\"/></svg>\n```\n";
+        let mut renderer = Renderer::new(SvgdxConfig {
+            renderer: "markdown".to_string(),
+            src_dir: dir.clone(),
+            output: config::PerRenderer::All(Output::Markdown),
+            ..Default::default()
+        });
+        let chapter = Chapter::new("test", content.to_owned(), "ch.md", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        let name = std::fs::read_dir(dir.join("svgdx-assets"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .file_name();
+        assert_contains!(
+            result,
+            &format!(
+                "\n```svgdx alt=\"A [box]\"\n<svg><rect wh=\"20 5\"/></svg>\n```\n\n\
+                 ![A \\[box\\]](svgdx-assets/{})\n\n",
+                name.to_string_lossy()
+            )
+        );
+    }
+
+    #[test]
+    fn file_output() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-file-output-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let content = "```svgdx caption=Box\n<svg><rect wh=\"20 5\"/></svg>\n```\n";
+        let mut renderer = Renderer::new(SvgdxConfig {
+            src_dir: dir.clone(),
+            output: config::PerRenderer::All(Output::File),
+            ..Default::default()
+        });
+        let chapter = Chapter::new("test", content.to_owned(), "part/ch.md", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert!(!result.contains("<svg"));
+        let name = std::fs::read_dir(dir.join("svgdx-assets"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .file_name();
+        let name = name.to_string_lossy();
+        assert_contains!(
+            result,
+            &format!(
+                r#"<img class="svgdx" src="../svgdx-assets/{name}" alt="Box" style="width: 45mm;"#
+            )
+        );
+        let svg = std::fs::read_to_string(dir.join("svgdx-assets").join(&*name)).unwrap();
+        assert_contains!(svg, r#"<rect width="20" height="5"/>"#);
+    }
+
+    #[test]
+    fn external_images() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-external-images-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("part/img")).unwrap();
+        std::fs::write(dir.join("part/img/small.png"), b"PNG").unwrap();
+        std::fs::write(dir.join("part/img/large.png"), [0; 100]).unwrap();
+        let content = r#"
+```svgdx
+<svg><image href="img/small.png" wh="10"/><image href="img/large.png" xy="20 0" wh="10"/></svg>
+```
+"#;
+        let mut renderer = Renderer::new(SvgdxConfig {
+            src_dir: dir.clone(),
+            external_images: ExternalImages::Inline,
+            inline_image_limit: 10,
+            ..Default::default()
+        });
+        let chapter = Chapter::new("test", content.to_owned(), "part/ch.md", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, r#"href="data:image/png;base64,UE5H""#);
+        let large = format!("{}.png", content_hash([0; 100]));
+        assert_contains!(result, &format!(r#"href="../svgdx-assets/{large}""#));
+        assert!(dir.join("svgdx-assets").join(large).exists());
+    }
+
+    #[test]
+    fn fence_src_file() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-fence-src-test");
+        std::fs::create_dir_all(dir.join("part/diagrams")).unwrap();
+        std::fs::write(
+            dir.join("part/diagrams/arch.xml"),
+            r#"<svg><rect wh="20 5"/></svg>"#,
+        )
+        .unwrap();
+
+        let content = r#"
+```svgdx-xml src="diagrams/arch.xml"
+```
+
+```svgdx src=missing.xml
+```
+
+```svgdx src="diagrams/arch.xml"
+<svg/>
+```
+"#;
+        let mut renderer = Renderer::default();
+        renderer.cfg.src_dir = dir.clone();
+        let chapter = Chapter::new("test", content.to_owned(), "part/ch.md", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, r#"<rect width="20" height="5"/>"#);
+        // The file's contents are shown as the source.
+        assert_contains!(result, r#"<svg><rect wh="20 5"/></svg>"#);
+        assert_contains!(
+            result,
+            &format!("Could not read {}", dir.join("part/missing.xml").display())
+        );
+        assert_contains!(result, "must be empty");
+    }
+
+    #[test]
+    fn render_code_in_html_block() {
+        let content = r#"
+<details>
+<summary>Diagram</summary>
+<pre><code class="language-svgdx-xml">&lt;svg&gt;
+  &lt;rect wh=&quot;20 5&quot;/&gt;
+&lt;/svg&gt;</code></pre>
+<pre><code class="language-rust">fn main() {}</code></pre>
+</details>
+"#;
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        assert_contains!(result, "<div class='svgdx-xml'>\n<svg ");
+        assert_contains!(result, r#"<rect width="20" height="5"/>"#);
+        assert_contains!(
+            result,
+            r#"</svg><pre><code class="language-xml">&lt;svg&gt;"#
+        );
+        assert_contains!(
+            result,
+            r#"<pre><code class="language-rust">fn main() {}</code></pre>"#
+        );
+    }
+
+    #[test]
+    fn deferred_output_substituted() {
+        let mut renderer = Renderer {
+            token_nonce: "abc".to_string(),
+            ..Default::default()
+        };
+        let first = renderer.defer(Deferred::Diagram(r#"<svg><rect wh="1"/></svg>"#.into()));
+        let info = FenceInfo::parse("xml-svgdx");
+        let second = renderer.defer(Deferred::Fence(info, "<svg><circle r=\"1\"/></svg>".into()));
+        let markdown = format!("{}\n\ntext <!--svgdx-output:abc:9-->\n{}", second, first);
+        let result = renderer.substitute_deferred(&markdown);
+        assert_contains!(
+            result,
+            "\n<div class='xml-svgdx'>\n<div>\n\n```xml\n<svg><circle"
+        );
+        assert_contains!(result, "text <!--svgdx-output:abc:9-->\n<svg ");
+        assert!(result.find("<circle").unwrap() < result.find("<rect").unwrap());
+        assert_eq!(renderer.block_count, 2);
+        assert!(renderer.deferred.is_empty());
+    }
+
+    #[test]
+    fn xml_source_fence_longer_than_content() {
+        assert_eq!(
+            xml_source("<a/>\n", None),
+            "<div>\n\n```xml\n<a/>\n```\n\n</div>\n"
+        );
+        assert_contains!(
+            xml_source("<!-- ```` -->", None),
+            "`````xml\n<!-- ```` -->\n`````\n"
+        );
+    }
+
+    #[test]
+    fn hidden_lines_rendered_not_shown() {
+        let content = r##"
+```svgdx-xml
+<svg>
+  <rect wh="20 5"/>
+  ~<circle r="3"/>
+</svg>
+```
+"##;
+        let mut renderer = Renderer::default();
+        renderer.cfg.hidden_line_marker = Some("~".to_string());
+        let chapter = Chapter::new("test", content.to_owned(), ".", Vec::new());
+        let result = codeblock_parser(&chapter, &mut renderer).unwrap();
+        assert_contains!(result, r#"<circle r="3"/>"#);
+        assert_contains!(result, "```xml hidelines=~\n<svg>\n");
+        assert_contains!(result, "\n  ~<circle r=\"3\"/>\n");
+    }
+
+    #[test]
+    fn manifest_hash_ignores_random_id() {
+        let mut renderer = Renderer::default();
+        renderer.cfg.manifest = Some(PathBuf::from("manifest.json"));
+        renderer.record_figure(r#"<svg id="svgdx-1234"><style>#svgdx-1234 {}</style></svg>"#);
+        renderer.record_figure(r#"<svg id="svgdx-5678"><style>#svgdx-5678 {}</style></svg>"#);
+        let hashes: Vec<_> = renderer.manifest.values().collect();
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    /// Processing time for a large chapter, mostly prose with a few diagrams.
+    /// Run with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore]
+    fn bench_large_chapter() {
+        let mut content = String::new();
+        for idx in 0..5000 {
+            content.push_str(&format!(
+                "## Section {idx}\n\nSome *text* with `code` and a [link](x.md).\n\n\
+                 | a | b |\n|---|---|\n| {idx} | x |\n\n<div>\n<b>{idx}</b>\n</div>\n\n"
+            ));
+            if idx % 500 == 0 {
+                content.push_str(&format!(
+                    "```svgdx\n<svg><rect wh=\"{idx} 5\"/></svg>\n```\n\n"
+                ));
+            }
+        }
+        let chapter = Chapter::new("bench", content, ".", Vec::new());
+        let iterations = 20;
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            codeblock_parser(&chapter, &mut Renderer::default()).unwrap();
+        }
+        println!(
+            "{} bytes: {:?} per iteration",
+            chapter.content.len(),
+            start.elapsed() / iterations
+        );
     }
 }
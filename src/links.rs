@@ -0,0 +1,84 @@
+//! Checking of references within diagrams.
+//!
+//! `href` (and `xlink:href`) attributes of links, images and `<use>` elements
+//! are checked to refer to an element of the diagram (for `#id` references)
+//! or a file in the book. Relative paths are resolved against the chapter's
+//! directory and absolute paths against the book's source directory; links to
+//! `.html` pages are also satisfied by the corresponding `.md` chapter. URLs
+//! with a scheme (e.g. `https:` or `data:`) aren't checked.
+
+use std::path::Path;
+
+/// Values of the `href` and `xlink:href` attributes in `svg`.
+fn hrefs(svg: &str) -> impl Iterator<Item = &str> {
+    svg.split(" href=\"")
+        .skip(1)
+        .chain(svg.split(" xlink:href=\"").skip(1))
+        .filter_map(|rest| rest.split_once('"').map(|(value, _)| value))
+}
+
+/// Whether `href` has a URL scheme, e.g. `https:` or `mailto:`.
+fn has_scheme(href: &str) -> bool {
+    href.starts_with("//")
+        || href.split_once(':').is_some_and(|(scheme, _)| {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        })
+}
+
+/// Whether the file referred to by `path` exists.
+fn file_exists(path: &Path) -> bool {
+    path.exists()
+        || (path.extension().is_some_and(|ext| ext == "html") && path.with_extension("md").exists())
+}
+
+/// References in `svg` which don't resolve, with relative paths resolved
+/// against `chapter_dir` and absolute paths against `src_dir`.
+pub(crate) fn dead_links(svg: &str, chapter_dir: &Path, src_dir: &Path) -> Vec<String> {
+    let mut dead = Vec::new();
+    for href in hrefs(svg) {
+        if has_scheme(href) || dead.iter().any(|d| d == href) {
+            continue;
+        }
+        let exists = match href.strip_prefix('#') {
+            Some(id) => svg.contains(&format!(" id=\"{}\"", id)),
+            None => {
+                let path = href.split(['#', '?']).next().unwrap_or_default();
+                match path.strip_prefix('/') {
+                    _ if path.is_empty() => true,
+                    Some(path) => file_exists(&src_dir.join(path)),
+                    None => file_exists(&chapter_dir.join(path)),
+                }
+            }
+        };
+        if !exists {
+            dead.push(href.to_string());
+        }
+    }
+    dead
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_dead_links() {
+        let dir = std::env::temp_dir().join("mdbook-svgdx-links-test");
+        std::fs::create_dir_all(dir.join("part/img")).unwrap();
+        std::fs::write(dir.join("part/img/a.png"), "").unwrap();
+        std::fs::write(dir.join("part/intro.md"), "").unwrap();
+        std::fs::write(dir.join("top.md"), "").unwrap();
+
+        let svg = r##"<svg><rect id="box"/><use href="#box"/><use xlink:href="#gone"/>
+<image href="img/a.png"/><image href="img/b.png"/>
+<a href="intro.html#start"><text/></a><a href="/top.html"><text/></a><a href="/part/x.md"/>
+<a href="https://example.com/missing"/><a href="mailto:a@example.com"/></svg>"##;
+        assert_eq!(
+            dead_links(svg, &dir.join("part"), &dir),
+            ["img/b.png", "/part/x.md", "#gone"]
+        );
+    }
+}
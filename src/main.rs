@@ -1,11 +1,28 @@
-use clap::{Arg, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use clap_complete::Shell;
 use mdbook::errors::Error;
 use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
 use semver::{Version, VersionReq};
+use std::path::Path;
 use std::process;
 use std::{env, io};
 
-use mdbook_svgdx::SvgdxProc;
+use mdbook_svgdx::doctor::{diagnose, Check};
+use mdbook_svgdx::inventory::book_inventory;
+use mdbook_svgdx::preview::render_preview;
+use mdbook_svgdx::selftest::self_test;
+use mdbook_svgdx::slides::render_slides;
+use mdbook_svgdx::worker::run_worker;
+use mdbook_svgdx::{BuildError, SvgdxProc};
+
+/// Exit status for errors without a more specific status.
+const EXIT_FAILURE: i32 = 1;
+/// Exit status when the `[preprocessor.svgdx]` configuration is invalid.
+const EXIT_CONFIG_ERROR: i32 = 3;
+/// Exit status when diagrams fail to render, unless `--error-exit-code` is given.
+const EXIT_RENDER_ERROR: i32 = 4;
+/// Exit status when a file can't be read or written.
+const EXIT_IO_ERROR: i32 = 5;
 
 fn make_app() -> Command {
     Command::new(env!("CARGO_PKG_NAME"))
@@ -15,28 +32,179 @@ fn make_app() -> Command {
             env!("CARGO_PKG_VERSION"),
             svgdx::VERSION
         ))
+        .long_version(format!(
+            "{}\nsvgdx {}\nbuilt against mdbook {}",
+            env!("CARGO_PKG_VERSION"),
+            svgdx::VERSION,
+            mdbook::MDBOOK_VERSION
+        ))
+        .disable_version_flag(true)
+        .arg(
+            Arg::new("version")
+                .short('V')
+                .long("version")
+                .action(ArgAction::SetTrue)
+                .help("Print version"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("With --version, also report the svgdx and mdbook versions in use"),
+        )
+        .arg(
+            Arg::new("timings")
+                .long("timings")
+                .action(ArgAction::SetTrue)
+                .help("Report the time spent in each phase of processing each chapter"),
+        )
+        .arg(
+            Arg::new("error-exit-code")
+                .long("error-exit-code")
+                .value_name("CODE")
+                .value_parser(value_parser!(u8).range(1..))
+                .help(format!(
+                    "Exit status when diagrams fail to render [default: {}]",
+                    EXIT_RENDER_ERROR
+                )),
+        )
         .subcommand(
             Command::new("supports")
                 .arg(Arg::new("renderer").required(true))
                 .about("Check whether a renderer is supported by this preprocessor"),
         )
+        .subcommand(
+            Command::new("slides")
+                .arg(Arg::new("chapter").required(true))
+                .about("Convert the svgdx diagrams in a chapter into an HTML slide deck"),
+        )
+        .subcommand(
+            Command::new("preview")
+                .arg(Arg::new("chapter").required(true))
+                .about("Print a chapter with rendered diagrams as a standalone HTML page"),
+        )
+        .subcommand(
+            Command::new("inventory")
+                .arg(
+                    Arg::new("dir")
+                        .default_value(".")
+                        .help("Root directory of the book"),
+                )
+                .about("List every svgdx block in the book as JSON"),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .arg(
+                    Arg::new("dir")
+                        .default_value(".")
+                        .help("Root directory of the book"),
+                )
+                .about("Check the book's svgdx setup, suggesting fixes for any problems"),
+        )
+        .subcommand(
+            Command::new("selftest")
+                .arg(
+                    Arg::new("dir")
+                        .default_value(".")
+                        .help("Root directory of the book"),
+                )
+                .about("Render a bundled corpus of test chapters with the book's configuration"),
+        )
+        .subcommand(
+            Command::new("completions")
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(value_parser!(Shell)),
+                )
+                .about("Print a shell completion script"),
+        )
+        .subcommand(Command::new("mangen").about("Print a man page in roff format"))
+        .subcommand(
+            Command::new("render-worker")
+                .arg(Arg::new("transform").long("transform"))
+                .hide(true)
+                .about("Render svgdx from stdin to stdout (used for isolation = \"process\")"),
+        )
 }
 
 fn main() {
     let matches = make_app().get_matches();
 
+    if matches.get_flag("version") {
+        if matches.get_flag("verbose") {
+            print!("{}", make_app().render_long_version());
+        } else {
+            print!("{}", make_app().render_version());
+        }
+        return;
+    }
+
     let preprocessor = SvgdxProc {};
 
     if let Some(sub_args) = matches.subcommand_matches("supports") {
         handle_supports(&preprocessor, sub_args);
-    } else if let Err(e) = handle_preprocessing(&preprocessor) {
-        eprintln!("{}", e);
-        process::exit(1);
+    } else if let Some(sub_args) = matches.subcommand_matches("render-worker") {
+        let transform = sub_args.get_one::<String>("transform");
+        if let Err(e) = run_worker(transform.map(String::as_str)) {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    } else if let Some(sub_args) = matches.subcommand_matches("slides") {
+        if let Err(e) = handle_slides(sub_args) {
+            fail(&e, &matches);
+        }
+    } else if let Some(sub_args) = matches.subcommand_matches("preview") {
+        if let Err(e) = handle_preview(sub_args) {
+            fail(&e, &matches);
+        }
+    } else if let Some(sub_args) = matches.subcommand_matches("inventory") {
+        if let Err(e) = handle_inventory(sub_args) {
+            fail(&e, &matches);
+        }
+    } else if let Some(sub_args) = matches.subcommand_matches("completions") {
+        handle_completions(sub_args);
+    } else if matches.subcommand_matches("mangen").is_some() {
+        print!("{}", man_page(&make_app()));
+    } else if let Some(sub_args) = matches.subcommand_matches("doctor") {
+        let dir = sub_args.get_one::<String>("dir").expect("Has default");
+        report(&diagnose(Path::new(dir)));
+    } else if let Some(sub_args) = matches.subcommand_matches("selftest") {
+        let dir = sub_args.get_one::<String>("dir").expect("Has default");
+        report(&self_test(Path::new(dir)));
+    } else if let Err(e) = handle_preprocessing(&preprocessor, matches.get_flag("timings")) {
+        fail(&e, &matches);
     }
 }
 
-fn handle_preprocessing(pre: &dyn Preprocessor) -> Result<(), Error> {
-    let (ctx, book) = CmdPreprocessor::parse_input(io::stdin())?;
+/// Report `e` and exit with a status for its kind of error.
+fn fail(e: &Error, matches: &ArgMatches) -> ! {
+    eprintln!("{}", e);
+    let code = match e.downcast_ref::<BuildError>() {
+        Some(BuildError::Config(_)) => EXIT_CONFIG_ERROR,
+        Some(BuildError::Render(_)) => matches
+            .get_one::<u8>("error-exit-code")
+            .map_or(EXIT_RENDER_ERROR, |&code| code.into()),
+        Some(BuildError::Io(_)) => EXIT_IO_ERROR,
+        None if e.chain().any(|cause| cause.is::<io::Error>()) => EXIT_IO_ERROR,
+        None => EXIT_FAILURE,
+    };
+    process::exit(code);
+}
+
+fn handle_preprocessing(pre: &dyn Preprocessor, timings: bool) -> Result<(), Error> {
+    let (mut ctx, book) = CmdPreprocessor::parse_input(io::stdin())?;
+    if timings {
+        ctx.config.set("preprocessor.svgdx.timings", true)?;
+    }
+
+    eprintln!(
+        "{} {} using svgdx {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        svgdx::VERSION
+    );
 
     let book_version = Version::parse(&ctx.mdbook_version)?;
     let version_req = VersionReq::parse(mdbook::MDBOOK_VERSION)?;
@@ -70,3 +238,111 @@ fn handle_supports(pre: &dyn Preprocessor, sub_args: &ArgMatches) -> ! {
         process::exit(1);
     }
 }
+
+fn handle_slides(sub_args: &ArgMatches) -> Result<(), Error> {
+    let chapter = sub_args
+        .get_one::<String>("chapter")
+        .expect("Required argument");
+    print!("{}", render_slides(Path::new(chapter))?);
+
+    Ok(())
+}
+
+fn handle_preview(sub_args: &ArgMatches) -> Result<(), Error> {
+    let chapter = sub_args
+        .get_one::<String>("chapter")
+        .expect("Required argument");
+    print!("{}", render_preview(Path::new(chapter))?);
+
+    Ok(())
+}
+
+fn handle_inventory(sub_args: &ArgMatches) -> Result<(), Error> {
+    let dir = sub_args.get_one::<String>("dir").expect("Has default");
+    println!("{}", book_inventory(Path::new(dir))?);
+
+    Ok(())
+}
+
+/// Print the outcome of `checks`, of the `doctor` or `selftest` subcommands.
+fn report(checks: &[Check]) -> ! {
+    for check in checks {
+        println!("{}", check);
+    }
+
+    // Signal whether all checks passed by exiting with 0 or 1.
+    if checks.iter().all(|check| check.passed) {
+        process::exit(0);
+    } else {
+        process::exit(1);
+    }
+}
+
+fn handle_completions(sub_args: &ArgMatches) {
+    let shell = sub_args
+        .get_one::<Shell>("shell")
+        .expect("Required argument");
+    clap_complete::generate(
+        *shell,
+        &mut make_app(),
+        env!("CARGO_PKG_NAME"),
+        &mut io::stdout(),
+    );
+}
+
+/// Escape `text` for roff.
+fn roff_escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// Man page for `app`, in roff format.
+fn man_page(app: &Command) -> String {
+    let name = app.get_name();
+    let about = app.get_about().map(|s| s.to_string()).unwrap_or_default();
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{} {}\"\n.SH NAME\n{} \\- {}\n",
+        roff_escape(&name.to_uppercase()),
+        roff_escape(name),
+        env!("CARGO_PKG_VERSION"),
+        roff_escape(name),
+        roff_escape(&about)
+    );
+    page.push_str(&format!(
+        ".SH SYNOPSIS\n\\fB{}\\fR [\\fIOPTIONS\\fR] [\\fICOMMAND\\fR]\n",
+        roff_escape(name)
+    ));
+    page.push_str(
+        ".SH DESCRIPTION\nWith no command, preprocess an mdbook book read as JSON from stdin.\n",
+    );
+    page.push_str(".SH OPTIONS\n");
+    for arg in app.get_arguments() {
+        let mut flags = Vec::new();
+        if let Some(short) = arg.get_short() {
+            flags.push(format!("\\fB\\-{}\\fR", short));
+        }
+        if let Some(long) = arg.get_long() {
+            flags.push(format!("\\fB\\-\\-{}\\fR", roff_escape(long)));
+        }
+        let help = arg.get_help().map(|s| s.to_string()).unwrap_or_default();
+        page.push_str(&format!(
+            ".TP\n{}\n{}\n",
+            flags.join(", "),
+            roff_escape(&help)
+        ));
+    }
+    page.push_str(".SH COMMANDS\n");
+    for sub in app.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let mut usage = format!("\\fB{}\\fR", roff_escape(sub.get_name()));
+        for arg in sub.get_positionals() {
+            let arg_name = roff_escape(arg.get_id().as_str());
+            if arg.is_required_set() {
+                usage.push_str(&format!(" \\fI{}\\fR", arg_name));
+            } else {
+                usage.push_str(&format!(" [\\fI{}\\fR]", arg_name));
+            }
+        }
+        let about = sub.get_about().map(|s| s.to_string()).unwrap_or_default();
+        page.push_str(&format!(".TP\n{}\n{}\n", usage, roff_escape(&about)));
+    }
+    page
+}
@@ -0,0 +1,75 @@
+//! Reporting of changed diagrams between builds.
+//!
+//! With the `manifest` option set, a hash of each rendered diagram is recorded
+//! in a JSON file. On the next build the new hashes are compared against it,
+//! and diagrams which were added, removed or changed are listed, to help
+//! reviewers of large documentation updates focus on the visual changes.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Hash of each rendered diagram, keyed by chapter path and block number.
+pub(crate) type Manifest = BTreeMap<String, String>;
+
+fn changes(previous: &Manifest, current: &Manifest) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (figure, hash) in current {
+        match previous.get(figure) {
+            None => changes.push(format!("added: {}", figure)),
+            Some(prev_hash) if prev_hash != hash => changes.push(format!("changed: {}", figure)),
+            _ => {}
+        }
+    }
+    for figure in previous.keys().filter(|f| !current.contains_key(*f)) {
+        changes.push(format!("removed: {}", figure));
+    }
+    changes
+}
+
+/// Report differences from the manifest at `path` (if any) to stderr, then
+/// replace it with the `current` manifest.
+pub(crate) fn update_manifest(path: &Path, current: &Manifest) -> io::Result<()> {
+    if let Ok(data) = fs::read_to_string(path) {
+        let previous: Manifest = serde_json::from_str(&data).unwrap_or_default();
+        let changes = changes(&previous, current);
+        if !changes.is_empty() {
+            eprintln!(
+                "svgdx: {} diagram(s) differ from the previous build:",
+                changes.len()
+            );
+            for change in changes {
+                eprintln!("  {}", change);
+            }
+        }
+    }
+    fs::write(path, serde_json::to_string_pretty(current)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_changes() {
+        let previous = Manifest::from([
+            ("intro.md#1".to_string(), "aaaa".to_string()),
+            ("intro.md#2".to_string(), "bbbb".to_string()),
+            ("old.md#1".to_string(), "cccc".to_string()),
+        ]);
+        let current = Manifest::from([
+            ("intro.md#1".to_string(), "aaaa".to_string()),
+            ("intro.md#2".to_string(), "dddd".to_string()),
+            ("new.md#1".to_string(), "cccc".to_string()),
+        ]);
+        assert_eq!(
+            changes(&previous, &current),
+            [
+                "changed: intro.md#2",
+                "added: new.md#1",
+                "removed: old.md#1"
+            ]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Book-wide colour palette enforcement.
+//!
+//! The `[preprocessor.svgdx.palette]` table maps colour names used in diagrams
+//! (e.g. via svgdx's `d-red` / `d-fill-red` classes, or `fill` and `stroke`
+//! attributes) to the colour values actually rendered, so diagrams follow a
+//! consistent brand palette without editing each source.
+
+use std::collections::HashMap;
+
+const PAINT_PROPERTIES: &[&str] = &["fill", "stroke", "stop-color", "color"];
+
+/// Values which aren't colours as far as the palette is concerned.
+fn is_neutral(value: &str) -> bool {
+    matches!(
+        value,
+        "none" | "transparent" | "currentColor" | "inherit" | "context-stroke" | "context-fill"
+    ) || value.starts_with("url(")
+}
+
+/// Colour values explicitly chosen in the rendered `svg`, in order of first use.
+///
+/// These are the values of colour class rules (e.g. `.d-red { stroke: red; }`)
+/// and of paint attributes on elements; theme defaults are not included.
+fn used_colours(svg: &str) -> Vec<&str> {
+    let mut colours = Vec::new();
+    for line in svg.lines().map(str::trim) {
+        if line.starts_with(".d-") {
+            for prop in PAINT_PROPERTIES {
+                if let Some(value) = css_value(line, prop) {
+                    colours.push(value);
+                }
+            }
+        }
+    }
+    for prop in PAINT_PROPERTIES {
+        let needle = format!(" {}=\"", prop);
+        for (idx, _) in svg.match_indices(&needle) {
+            let rest = &svg[idx + needle.len()..];
+            if let Some((value, _)) = rest.split_once('"') {
+                colours.push(value);
+            }
+        }
+    }
+    let mut seen = Vec::new();
+    for colour in colours {
+        if !is_neutral(colour) && !seen.contains(&colour) {
+            seen.push(colour);
+        }
+    }
+    seen
+}
+
+/// Value of the first `prop: value;` declaration in a CSS rule.
+fn css_value<'a>(rule: &'a str, prop: &str) -> Option<&'a str> {
+    let needle = format!(" {}: ", prop);
+    let start = rule.find(&needle)? + needle.len();
+    rule[start..].split([';', '}']).next().map(str::trim)
+}
+
+/// Colours used in `svg` which are neither palette names nor palette values.
+pub(crate) fn off_palette<'a>(svg: &'a str, palette: &HashMap<String, String>) -> Vec<&'a str> {
+    used_colours(svg)
+        .into_iter()
+        .filter(|colour| {
+            !palette.iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case(colour) || value.eq_ignore_ascii_case(colour)
+            })
+        })
+        .collect()
+}
+
+/// Replace colour names in paint declarations and attributes with palette values.
+pub(crate) fn remap(svg: &str, palette: &HashMap<String, String>) -> String {
+    let mut svg = svg.to_string();
+    for (name, value) in palette {
+        for prop in PAINT_PROPERTIES {
+            svg = svg
+                .replace(
+                    &format!(" {}: {};", prop, name),
+                    &format!(" {}: {};", prop, value),
+                )
+                .replace(
+                    &format!(" {}=\"{}\"", prop, name),
+                    &format!(" {}=\"{}\"", prop, value),
+                );
+        }
+    }
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG: &str = r##"<svg>
+  <style>
+      rect, circle, ellipse, polygon { stroke-width: 0.5; fill: white; stroke: black; }
+      .d-red { stroke: red; }
+      text.d-red, text.d-red * { fill: red; stroke: white; }
+      .d-fill-green { fill: green; }
+  </style>
+<rect class="d-red"/><rect class="d-fill-green" stroke="#123456"/><line stroke="none"/>
+</svg>"##;
+
+    #[test]
+    fn detect_off_palette_colours() {
+        let palette = HashMap::from([("red".to_string(), "#c0392b".to_string())]);
+        assert_eq!(off_palette(SVG, &palette), ["green", "#123456"]);
+    }
+
+    #[test]
+    fn remap_colours() {
+        let palette = HashMap::from([("red".to_string(), "#c0392b".to_string())]);
+        let svg = remap(SVG, &palette);
+        assert!(svg.contains(".d-red { stroke: #c0392b; }"));
+        assert!(svg.contains("text.d-red, text.d-red * { fill: #c0392b; stroke: white; }"));
+        assert!(svg.contains(r#"<rect class="d-red"/>"#));
+    }
+}
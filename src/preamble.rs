@@ -0,0 +1,46 @@
+//! Shared definitions included in every diagram.
+//!
+//! The `preamble` option names a file of svgdx elements (e.g. `<defs>`,
+//! `<specs>` and `<var>` elements) which is inserted at the start of each
+//! diagram's root element before it is transformed, so that shapes, styles
+//! and variables used throughout a book are defined once.
+
+use crate::svg::root_tag;
+
+/// `source` with `preamble` inserted as the first content of its root `<svg>`
+/// element, or before it if it has none (e.g. an `svgdx-part` fragment).
+pub(crate) fn with_preamble(source: &str, preamble: &str) -> String {
+    if !source.contains("<svg") {
+        return format!("{}{}", preamble, source);
+    }
+    let Some(tag) = root_tag(source) else {
+        return source.to_string();
+    };
+    let (open, rest) = source.split_at(tag.end);
+    match open.strip_suffix("/>") {
+        Some(start) => format!("{}>\n{}</svg>{}", start, preamble, rest),
+        None => format!("{}\n{}{}", open, preamble, rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preamble_inserted_in_root() {
+        let preamble = "<specs><rect id=\"box\" wh=\"10\"/></specs>\n";
+        assert_eq!(
+            with_preamble("<svg a=\"x>y\">\n<reuse href=\"#box\"/>\n</svg>\n", preamble),
+            "<svg a=\"x>y\">\n<specs><rect id=\"box\" wh=\"10\"/></specs>\n\n<reuse href=\"#box\"/>\n</svg>\n"
+        );
+        assert_eq!(
+            with_preamble("<svg/>", preamble),
+            "<svg>\n<specs><rect id=\"box\" wh=\"10\"/></specs>\n</svg>"
+        );
+        assert_eq!(
+            with_preamble("<rect wh=\"5\"/>\n", preamble),
+            "<specs><rect id=\"box\" wh=\"10\"/></specs>\n<rect wh=\"5\"/>\n"
+        );
+    }
+}
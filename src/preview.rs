@@ -0,0 +1,124 @@
+//! Standalone HTML preview of a single chapter.
+//!
+//! The chapter is processed as in a book build, using the configuration from
+//! the `book.toml` of the enclosing book (if any), and rendered to HTML with
+//! mdbook's markdown renderer, so diagram layout can be checked quickly.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use mdbook::book::Chapter;
+use mdbook::errors::Error;
+use mdbook::Config;
+
+use crate::config::{Output, PerRenderer, SvgdxConfig};
+use crate::encoding::read_source;
+use crate::figures::Figures;
+use crate::{codeblock_parser, escape_html, Renderer};
+
+/// Directory containing the `book.toml` of the book enclosing `path`.
+fn book_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.join("book.toml").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// The configuration of the book enclosing the chapter at `path` (which
+/// must be canonical), or the defaults if there is none.
+fn chapter_config(path: &Path) -> Result<SvgdxConfig, Error> {
+    Ok(match book_root(path) {
+        Some(root) => {
+            let config = Config::from_disk(root.join("book.toml"))?;
+            SvgdxConfig::from_config(&config, &root, "html")?
+        }
+        None => SvgdxConfig {
+            renderer: "html".to_string(),
+            src_dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            ..Default::default()
+        },
+    })
+}
+
+/// The chapter at `path`, and a renderer for it as in a build of the
+/// enclosing book, but with diagrams always inline.
+pub(crate) fn chapter_renderer(path: &Path) -> Result<(Chapter, Renderer), Error> {
+    let path = path.canonicalize()?;
+    let content = read_source(&path)?;
+    let mut cfg = chapter_config(&path)?;
+    // The page is self-contained, so diagrams are always inline.
+    cfg.output = PerRenderer::All(Output::Inline);
+    let source_path = path
+        .strip_prefix(&cfg.src_dir)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.file_name().map(PathBuf::from).unwrap_or_default());
+    let title = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let chapter = Chapter::new(&title, content, source_path, Vec::new());
+    let mut renderer = Renderer::new(cfg);
+    renderer.figures = Arc::new(Figures::collect(
+        &[&chapter],
+        renderer.cfg.figure_numbers,
+        renderer.cfg.profile,
+    ));
+    Ok((chapter, renderer))
+}
+
+/// Render the chapter at `path` as a self-contained HTML page.
+pub fn render_preview(path: &Path) -> Result<String, Error> {
+    let (chapter, mut renderer) = chapter_renderer(path)?;
+    let markdown = codeblock_parser(&chapter, &mut renderer)?;
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{}</title>
+<style>
+body {{ max-width: 50em; margin: 0 auto; padding: 1em; font-family: sans-serif; }}
+pre {{ overflow-x: auto; background: #f6f7f6; padding: 0.5em; }}
+</style>
+</head>
+<body>
+{}</body>
+</html>
+"#,
+        escape_html(&chapter.name),
+        mdbook::utils::render_markdown(&markdown, false)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn preview_uses_book_config() {
+        let root = std::env::temp_dir().join("mdbook-svgdx-preview-test");
+        fs::create_dir_all(root.join("src/part")).unwrap();
+        fs::write(
+            root.join("book.toml"),
+            "[preprocessor.svgdx]\nversion-comment = true\n",
+        )
+        .unwrap();
+        let chapter = root.join("src/part/ch1.md");
+        fs::write(
+            &chapter,
+            "# Title\n\n```svgdx\n<svg><rect wh=\"20 5\"/></svg>\n```\n",
+        )
+        .unwrap();
+
+        let html = render_preview(&chapter).unwrap();
+        assert_contains!(html, "<title>ch1</title>");
+        assert_contains!(html, "<h1>Title</h1>");
+        assert_contains!(html, "<div class='svgdx'>\n<svg ");
+        assert_contains!(html, "<!-- svgdx ");
+    }
+}
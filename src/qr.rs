@@ -0,0 +1,570 @@
+//! QR codes in diagrams.
+//!
+//! A `<qr data="..."/>` element is drawn as a QR code encoding `data`, e.g. so
+//! printed diagrams can link to live dashboards. It is positioned and sized
+//! like a `<rect>`, which it becomes before rendering with svgdx:
+//!
+//! ```xml
+//! <qr id="dash" xy="^:h 5" wh="20" data="https://status.example.com"/>
+//! ```
+//!
+//! The data is stored (hex-encoded) in a marker attribute on the `<rect>`, and
+//! the code's modules are drawn within the rendered rect's bounds afterwards.
+//! Codes use byte mode with error correction level M, in versions 1 to 10,
+//! limiting data to 213 bytes.
+
+use crate::svg::root_tag;
+
+const MARKER: &str = "data-svgdx-qr";
+
+/// Error correction blocks for level M, versions 1 to 10: codewords per
+/// block, then the number of blocks and data codewords in each of two groups.
+const LEVEL_M: [(usize, usize, usize, usize, usize); 10] = [
+    (10, 1, 16, 0, 0),
+    (16, 1, 28, 0, 0),
+    (26, 1, 44, 0, 0),
+    (18, 2, 32, 0, 0),
+    (24, 2, 43, 0, 0),
+    (16, 4, 27, 0, 0),
+    (18, 4, 31, 0, 0),
+    (22, 2, 38, 2, 39),
+    (22, 3, 36, 2, 37),
+    (26, 4, 43, 1, 44),
+];
+
+/// Alignment pattern centre coordinates for versions 1 to 10.
+const ALIGNMENT: [&[usize]; 10] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+/// Width of the light border around the code, in modules.
+const QUIET_ZONE: usize = 4;
+
+/// Multiplication in GF(2^8) with the QR code polynomial.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1d;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// Reed-Solomon error correction codewords for `data`.
+fn rs_remainder(data: &[u8], degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_mul(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_mul(root, 2);
+    }
+
+    let mut remainder = vec![0; degree];
+    for &byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (r, &d) in remainder.iter_mut().zip(&divisor) {
+            *r ^= gf_mul(d, factor);
+        }
+    }
+    remainder
+}
+
+/// Data and error correction codewords for `data`, interleaved in the
+/// order they are placed in the symbol, with the version used.
+fn codewords(data: &[u8]) -> Result<(usize, Vec<u8>), String> {
+    let (version, &(ec_len, blocks1, len1, blocks2, len2)) = LEVEL_M
+        .iter()
+        .enumerate()
+        .map(|(idx, blocks)| (idx + 1, blocks))
+        .find(|(version, (_, b1, l1, b2, l2))| {
+            let count_bits = if *version < 10 { 8 } else { 16 };
+            4 + count_bits + data.len() * 8 <= (b1 * l1 + b2 * l2) * 8
+        })
+        .ok_or_else(|| format!("QR code data too long ({} bytes, maximum 213)", data.len()))?;
+    let capacity = blocks1 * len1 + blocks2 * len2;
+
+    let mut bits = Vec::with_capacity(capacity * 8);
+    let mut push = |value: usize, len: usize| {
+        bits.extend((0..len).rev().map(|i| (value >> i) & 1 != 0));
+    };
+    push(0b0100, 4);
+    push(data.len(), if version < 10 { 8 } else { 16 });
+    for &byte in data {
+        push(byte.into(), 8);
+    }
+    let terminator = (capacity * 8 - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+    let mut bytes: Vec<u8> = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, &bit| (acc << 1) | u8::from(bit)))
+        .collect();
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if bytes.len() == capacity {
+            break;
+        }
+        bytes.push(pad);
+    }
+
+    let mut rest = bytes.as_slice();
+    let mut data_blocks = Vec::new();
+    for len in std::iter::repeat_n(len1, blocks1).chain(std::iter::repeat_n(len2, blocks2)) {
+        let (block, remaining) = rest.split_at(len);
+        data_blocks.push(block);
+        rest = remaining;
+    }
+    let ec_blocks: Vec<_> = data_blocks
+        .iter()
+        .map(|block| rs_remainder(block, ec_len))
+        .collect();
+
+    let mut interleaved = Vec::new();
+    for i in 0..len1.max(len2) {
+        interleaved.extend(data_blocks.iter().filter_map(|block| block.get(i)));
+    }
+    for i in 0..ec_len {
+        interleaved.extend(ec_blocks.iter().map(|block| block[i]));
+    }
+    Ok((version, interleaved))
+}
+
+/// Whether the module at column `x` and row `y` is inverted by `mask`.
+fn masked(mask: u8, x: usize, y: usize) -> bool {
+    match mask {
+        0 => (x + y).is_multiple_of(2),
+        1 => y.is_multiple_of(2),
+        2 => x.is_multiple_of(3),
+        3 => (x + y).is_multiple_of(3),
+        4 => (x / 3 + y / 2).is_multiple_of(2),
+        5 => x * y % 2 + x * y % 3 == 0,
+        6 => (x * y % 2 + x * y % 3).is_multiple_of(2),
+        _ => ((x + y) % 2 + x * y % 3).is_multiple_of(2),
+    }
+}
+
+/// The 15 format information bits for level M with the given `mask`.
+fn format_bits(mask: u8) -> u32 {
+    // Level M is indicated by 0b00.
+    let data = u32::from(mask);
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    ((data << 10) | rem) ^ 0x5412
+}
+
+/// The 18 version information bits, used from version 7.
+fn version_bits(version: usize) -> u32 {
+    let version = version as u32;
+    let mut rem = version;
+    for _ in 0..12 {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+    }
+    (version << 12) | rem
+}
+
+/// A QR code symbol, as rows of dark (`true`) and light modules.
+pub(crate) struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<Self, String> {
+        let (version, codewords) = codewords(data)?;
+        let size = version * 4 + 17;
+        let mut qr = Self {
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&codewords);
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask);
+                qr.draw_format_bits(mask);
+                let penalty = qr.penalty();
+                qr.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or_default();
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+        Ok(qr)
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4_isize..=4 {
+                for dx in -4_isize..=4 {
+                    let (x, y) = (cx as isize + dx, cy as isize + dy);
+                    if (0..size as isize).contains(&x) && (0..size as isize).contains(&y) {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, dist != 2 && dist != 4);
+                    }
+                }
+            }
+        }
+        let centres = ALIGNMENT[version - 1];
+        let last = centres.len().saturating_sub(1);
+        for (i, &cx) in centres.iter().enumerate() {
+            for (j, &cy) in centres.iter().enumerate() {
+                // Skip those overlapping the finder patterns.
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in 0_usize..5 {
+                    for dx in 0_usize..5 {
+                        let dist = dx.abs_diff(2).max(dy.abs_diff(2));
+                        self.set_function(cx + dx - 2, cy + dy - 2, dist != 1);
+                    }
+                }
+            }
+        }
+        // Reserve the format information modules; drawn once the mask is chosen.
+        self.draw_format_bits(0);
+        if version >= 7 {
+            let bits = version_bits(version);
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let size = self.size;
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Place codewords in two-module wide columns, zig-zagging upwards and
+    /// downwards from the bottom right corner.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut bit = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                // Skip the vertical timing pattern.
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vert in 0..size {
+                let y = if upward { size - 1 - vert } else { vert };
+                for x in [right, right - 1] {
+                    if !self.function[y * size + x] && bit < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[bit / 8] >> (7 - bit % 8)) & 1 != 0;
+                        bit += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if !self.function[y * self.size + x] && masked(mask, x, y) {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    /// Penalty score of the symbol, used to choose the mask pattern making
+    /// the symbol easiest to scan.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let finder_like = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+        let mut penalty = 0;
+        let lines = (0..size)
+            .map(|y| (0..size).map(|x| self.get(x, y)).collect::<Vec<_>>())
+            .chain((0..size).map(|x| (0..size).map(|y| self.get(x, y)).collect()));
+        for line in lines {
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            for window in line.windows(finder_like.len()) {
+                if window == finder_like || window.iter().rev().eq(finder_like.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.get(x, y);
+                if self.get(x + 1, y) == dark
+                    && self.get(x, y + 1) == dark
+                    && self.get(x + 1, y + 1) == dark
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        penalty + (dark * 100 / self.modules.len()).abs_diff(50) / 5 * 10
+    }
+
+    /// SVG path data drawing the dark modules as unit squares, with the
+    /// origin at the top left of the symbol.
+    pub fn path_data(&self) -> String {
+        let mut d = String::new();
+        for y in 0..self.size {
+            let mut x = 0;
+            while x < self.size {
+                let start = x;
+                while x < self.size && self.get(x, y) {
+                    x += 1;
+                }
+                if x > start {
+                    d.push_str(&format!("M{} {}h{}v1h-{}z", start, y, x - start, x - start));
+                }
+                x += 1;
+            }
+        }
+        d
+    }
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Replace XML character references in an attribute value.
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Byte range of the value of attribute `name` within the start tag `tag`.
+fn attr_range(tag: &str, name: &str) -> Option<std::ops::Range<usize>> {
+    let needle = format!(" {}=\"", name);
+    let start = tag.find(&needle)? + needle.len();
+    let end = start + tag[start..].find('"')?;
+    Some(start..end)
+}
+
+/// Convert `<qr>` elements in svgdx `source` to marked `<rect>` elements.
+pub(crate) fn expand_qr(source: &str) -> Result<String, String> {
+    if !source.contains("<qr") {
+        return Ok(source.to_string());
+    }
+    let mut expanded = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("<qr") {
+        let after = &rest[start + 3..];
+        if !after.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>') {
+            expanded.push_str(&rest[..start + 3]);
+            rest = after;
+            continue;
+        }
+        let tag_len = root_tag(&format!("<svg{}", after))
+            .ok_or("Unterminated <qr> element")?
+            .end
+            - 4;
+        let attrs = &after[..tag_len];
+        let range = attr_range(attrs, "data").ok_or("<qr> element without a data attribute")?;
+        let data = unescape_xml(&attrs[range.clone()]);
+        QrCode::encode(data.as_bytes())?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&format!(
+            "<rect{} {}=\"{}\"{}",
+            &attrs[..range.start - " data=\"".len()],
+            MARKER,
+            hex_encode(data.as_bytes()),
+            &attrs[range.end + 1..]
+        ));
+        rest = &after[tag_len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded.replace("</qr>", "</rect>"))
+}
+
+fn number(value: f32) -> String {
+    let formatted = format!("{:.3}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Draw a QR code within each marked `<rect>` of the rendered `svg`.
+pub(crate) fn draw_qr_codes(svg: String) -> String {
+    if !svg.contains(MARKER) {
+        return svg;
+    }
+    let mut drawn = String::with_capacity(svg.len());
+    let mut rest = svg.as_str();
+    while let Some(start) = rest.find("<rect ") {
+        let Some(tag) = root_tag(&format!("<svg{}", &rest[start + 5..])) else {
+            break;
+        };
+        let end = start + 5 + tag.end - 4;
+        let rect = &rest[start..end];
+        drawn.push_str(&rest[..start]);
+        rest = &rest[end..];
+        let Some(marker) = attr_range(rect, MARKER) else {
+            drawn.push_str(rect);
+            continue;
+        };
+        let Some(qr) =
+            hex_decode(&rect[marker.clone()]).and_then(|data| QrCode::encode(&data).ok())
+        else {
+            drawn.push_str(rect);
+            continue;
+        };
+        let attr = |name| {
+            attr_range(rect, name)
+                .and_then(|range| rect[range].parse::<f32>().ok())
+                .unwrap_or_default()
+        };
+        let (x, y, w, h) = (attr("x"), attr("y"), attr("width"), attr("height"));
+        let modules = (qr.size + 2 * QUIET_ZONE) as f32;
+        let scale = w.min(h) / modules;
+        let left = x + (w - scale * modules) / 2. + scale * QUIET_ZONE as f32;
+        let top = y + (h - scale * modules) / 2. + scale * QUIET_ZONE as f32;
+        drawn.push_str(&rect[..marker.start - MARKER.len() - 3]);
+        drawn.push_str(&rect[marker.end + 1..]);
+        drawn.push_str(&format!(
+            "<path class=\"svgdx-qr\" transform=\"translate({} {}) scale({})\" \
+             style=\"fill: black; stroke: none;\" d=\"{}\"/>",
+            number(left),
+            number(top),
+            number(scale),
+            qr.path_data()
+        ));
+    }
+    drawn.push_str(rest);
+    drawn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reed_solomon_reference() {
+        // "HELLO WORLD" in version 1-M, from the thonky.com QR code tutorial.
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            rs_remainder(&data, 10),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn format_and_version_reference() {
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(format_bits(5), 0b100000011001110);
+        assert_eq!(format_bits(7), 0b100101010100000);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+    }
+
+    #[test]
+    fn version_by_length() {
+        assert_eq!(QrCode::encode(b"https://example.com").unwrap().size, 25);
+        assert_eq!(QrCode::encode(&[b'a'; 213]).unwrap().size, 57);
+        assert!(QrCode::encode(&[b'a'; 214]).is_err());
+    }
+
+    #[test]
+    fn qr_element_roundtrip() {
+        let source = r#"<svg><qr id="q" wh="29" data="a&amp;b"/></svg>"#;
+        let expanded = expand_qr(source).unwrap();
+        assert_eq!(
+            expanded,
+            r#"<svg><rect id="q" wh="29" data-svgdx-qr="612662"/></svg>"#
+        );
+        assert!(expand_qr("<svg><qr wh=\"1\"/></svg>").is_err());
+
+        let svg = draw_qr_codes(
+            r#"<svg><rect id="q" x="0" y="0" width="29" height="29" data-svgdx-qr="612662"/></svg>"#
+                .to_string(),
+        );
+        assert!(svg.starts_with(
+            r#"<svg><rect id="q" x="0" y="0" width="29" height="29"/><path class="svgdx-qr" transform="translate(4 4) scale(1)" "#
+        ));
+        // Top row of the top left finder pattern.
+        assert!(svg.contains(r#"d="M0 0h7v1h-7z"#));
+    }
+}
@@ -0,0 +1,201 @@
+//! A bundled corpus of representative chapters, rendered by the `selftest`
+//! subcommand to check an installation before a large build.
+//!
+//! Each chapter exercises markdown which has caused problems before (e.g.
+//! CRLF line endings, unterminated fences and diagrams within lists), and is
+//! processed as a book chapter would be, with the book's options and backend.
+//! Diagrams are written to a temporary directory rather than the book's, and
+//! the cache and manifest aren't used.
+
+use std::path::Path;
+use std::time::Instant;
+
+use mdbook::book::Chapter;
+use mdbook::Config;
+
+use crate::config::SvgdxConfig;
+use crate::doctor::Check;
+use crate::process_chapters;
+
+/// Shown in place of a diagram which fails to render.
+const ERROR_STYLE: &str = "border: 5px double red;";
+
+/// A chapter of the corpus, and a check of its processed content.
+struct Case {
+    name: &'static str,
+    markdown: String,
+    expect: fn(&str) -> Result<(), String>,
+}
+
+/// Number of diagrams (inline or as images) in `content`.
+fn diagrams(content: &str) -> usize {
+    content.matches("<svg ").count() + content.matches("<img ").count()
+}
+
+/// Check `content` shows a diagram and no errors.
+fn rendered(content: &str) -> Result<(), String> {
+    if content.contains(ERROR_STYLE) {
+        return Err("an error was shown".to_string());
+    }
+    if diagrams(content) == 0 {
+        return Err("no diagram was rendered".to_string());
+    }
+    Ok(())
+}
+
+/// Check the non-blank `lines` all start with `prefix`, so the container
+/// (e.g. a list item) they are within isn't broken.
+fn prefixed<'a>(mut lines: impl Iterator<Item = &'a str>, prefix: &str) -> Result<(), String> {
+    match lines.find(|line| !line.trim().is_empty() && !line.starts_with(prefix)) {
+        Some(line) => Err(format!("line '{}' isn't prefixed by '{}'", line, prefix)),
+        None => Ok(()),
+    }
+}
+
+fn corpus() -> Vec<Case> {
+    let huge = (0..2000)
+        .map(|idx| {
+            format!(
+                "<rect xy=\"{} {}\" wh=\"2\"/>\n",
+                idx % 50 * 3,
+                idx / 50 * 3
+            )
+        })
+        .collect::<String>();
+    vec![
+        Case {
+            name: "crlf",
+            markdown: "# CRLF\r\n\r\n```svgdx\r\n<svg>\r\n<rect wh=\"20 5\" text=\"a\"/>\r\n</svg>\r\n```\r\n"
+                .to_string(),
+            expect: |content| {
+                rendered(content)?;
+                if content.contains('\r') {
+                    return Err("carriage returns remain".to_string());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "bom",
+            markdown: "\u{feff}```svgdx\n<svg><rect wh=\"20 5\"/></svg>\n```\n".to_string(),
+            expect: rendered,
+        },
+        Case {
+            name: "unterminated-fence",
+            markdown: "Intro\n\n```svgdx\n<svg><rect wh=\"20 5\"/></svg>\n".to_string(),
+            expect: |content| {
+                rendered(content)?;
+                if !content.starts_with("Intro") {
+                    return Err("text before the block was changed".to_string());
+                }
+                Ok(())
+            },
+        },
+        Case {
+            name: "nested-list",
+            markdown: "- First\n\n  ```svgdx\n  <svg><rect wh=\"20 5\"/></svg>\n  ```\n\n- Second\n"
+                .to_string(),
+            expect: |content| {
+                rendered(content)?;
+                let item = content
+                    .lines()
+                    .skip_while(|line| *line != "- First")
+                    .skip(1)
+                    .take_while(|line| *line != "- Second");
+                prefixed(item, "  ")
+            },
+        },
+        Case {
+            name: "blockquote",
+            markdown: "> Quote\n>\n> ```svgdx\n> <svg><rect wh=\"20 5\"/></svg>\n> ```\n"
+                .to_string(),
+            expect: |content| {
+                rendered(content)?;
+                prefixed(content.lines(), ">")
+            },
+        },
+        Case {
+            name: "huge-diagram",
+            markdown: format!("```svgdx\n<svg>\n{}</svg>\n```\n", huge),
+            expect: rendered,
+        },
+        Case {
+            name: "invalid-diagram",
+            markdown: "```svgdx\n<svg><rect xy=\"#missing\"/></svg>\n```\n".to_string(),
+            expect: |content| match (diagrams(content), content.contains(ERROR_STYLE)) {
+                (0, true) => Ok(()),
+                _ => Err("the error wasn't shown in place of the diagram".to_string()),
+            },
+        },
+    ]
+}
+
+/// Render the corpus with the configuration of the book at `root` (or the
+/// default configuration, if it has no `book.toml`), checking each chapter.
+pub fn self_test(root: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let book_toml = root.join("book.toml");
+    let config = if book_toml.is_file() {
+        Config::from_disk(&book_toml)
+    } else {
+        Ok(Config::default())
+    };
+    let cfg = match config.and_then(|config| SvgdxConfig::from_config(&config, root, "html")) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            checks.push(Check::fail(
+                "configuration",
+                format!("{:#}", e),
+                "Correct the [preprocessor.svgdx] options in book.toml",
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::pass(
+        "configuration",
+        if book_toml.is_file() {
+            book_toml.display().to_string()
+        } else {
+            "defaults (no book.toml)".to_string()
+        },
+    ));
+    let cfg = SvgdxConfig {
+        src_dir: std::env::temp_dir().join("mdbook-svgdx-selftest"),
+        cache_dir: None,
+        manifest: None,
+        ..cfg
+    };
+    for case in corpus() {
+        let chapter = Chapter::new(case.name, case.markdown, "selftest.md", Vec::new());
+        let start = Instant::now();
+        let (mut contents, _) = process_chapters(&[&chapter], cfg.clone());
+        let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+        let result = match contents.pop().flatten() {
+            Some(content) => (case.expect)(&content),
+            None => Err("the chapter couldn't be processed".to_string()),
+        };
+        checks.push(match result {
+            Ok(()) => Check::pass(case.name, format!("passed in {:.1} ms", elapsed)),
+            Err(e) => Check::fail(
+                case.name,
+                e,
+                "Check the [preprocessor.svgdx] options, or report this as a bug",
+            ),
+        });
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_passes() {
+        let checks = self_test(Path::new("no-such-book"));
+        assert_eq!(checks.len(), corpus().len() + 1);
+        for check in &checks {
+            assert!(check.passed, "{}", check);
+        }
+    }
+}
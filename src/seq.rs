@@ -0,0 +1,186 @@
+//! Sequence diagram shorthand, expanded to svgdx XML before rendering.
+//!
+//! `svgdx-seq` blocks contain one message per line, between participants
+//! which are placed left to right in order of first appearance:
+//!
+//! ```text
+//! participant Client
+//! Client -> Server: request
+//! Server -> Server: validate
+//! Server --> Client: response
+//! ```
+//!
+//! `->` is a solid arrow and `-->` a dashed one (e.g. for replies); the label
+//! after `:` is optional. Blank lines and lines starting with `#` are ignored.
+
+use crate::escape_html;
+
+const BOX_WIDTH: f32 = 30.;
+const BOX_HEIGHT: f32 = 8.;
+const ROW_HEIGHT: f32 = 7.;
+
+struct Message {
+    from: usize,
+    to: usize,
+    dashed: bool,
+    label: String,
+}
+
+/// A `from -> to: label` line, shared with the graph shorthand.
+pub(crate) struct Arrow<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    /// `-->` rather than `->`.
+    pub dashed: bool,
+    pub label: &'a str,
+}
+
+/// Parse an arrow `line`, returning `None` if it contains no arrow.
+pub(crate) fn parse_arrow(line: &str) -> Option<Result<Arrow<'_>, String>> {
+    let (arrow, dashed) = match line.find("-->") {
+        Some(pos) => (pos..pos + 3, true),
+        None => {
+            let pos = line.find("->")?;
+            (pos..pos + 2, false)
+        }
+    };
+    let from = line[..arrow.start].trim();
+    let rest = &line[arrow.end..];
+    let (to, label) = rest.split_once(':').unwrap_or((rest, ""));
+    let to = to.trim();
+    if from.is_empty() || to.is_empty() {
+        return Some(Err(format!("missing name in '{}'", line)));
+    }
+    Some(Ok(Arrow {
+        from,
+        to,
+        dashed,
+        label: label.trim(),
+    }))
+}
+
+/// Index of the named entry in `names`, adding it if not already present.
+pub(crate) fn name_index(names: &mut Vec<String>, name: &str) -> usize {
+    names.iter().position(|n| n == name).unwrap_or_else(|| {
+        names.push(name.to_string());
+        names.len() - 1
+    })
+}
+
+/// Expand sequence diagram shorthand to svgdx XML.
+pub(crate) fn expand_seq(source: &str) -> Result<String, String> {
+    let mut participants = Vec::new();
+    let mut messages = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("participant ") {
+            name_index(&mut participants, name.trim());
+            continue;
+        }
+        let arrow = match parse_arrow(line) {
+            Some(Ok(arrow)) => arrow,
+            Some(Err(e)) => return Err(format!("svgdx-seq line {}: {}", idx + 1, e)),
+            None => {
+                return Err(format!(
+                    "svgdx-seq line {}: expected 'from -> to: message', found '{}'",
+                    idx + 1,
+                    line
+                ))
+            }
+        };
+        messages.push(Message {
+            from: name_index(&mut participants, arrow.from),
+            to: name_index(&mut participants, arrow.to),
+            dashed: arrow.dashed,
+            label: arrow.label.to_string(),
+        });
+    }
+
+    // Leave room for the longest label between adjacent lifelines.
+    let longest = messages.iter().map(|m| m.label.chars().count()).max();
+    let spacing = (longest.unwrap_or_default() as f32 * 1.8 + 10.).max(BOX_WIDTH + 10.);
+    let centre = |p: usize| p as f32 * spacing + BOX_WIDTH / 2.;
+
+    let mut rows = Vec::new();
+    let mut y = BOX_HEIGHT;
+    for msg in &messages {
+        y += ROW_HEIGHT;
+        let class = if msg.dashed {
+            "d-arrow d-dash"
+        } else {
+            "d-arrow"
+        };
+        let label = escape_html(&msg.label);
+        let (x1, x2) = (centre(msg.from), centre(msg.to));
+        if msg.from == msg.to {
+            rows.push(format!(
+                r#"  <polyline points="{x1} {y}, {} {y}, {} {}, {x1} {}" class="{class}" text="{label}" text-loc="r"/>"#,
+                x1 + 8.,
+                x1 + 8.,
+                y + 3.,
+                y + 3.
+            ));
+            y += 3.;
+        } else {
+            rows.push(format!(
+                r#"  <line x1="{x1}" y1="{y}" x2="{x2}" y2="{y}" class="{class}" text="{label}" text-loc="t"/>"#
+            ));
+        }
+    }
+    let bottom = y + ROW_HEIGHT;
+
+    let mut xml = String::from("<svg>\n");
+    for (idx, name) in participants.iter().enumerate() {
+        let x = centre(idx);
+        xml.push_str(&format!(
+            r#"  <rect x="{}" y="0" wh="{BOX_WIDTH} {BOX_HEIGHT}" text="{}"/>"#,
+            x - BOX_WIDTH / 2.,
+            escape_html(name)
+        ));
+        xml.push('\n');
+        xml.push_str(&format!(
+            r#"  <line x1="{x}" y1="{BOX_HEIGHT}" x2="{x}" y2="{bottom}" class="d-dot"/>"#
+        ));
+        xml.push('\n');
+    }
+    for row in rows {
+        xml.push_str(&row);
+        xml.push('\n');
+    }
+    xml.push_str("</svg>\n");
+    Ok(xml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::assert_contains;
+
+    #[test]
+    fn expand_messages() {
+        let xml = expand_seq(
+            "# comment\nparticipant Server\nClient -> Server: GET\n\nServer -> Server\nServer --> Client: 200 & OK\n",
+        )
+        .unwrap();
+        assert!(xml.find(r#"text="Server""#).unwrap() < xml.find(r#"text="Client""#).unwrap());
+        assert_contains!(
+            xml,
+            r#"<line x1="55" y1="15" x2="15" y2="15" class="d-arrow" text="GET" text-loc="t"/>"#
+        );
+        assert_contains!(xml, r#"<polyline points="15 22, 23 22, 23 25, 15 25""#);
+        assert_contains!(xml, r#"class="d-arrow d-dash" text="200 &amp; OK""#);
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        assert_eq!(
+            expand_seq("Client to Server").unwrap_err(),
+            "svgdx-seq line 1: expected 'from -> to: message', found 'Client to Server'"
+        );
+        assert!(expand_seq(" -> Server").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! Conversion of a single chapter's svgdx diagrams into a standalone HTML slide deck.
+//!
+//! The chapter is processed as in a build of the enclosing book, as for
+//! `preview`, so each diagram has the captions, themes and other options of
+//! its in-book figure. Each rendered diagram becomes one slide, titled with
+//! the text of the most recent heading preceding it; the chapter's prose and
+//! other content is left out.
+
+use std::path::Path;
+
+use mdbook::errors::Error;
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+use crate::preview::chapter_renderer;
+use crate::{codeblock_parser, escape_html, line_prefix};
+
+struct Slide {
+    title: String,
+    html: String,
+}
+
+/// Number of the wrapper elements opened in `html` but not closed.
+fn open_elements(html: &str) -> usize {
+    let (mut opened, mut closed) = (0, 0);
+    for tag in ["div", "figure", "details", "p"] {
+        opened += html.matches(&format!("<{}>", tag)).count();
+        opened += html.matches(&format!("<{} ", tag)).count();
+        closed += html.matches(&format!("</{}>", tag)).count();
+    }
+    opened.saturating_sub(closed)
+}
+
+/// `text` with the first `indent` characters of container markers (e.g. of a
+/// list item or blockquote) removed from each line after the first.
+fn dedent(text: &str, indent: usize) -> String {
+    text.split_inclusive('\n')
+        .enumerate()
+        .map(|(idx, line)| {
+            if idx == 0 {
+                return line;
+            }
+            let marker_len = line
+                .char_indices()
+                .take(indent)
+                .take_while(|(_, c)| *c == ' ' || *c == '>')
+                .map(|(i, c)| i + c.len_utf8())
+                .last()
+                .unwrap_or_default();
+            &line[marker_len..]
+        })
+        .collect()
+}
+
+/// The diagrams in processed chapter `markdown`, each with the markdown
+/// (e.g. of a source listing) within its wrapper.
+fn collect_slides(markdown: &str) -> Vec<Slide> {
+    let mut slides = Vec::new();
+    let mut heading = String::new();
+    let mut in_heading = false;
+    let mut slide_start = None;
+    for (ev, range) in mdbook::utils::new_cmark_parser(markdown, false).into_offset_iter() {
+        match &ev {
+            Event::Start(Tag::Heading { .. }) if slide_start.is_none() => {
+                heading.clear();
+                in_heading = true;
+            }
+            Event::End(TagEnd::Heading(_)) => in_heading = false,
+            Event::Text(text) | Event::Code(text) if in_heading => heading.push_str(text),
+            Event::Start(Tag::HtmlBlock) if slide_start.is_none() => {
+                let block = &markdown[range.clone()];
+                if block.contains("<svg") || block.contains("<img") {
+                    slide_start = Some(range.start);
+                }
+            }
+            _ => {}
+        }
+        if let (Some(start), Event::End(_)) = (slide_start, &ev) {
+            let html = &markdown[start..range.end];
+            if open_elements(html) == 0 {
+                let indent = line_prefix(markdown, start).chars().count();
+                slides.push(Slide {
+                    title: heading.clone(),
+                    html: mdbook::utils::render_markdown(&dedent(html, indent), false),
+                });
+                slide_start = None;
+            }
+        }
+    }
+    slides
+}
+
+/// Render the svgdx blocks of the chapter at `path` as a self-contained HTML
+/// slide deck, navigable with the arrow keys.
+pub fn render_slides(path: &Path) -> Result<String, Error> {
+    let (chapter, mut renderer) = chapter_renderer(path)?;
+    let markdown = codeblock_parser(&chapter, &mut renderer)?;
+    Ok(slide_deck(&markdown, &chapter.name))
+}
+
+/// The diagrams in processed chapter `markdown` as an HTML slide deck.
+fn slide_deck(markdown: &str, title: &str) -> String {
+    let sections = collect_slides(markdown)
+        .iter()
+        .enumerate()
+        .map(|(idx, slide)| {
+            format!(
+                "<section{}>\n<h1>{}</h1>\n{}\n</section>\n",
+                if idx == 0 { " class='current'" } else { "" },
+                escape_html(&slide.title),
+                slide.html.trim_end()
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{}</title>
+<style>
+body {{ margin: 0; font-family: sans-serif; }}
+section {{ display: none; height: 100vh; box-sizing: border-box; padding: 2em;
+  flex-direction: column; align-items: center; justify-content: center; }}
+section.current {{ display: flex; }}
+section svg {{ max-height: 80vh; }}
+</style>
+</head>
+<body>
+{}<script>
+const slides = document.querySelectorAll("section");
+let current = 0;
+function show(n) {{
+  if (!slides.length) return;
+  slides[current].classList.remove("current");
+  current = Math.max(0, Math.min(n, slides.length - 1));
+  slides[current].classList.add("current");
+}}
+document.addEventListener("keydown", (e) => {{
+  if (["ArrowRight", "PageDown", " "].includes(e.key)) show(current + 1);
+  if (["ArrowLeft", "PageUp"].includes(e.key)) show(current - 1);
+}});
+</script>
+</body>
+</html>
+"#,
+        escape_html(title),
+        sections
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use assertables::{assert_contains, assert_not_contains};
+    use mdbook::book::Chapter;
+
+    use crate::config::TransformOptions;
+    use crate::Renderer;
+
+    fn deck(content: &str, title: &str, renderer: &mut Renderer) -> String {
+        let chapter = Chapter::new(title, content.to_string(), "deck.md", Vec::new());
+        slide_deck(&codeblock_parser(&chapter, renderer).unwrap(), title)
+    }
+
+    #[test]
+    fn slides_use_preceding_heading() {
+        let content = r##"
+# Intro
+
+Some text
+
+```svgdx caption="A box"
+<svg><rect wh="20 5"/></svg>
+```
+
+## Second `part`
+
+- Item
+
+  ```svgdx-xml legend=auto
+  <svg><circle r="3"/></svg>
+  ```
+
+```xml
+<not-a-slide/>
+```
+"##;
+        let deck = deck(content, "Deck & Co", &mut Renderer::default());
+        assert_contains!(deck, "<title>Deck &amp; Co</title>");
+        assert_contains!(
+            deck,
+            "<section class='current'>\n<h1>Intro</h1>\n<div class='svgdx'>"
+        );
+        assert_contains!(deck, ">A box</div>");
+        assert_contains!(
+            deck,
+            "<section>\n<h1>Second part</h1>\n<div class='svgdx-xml'>"
+        );
+        assert_contains!(deck, r#"<circle r="3"/>"#);
+        assert_eq!(deck.matches("<section").count(), 2);
+        assert_not_contains!(deck, "not-a-slide");
+        assert_not_contains!(deck, "Some text");
+        assert_not_contains!(deck, "Item");
+    }
+
+    #[test]
+    fn slides_rendered_as_in_book() {
+        let content = "```svgdx-defs\n<specs><rect id=\"box\" wh=\"20 5\"/></specs>\n```\n\n\
+                       ```svgdx\n<svg><reuse href=\"#box\"/></svg>\n```\n";
+        let mut renderer = Renderer::default();
+        renderer.cfg.transform = TransformOptions {
+            scale: Some(2.0),
+            ..Default::default()
+        };
+        let deck = deck(content, "Deck", &mut renderer);
+        assert_eq!(deck.matches("<section").count(), 1);
+        assert_contains!(deck, r#"width="60mm""#);
+        assert_contains!(deck, r#"<rect width="20" height="5" class="box"/>"#);
+    }
+}
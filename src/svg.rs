@@ -0,0 +1,1102 @@
+//! Post-processing helpers operating on rendered SVG text.
+//!
+//! svgdx output is well-formed and uses double-quoted attributes, so simple
+//! string manipulation is sufficient for the small adjustments made here.
+
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+
+use crate::escape_html;
+
+/// Byte range of the root `<svg ...>` start tag, including the angle brackets.
+pub(crate) fn root_tag(svg: &str) -> Option<Range<usize>> {
+    let start = svg.find("<svg")?;
+    let mut quote = None;
+    for (idx, c) in svg[start..].char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(start..start + idx + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Remove the named attribute from the root `<svg>` element, if present.
+pub(crate) fn remove_root_attr(svg: &str, name: &str) -> String {
+    let Some(tag) = root_tag(svg) else {
+        return svg.to_string();
+    };
+    let needle = format!(" {}=\"", name);
+    let Some(attr_start) = svg[tag.clone()].find(&needle).map(|i| tag.start + i) else {
+        return svg.to_string();
+    };
+    let value_start = attr_start + needle.len();
+    let Some(attr_end) = svg[value_start..].find('"').map(|i| value_start + i + 1) else {
+        return svg.to_string();
+    };
+    format!("{}{}", &svg[..attr_start], &svg[attr_end..])
+}
+
+/// Value of the named attribute on the root `<svg>` element.
+pub(crate) fn root_attr<'a>(svg: &'a str, name: &str) -> Option<&'a str> {
+    let tag = &svg[root_tag(svg)?];
+    let needle = format!(" {}=\"", name);
+    let value_start = tag.find(&needle)? + needle.len();
+    tag[value_start..].split('"').next()
+}
+
+/// The `x y width height` values of the root element's `viewBox` attribute.
+pub(crate) fn view_box(svg: &str) -> Option<[f32; 4]> {
+    let values = root_attr(svg, "viewBox")?
+        .split_whitespace()
+        .map(|v| v.parse().ok())
+        .collect::<Option<Vec<f32>>>()?;
+    values.try_into().ok()
+}
+
+/// Add `class` to the class list of the root `<svg>` element.
+pub(crate) fn add_root_class(svg: String, class: &str) -> String {
+    let Some(tag) = root_tag(&svg) else {
+        return svg;
+    };
+    let class = escape_html(class);
+    match svg[tag.clone()].find(" class=\"") {
+        Some(idx) => {
+            let value_start = tag.start + idx + " class=\"".len();
+            format!("{}{} {}", &svg[..value_start], class, &svg[value_start..])
+        }
+        None => {
+            let name_end = tag.start + "<svg".len();
+            format!(
+                "{} class=\"{}\"{}",
+                &svg[..name_end],
+                class,
+                &svg[name_end..]
+            )
+        }
+    }
+}
+
+/// Byte ranges of the `<style>` elements of `svg`, including their tags.
+///
+/// Styles within `<foreignObject>` content belong to the embedded document,
+/// so are not included.
+fn style_elements(svg: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    loop {
+        let style = svg[offset..].find("<style").map(|i| offset + i);
+        let foreign = svg[offset..].find("<foreignObject").map(|i| offset + i);
+        match (style, foreign) {
+            (Some(start), foreign) if foreign.is_none_or(|f| start < f) => {
+                let Some(end) = svg[start..].find("</style>").map(|i| start + i + 8) else {
+                    break;
+                };
+                ranges.push(start..end);
+                offset = end;
+            }
+            (_, Some(start)) => {
+                let Some(end) = svg[start..].find("</foreignObject>").map(|i| start + i) else {
+                    break;
+                };
+                offset = end;
+            }
+            _ => break,
+        }
+    }
+    ranges
+}
+
+/// Remove all `<style>` elements from `svg`.
+pub(crate) fn strip_styles(svg: String) -> String {
+    let mut stripped = String::with_capacity(svg.len());
+    let mut offset = 0;
+    for range in style_elements(&svg) {
+        stripped.push_str(&svg[offset..range.start]);
+        offset = range.end;
+    }
+    stripped.push_str(&svg[offset..]);
+    stripped
+}
+
+/// Rewrite the `<style>` elements of `svg` as flat rules scoped to a class on
+/// the root element, named after the root element's id.
+///
+/// svgdx nests its rules within a `#id { ... }` rule, which browsers without
+/// CSS nesting support ignore. The class selector also takes precedence over
+/// element selectors in the book theme's CSS.
+pub(crate) fn scope_styles(svg: String) -> String {
+    rescope_styles(svg, true)
+}
+
+/// Rewrite the `<style>` elements of `svg` which aren't scoped to the root
+/// element's id (typically those written in the diagram's source) as by
+/// [`scope_styles`], leaving those svgdx nested within a rule for the id.
+///
+/// Styles of inline SVG apply to the whole page, so unscoped rules (e.g. a
+/// diagram's `rect { fill: red; }`) would otherwise affect every diagram on
+/// it, and on the print page those of the whole book.
+pub(crate) fn scope_author_styles(svg: String) -> String {
+    rescope_styles(svg, false)
+}
+
+/// Rewrite the `<style>` elements of `svg` (or only those not referencing
+/// the root element's id, unless `all`) scoped to a class on the root element.
+fn rescope_styles(svg: String, all: bool) -> String {
+    let Some(id) = root_attr(&svg, "id").map(str::to_owned) else {
+        return svg;
+    };
+    let mut scoped = String::with_capacity(svg.len());
+    let mut offset = 0;
+    for range in style_elements(&svg) {
+        let element = &svg[range.clone()];
+        if !all && element.contains(&format!("#{id}")) {
+            continue;
+        }
+        let css = element[element.find('>').map_or(0, |i| i + 1)..element.len() - 8]
+            .trim()
+            .trim_start_matches("<![CDATA[")
+            .trim_end_matches("]]>");
+        scoped.push_str(&svg[offset..range.start]);
+        scoped.push_str(&format!(
+            "<style><![CDATA[{}]]></style>",
+            scoped_rules(css, &id).join(" ")
+        ));
+        offset = range.end;
+    }
+    if offset == 0 && !all {
+        return svg;
+    }
+    scoped.push_str(&svg[offset..]);
+    add_root_class(scoped, &id)
+}
+
+/// The rules of `css` with each selector scoped to the class `id`.
+fn scoped_rules(css: &str, id: &str) -> Vec<String> {
+    let mut rules = Vec::new();
+    let mut rest = css;
+    while let Some(open) = rest.find('{') {
+        let mut depth = 0;
+        let Some(close) = rest[open..].char_indices().find_map(|(i, c)| {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(open + i)
+        }) else {
+            break;
+        };
+        let selector = rest[..open].trim();
+        let body = rest[open + 1..close].trim();
+        if selector == format!("#{id}") {
+            rules.extend(scoped_rules(body, id));
+        } else if selector.starts_with('@') && !body.contains('{') {
+            // At-rules without nested rules, e.g. `@font-face`, apply as is.
+            rules.push(format!("{selector} {{ {body} }}"));
+        } else if selector.starts_with('@') {
+            rules.push(format!(
+                "{selector} {{ {} }}",
+                scoped_rules(body, id).join(" ")
+            ));
+        } else {
+            let selectors = selector
+                .split(',')
+                .map(|sel| {
+                    let sel = sel.trim();
+                    if sel.contains(&format!("#{id}")) {
+                        sel.replace(&format!("svg#{id}"), &format!("svg.{id}"))
+                            .replace(&format!("#{id}"), &format!(".{id}"))
+                    } else {
+                        format!(".{id} {sel}")
+                    }
+                })
+                .collect::<Vec<_>>();
+            rules.push(format!("{} {{ {body} }}", selectors.join(", ")));
+        }
+        rest = &rest[close + 1..];
+    }
+    rules
+}
+
+/// Replace blank lines in `svg` with character references, so it can be
+/// included in markdown as a single HTML block.
+///
+/// A blank line ends an HTML block, after which markdown processing resumes
+/// (e.g. indented lines become a code block). Encoding the newline keeps any
+/// whitespace-sensitive content, such as a `<pre>` within a `foreignObject`,
+/// intact. Blank lines within comments and CDATA sections, where character
+/// references aren't decoded, are removed instead.
+pub(crate) fn encode_blank_lines(svg: &str) -> String {
+    let mut encoded = String::with_capacity(svg.len());
+    // Terminator of the comment or CDATA section at the current position.
+    let mut raw_end: Option<&str> = None;
+    for line in svg.trim().split_inclusive('\n') {
+        if line.trim().is_empty() {
+            if raw_end.is_none() {
+                encoded.push_str(line.trim_end_matches(['\r', '\n']));
+                encoded.push_str("&#10;");
+            }
+            continue;
+        }
+        encoded.push_str(line);
+        let mut rest = line;
+        loop {
+            match raw_end {
+                Some(end) => match rest.find(end) {
+                    Some(idx) => {
+                        rest = &rest[idx + end.len()..];
+                        raw_end = None;
+                    }
+                    None => break,
+                },
+                None => {
+                    let cdata = rest.find("<![CDATA[");
+                    let comment = rest.find("<!--");
+                    let (start, open, end) = match (cdata, comment) {
+                        (Some(c), Some(m)) if m < c => (m, "<!--", "-->"),
+                        (Some(c), _) => (c, "<![CDATA[", "]]>"),
+                        (None, Some(m)) => (m, "<!--", "-->"),
+                        (None, None) => break,
+                    };
+                    rest = &rest[start + open.len()..];
+                    raw_end = Some(end);
+                }
+            }
+        }
+    }
+    encoded
+}
+
+/// Undo the extra escaping of text within `<foreignObject>` elements.
+///
+/// svgdx escapes text content as written in the source, so entities such as
+/// `&amp;` in embedded XHTML become `&amp;amp;`. Text elsewhere is left as is,
+/// since svgdx also generates correctly escaped text (e.g. from `text` attributes).
+pub(crate) fn unescape_foreign_text(svg: String) -> String {
+    if !svg.contains("<foreignObject") {
+        return svg;
+    }
+    let mut fixed = String::with_capacity(svg.len());
+    let mut rest = svg.as_str();
+    while let Some(start) = rest.find("<foreignObject") {
+        let Some(end) = rest[start..].find("</foreignObject>").map(|i| start + i) else {
+            break;
+        };
+        fixed.push_str(&rest[..start]);
+        let mut content = &rest[start..end];
+        // Text runs alternate with tags; only text is unescaped.
+        while let Some(tag_start) = content.find('<') {
+            fixed.push_str(&content[..tag_start].replace("&amp;", "&"));
+            let tag_end = content[tag_start..]
+                .find('>')
+                .map_or(content.len(), |i| tag_start + i + 1);
+            fixed.push_str(&content[tag_start..tag_end]);
+            content = &content[tag_end..];
+        }
+        fixed.push_str(&content.replace("&amp;", "&"));
+        rest = &rest[end..];
+    }
+    fixed.push_str(rest);
+    fixed
+}
+
+/// Insert `content` as the first child of the root `<svg>` element.
+///
+/// If `svg` has no root `<svg>` element (e.g. it is an error message), it is
+/// returned unchanged.
+pub(crate) fn insert_after_root(svg: String, content: &str) -> String {
+    let Some(tag) = root_tag(&svg) else {
+        return svg;
+    };
+    format!("{}{}{}", &svg[..tag.end], content, &svg[tag.end..])
+}
+
+/// Add stroke dash patterns or hatched fills to elements with the given classes,
+/// so colour-coded elements remain distinguishable without colour.
+///
+/// Each pattern is either a `stroke-dasharray` value such as `"4 2"`, or
+/// `"hatch"` for a diagonally hatched fill.
+pub(crate) fn add_pattern_styles(svg: String, patterns: &BTreeMap<String, String>) -> String {
+    let Some(id) = root_attr(&svg, "id").map(str::to_owned) else {
+        return svg;
+    };
+    let mut defs = String::new();
+    let mut rules = String::new();
+    for (class, pattern) in patterns {
+        if pattern == "hatch" {
+            if defs.is_empty() {
+                defs = format!(
+                    "<defs><pattern id=\"{id}-hatch\" width=\"2\" height=\"2\" \
+                     patternUnits=\"userSpaceOnUse\" patternTransform=\"rotate(45)\">\
+                     <line x1=\"0\" y1=\"0\" x2=\"0\" y2=\"2\" style=\"stroke: black; stroke-width: 0.5;\"/>\
+                     </pattern></defs>"
+                );
+            }
+            rules.push_str(&format!("#{id} .{class} {{ fill: url(#{id}-hatch); }} "));
+        } else {
+            rules.push_str(&format!(
+                "#{id} .{class} {{ stroke-dasharray: {pattern}; }} "
+            ));
+        }
+    }
+    if rules.is_empty() {
+        return svg;
+    }
+    insert_after_root(svg, &format!("{defs}<style>{}</style>", rules.trim_end()))
+}
+
+/// Overlay `text` as a large translucent banner across the diagram.
+///
+/// The banner is centred on the root element's `viewBox`; if there is none
+/// (e.g. `svg` is an error message) it is returned unchanged.
+pub(crate) fn add_draft_banner(svg: String, text: &str) -> String {
+    let Some([x, y, w, h]) = view_box(&svg) else {
+        return svg;
+    };
+    let Some(end) = svg.rfind("</svg>") else {
+        return svg;
+    };
+    let (cx, cy) = (x + w / 2., y + h / 2.);
+    // Scale the text to fit within the width when rotated.
+    let size = (w / (text.chars().count().max(1) as f32 * 0.7)).min(h / 2.);
+    let banner = format!(
+        "<text x=\"{cx}\" y=\"{cy}\" transform=\"rotate(-20 {cx} {cy})\" \
+         style=\"font: bold {size}px sans-serif; text-anchor: middle; dominant-baseline: central; \
+         fill: red; stroke: none; opacity: 0.3; pointer-events: none;\">{}</text>",
+        escape_html(text)
+    );
+    format!("{}{}{}", &svg[..end], banner, &svg[end..])
+}
+
+/// `value` (a number with an optional unit, e.g. `"22.5mm"`) multiplied by `factor`.
+fn scale_length(value: &str, factor: f32) -> Option<String> {
+    let unit_start = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(value.len());
+    let number: f32 = value[..unit_start].parse().ok()?;
+    Some(format!("{}{}", number * factor, &value[unit_start..]))
+}
+
+/// `svg` with each font size multiplied by `factor`.
+///
+/// Sizes are those of `font-size` attributes and style properties given in
+/// user units or pixels; relative sizes (e.g. `120%` or `larger`) follow them.
+fn scale_font_sizes(svg: &str, factor: f32) -> String {
+    let mut output = String::with_capacity(svg.len());
+    let mut offset = 0;
+    for (idx, _) in svg.match_indices("font-size") {
+        let after = idx + "font-size".len();
+        let rest = &svg[after..];
+        let value_start = if let Some(attr) = rest.strip_prefix("=\"") {
+            after + rest.len() - attr.len()
+        } else if let Some(prop) = rest.trim_start().strip_prefix(':') {
+            after + rest.len() - prop.trim_start().len()
+        } else {
+            continue;
+        };
+        let value_len = svg[value_start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '%')))
+            .unwrap_or(svg.len() - value_start);
+        let value = &svg[value_start..value_start + value_len];
+        if !value
+            .trim_end_matches("px")
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.')
+        {
+            continue;
+        }
+        if let Some(scaled) = scale_length(value, factor) {
+            output.push_str(&svg[offset..value_start]);
+            output.push_str(&scaled);
+            offset = value_start + value_len;
+        }
+    }
+    output.push_str(&svg[offset..]);
+    output
+}
+
+/// `svg` with its text sized as if rendered with `scale` mm per user unit,
+/// whatever scale it was actually rendered with.
+///
+/// The actual scale is taken from the root element's `width` (in mm) and
+/// `viewBox`; if either is missing, `svg` is returned unchanged.
+pub(crate) fn harmonize_text_size(svg: String, scale: f32) -> String {
+    let Some(width) = root_attr(&svg, "width")
+        .and_then(|w| w.strip_suffix("mm"))
+        .and_then(|w| w.parse::<f32>().ok())
+    else {
+        return svg;
+    };
+    let Some([_, _, vb_width, _]) = view_box(&svg) else {
+        return svg;
+    };
+    if width <= 0.0 || vb_width <= 0.0 {
+        return svg;
+    }
+    let factor = scale * vb_width / width;
+    if (factor - 1.0).abs() < 0.001 {
+        return svg;
+    }
+    scale_font_sizes(&svg, factor)
+}
+
+/// Add a footer line of `text` beneath the diagram, extending its `viewBox`
+/// (and `height`, if given) to make room.
+///
+/// If `svg` has no `viewBox` (e.g. it is an error message) it is returned unchanged.
+pub(crate) fn add_footer(svg: String, text: &str) -> String {
+    let Some([x, y, w, h]) = view_box(&svg) else {
+        return svg;
+    };
+    // Fit the text within the width, up to the default svgdx font size.
+    let size = (w / (text.chars().count().max(1) as f32 * 0.6)).min(3.);
+    let band = size * 2.;
+    let svg = match root_attr(&svg, "height").and_then(|v| scale_length(v, (h + band) / h)) {
+        Some(height) => {
+            let svg = remove_root_attr(&svg, "height");
+            set_root_attr(svg, "height", &height)
+        }
+        None => svg,
+    };
+    let svg = set_root_attr(
+        remove_root_attr(&svg, "viewBox"),
+        "viewBox",
+        &format!("{} {} {} {}", x, y, w, h + band),
+    );
+    let Some(end) = svg.rfind("</svg>") else {
+        return svg;
+    };
+    let footer = format!(
+        "<text class=\"svgdx-stamp\" x=\"{}\" y=\"{}\" \
+         style=\"font: {}px sans-serif; text-anchor: end; fill: gray; stroke: none;\">{}</text>",
+        x + w,
+        y + h + band * 0.7,
+        size,
+        escape_html(text)
+    );
+    format!("{}{}{}", &svg[..end], footer, &svg[end..])
+}
+
+/// Text content of the `<text>` elements in `svg`, in document order and
+/// without duplicates. Entities are left escaped.
+fn text_content(svg: &str) -> Vec<String> {
+    let mut text = Vec::new();
+    let mut rest = svg;
+    while let Some(start) = rest.find("<text") {
+        rest = &rest[start + "<text".len()..];
+        if !rest.starts_with([' ', '>']) {
+            continue;
+        }
+        let Some(end) = rest.find("</text>") else {
+            break;
+        };
+        let element = &rest[rest.find('>').map_or(0, |i| i + 1).min(end)..end];
+        rest = &rest[end..];
+        // Text runs alternate with tags (e.g. `<tspan>`), which are dropped.
+        let mut content = String::new();
+        for (idx, run) in element.split(['<', '>']).enumerate() {
+            if idx % 2 == 0 {
+                content.push_str(run);
+                content.push(' ');
+            }
+        }
+        let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !content.is_empty() && !text.contains(&content) {
+            text.push(content);
+        }
+    }
+    text
+}
+
+/// Text of the `<title>` element which is a child of the root element, if any.
+pub(crate) fn root_title(svg: &str) -> Option<String> {
+    let mut rest = &svg[root_tag(svg)?.end..];
+    let mut depth = 0;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->").map(|i| i + 3)
+        } else if rest.starts_with("<![CDATA[") {
+            rest.find("]]>").map(|i| i + 3)
+        } else {
+            rest.find('>').map(|i| i + 1)
+        }?;
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        if tag.starts_with("</") {
+            if depth == 0 {
+                return None;
+            }
+            depth -= 1;
+        } else if depth == 0 && (tag == "<title>" || tag.starts_with("<title ")) {
+            let text = &rest[..rest.find("</title>")?];
+            let text = text
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&");
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            return (!text.is_empty()).then_some(text);
+        } else if !tag.starts_with("<!") && !tag.starts_with("<?") && !tag.ends_with("/>") {
+            depth += 1;
+        }
+    }
+    None
+}
+
+/// Describe `svg` for screen readers: give it the `img` role and the
+/// accessible name `label` (also as its `<title>`), with a `<desc>` listing
+/// the diagram's text.
+pub(crate) fn add_accessibility(svg: String, label: Option<&str>) -> String {
+    if root_tag(&svg).is_none() {
+        return svg;
+    }
+    let mut svg = set_root_attr(svg, "role", "img");
+    let mut children = String::new();
+    if let Some(label) = label {
+        svg = set_root_attr(svg, "aria-label", label);
+        if root_title(&svg).as_deref() != Some(label) {
+            children.push_str(&format!("<title>{}</title>", escape_html(label)));
+        }
+    }
+    let text = text_content(&svg);
+    if !text.is_empty() {
+        children.push_str(&format!("<desc>{}</desc>", text.join(", ")));
+    }
+    insert_after_root(svg, &children)
+}
+
+/// Add attribute `name` with the given `value` to the root `<svg>` element.
+fn set_root_attr(svg: String, name: &str, value: &str) -> String {
+    let Some(tag) = root_tag(&svg) else {
+        return svg;
+    };
+    let end = tag.end - if svg[..tag.end].ends_with("/>") { 2 } else { 1 };
+    format!(
+        "{} {}=\"{}\"{}",
+        &svg[..end],
+        name,
+        escape_html(value),
+        &svg[end..]
+    )
+}
+
+/// Insert a comment recording the svgdx version just inside the root `<svg>` element.
+pub(crate) fn add_version_comment(svg: String) -> String {
+    insert_after_root(svg, &format!("<!-- svgdx {} -->", svgdx::VERSION))
+}
+
+/// Dash arrays distinguishing stroke colours in high-contrast display, in
+/// order of each colour's first use.
+const CONTRAST_DASHES: [&str; 5] = ["4 1", "1 1", "4 1 1 1", "2 2", "6 2"];
+
+/// Angles of the hatching distinguishing fill colours in high-contrast
+/// display; further colours repeat these with wider spacing.
+const CONTRAST_HATCHES: [u32; 4] = [45, 135, 0, 90];
+
+/// The `(property, value)` declarations of a CSS rule body.
+fn declarations(body: &str) -> impl Iterator<Item = (&str, &str)> {
+    body.split(';')
+        .filter_map(|decl| decl.split_once(':'))
+        .map(|(prop, value)| (prop.trim(), value.trim()))
+}
+
+/// The class of an svgdx colour rule's selectors (e.g. `d-red` for
+/// `#id .d-red`), if each selects only that class.
+fn colour_class(selectors: &[&str]) -> Option<String> {
+    let classes = selectors
+        .iter()
+        .map(|sel| {
+            sel.rsplit(' ')
+                .next()
+                .and_then(|last| last.strip_prefix('.'))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let class = classes.first()?;
+    (class.starts_with("d-") && classes.iter().all(|c| c == class)).then(|| class.to_string())
+}
+
+/// Add style rules for a high-contrast variant of `svg`, shown when the reader
+/// selects it with the toggle (which sets the `svgdx-high-contrast` class on
+/// the page's root element), or when their system or browser settings request
+/// increased contrast (unless they have chosen `svgdx-standard-contrast`).
+///
+/// In the variant, strokes narrower than `min_stroke` are widened to it, each
+/// stroke colour set by a class is given a distinct dash pattern, and each fill
+/// colour a distinct hatching, so that elements aren't distinguished only by
+/// colour. Text is shown in the page's text colour.
+pub(crate) fn add_high_contrast_style(svg: String, min_stroke: f32) -> String {
+    // Styles in inline SVG apply to the whole page, so must be scoped to
+    // this diagram's id (assigned by svgdx's `use_local_styles` option).
+    let Some(id) = root_attr(&svg, "id").map(str::to_owned) else {
+        return svg;
+    };
+    let mut overrides = Vec::new();
+    let mut strokes: Vec<String> = Vec::new();
+    let mut fills: Vec<String> = Vec::new();
+    for range in style_elements(&svg) {
+        let element = &svg[range];
+        let css = element[element.find('>').map_or(0, |i| i + 1)..element.len() - 8]
+            .trim()
+            .trim_start_matches("<![CDATA[")
+            .trim_end_matches("]]>");
+        for rule in scoped_rules(css, &id) {
+            let Some((selector, body)) = rule.split_once(" { ") else {
+                continue;
+            };
+            if selector.starts_with('@') {
+                continue;
+            }
+            // Selectors were scoped to a class named after the id.
+            let selectors = selector
+                .split(", ")
+                .map(|sel| sel.replacen(&format!(".{id}"), &format!("#{id}"), 1))
+                .collect::<Vec<_>>();
+            let selectors = selectors.iter().map(String::as_str).collect::<Vec<_>>();
+            let is_text = selectors.iter().any(|sel| {
+                let last = sel.rsplit(' ').next().unwrap_or_default();
+                last.starts_with("text") || last.starts_with("tspan") || last == "*"
+            });
+            if is_text {
+                continue;
+            }
+            let class = colour_class(&selectors);
+            let mut decls = Vec::new();
+            for (prop, value) in declarations(body.trim_end_matches('}')) {
+                match prop {
+                    "stroke-width" => {
+                        // Every rule is mirrored, so that the cascade (e.g.
+                        // of `d-thick` over element rules) is unchanged.
+                        let width = value.parse::<f32>().unwrap_or_default();
+                        if width > 0. {
+                            decls.push(format!("stroke-width: {}", width.max(min_stroke)));
+                        }
+                    }
+                    "stroke" if class.is_some() && !matches!(value, "none" | "black") => {
+                        let idx = strokes.iter().position(|s| s == value).unwrap_or_else(|| {
+                            strokes.push(value.to_string());
+                            strokes.len() - 1
+                        });
+                        let dashes = CONTRAST_DASHES[idx % CONTRAST_DASHES.len()];
+                        decls.push(format!("stroke-dasharray: {}", dashes));
+                    }
+                    "fill" if class.is_some() && !matches!(value, "none" | "transparent") => {
+                        let idx = fills.iter().position(|f| f == value).unwrap_or_else(|| {
+                            fills.push(value.to_string());
+                            fills.len() - 1
+                        });
+                        decls.push(format!("fill: url(#{id}-hc-{idx})"));
+                    }
+                    _ => {}
+                }
+            }
+            if !decls.is_empty() {
+                overrides.push((selectors.join(", "), decls.join("; ")));
+            }
+        }
+    }
+    let mut patterns = String::new();
+    for (idx, colour) in fills.iter().enumerate() {
+        let angle = CONTRAST_HATCHES[idx % CONTRAST_HATCHES.len()];
+        let size = 2 + idx / CONTRAST_HATCHES.len();
+        patterns.push_str(&format!(
+            "<pattern id=\"{id}-hc-{idx}\" width=\"{size}\" height=\"{size}\" \
+             patternUnits=\"userSpaceOnUse\" patternTransform=\"rotate({angle})\">\
+             <rect width=\"{size}\" height=\"{size}\" style=\"fill: {colour}; stroke: none;\"/>\
+             <line x1=\"0\" y1=\"0\" x2=\"0\" y2=\"{size}\" style=\"stroke: black; stroke-width: 0.5;\"/>\
+             </pattern>"
+        ));
+    }
+    overrides.push((
+        format!("#{id} text, #{id} tspan"),
+        "fill: CanvasText; stroke: none; font-weight: bold".to_string(),
+    ));
+    let rules = |scope: &str| {
+        overrides
+            .iter()
+            .map(|(selectors, decls)| {
+                let selectors = selectors
+                    .split(", ")
+                    .map(|sel| format!("{scope} {sel}"))
+                    .collect::<Vec<_>>();
+                format!("{} {{ {}; }}", selectors.join(", "), decls)
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let style = format!(
+        "{}<style><![CDATA[{} @media (prefers-contrast: more) {{ {} }}]]></style>",
+        if patterns.is_empty() {
+            String::new()
+        } else {
+            format!("<defs>{}</defs>", patterns)
+        },
+        rules(":root.svgdx-high-contrast"),
+        rules(":root:not(.svgdx-standard-contrast)")
+    );
+    match svg.rfind("</svg>") {
+        // After the diagram's own styles, so that it takes precedence.
+        Some(end) => format!("{}{}{}", &svg[..end], style, &svg[end..]),
+        None => svg,
+    }
+}
+
+/// Whether `c` can continue an id in a CSS selector or `url()` reference.
+fn is_id_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+/// The ids of the elements of `svg` other than the root.
+fn element_ids(svg: &str) -> HashSet<String> {
+    let mut ids = HashSet::new();
+    let start = root_tag(svg).map_or(0, |tag| tag.end);
+    let mut rest = &svg[start..];
+    while let Some(idx) = rest.find(" id=\"") {
+        rest = &rest[idx + 5..];
+        if let Some((id, _)) = rest.split_once('"') {
+            ids.insert(id.to_string());
+        }
+    }
+    ids
+}
+
+/// `text` with each `#id` reference to one of `ids` prefixed with `prefix`,
+/// where `is_ref` accepts the text preceding the `#`.
+fn prefix_refs(
+    text: &str,
+    ids: &HashSet<String>,
+    prefix: &str,
+    is_ref: impl Fn(&str) -> bool,
+) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut offset = 0;
+    for (idx, _) in text.match_indices('#') {
+        let id_end = text[idx + 1..]
+            .find(|c| !is_id_char(c))
+            .map_or(text.len(), |i| idx + 1 + i);
+        if ids.contains(&text[idx + 1..id_end]) && is_ref(&text[..idx]) {
+            output.push_str(&text[offset..=idx]);
+            output.push_str(prefix);
+            offset = idx + 1;
+        }
+    }
+    output.push_str(&text[offset..]);
+    output
+}
+
+/// Whether `id` is of the form svgdx gives the root of a diagram with local
+/// styles, e.g. `svgdx-1a2b3c4d`.
+fn is_style_id(id: &str) -> bool {
+    id.strip_prefix("svgdx-")
+        .is_some_and(|hex| hex.len() == 8 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// `svg` with the id svgdx gives its root element for local styles, which is
+/// random, replaced by `id` throughout.
+pub(crate) fn set_style_id(svg: String, id: &str) -> String {
+    match root_attr(&svg, "id") {
+        Some(old) if is_style_id(old) => {
+            let old = old.to_string();
+            svg.replace(&old, id)
+        }
+        _ => svg,
+    }
+}
+
+/// Prefix the ids of the elements of `svg` (other than the root) with
+/// `prefix`, along with references to them.
+///
+/// Inline diagrams share the page's id namespace, so e.g. two diagrams each
+/// defining a `d-arrow` marker would otherwise both use the first one's.
+/// References are `url(#id)` values, `href="#id"` attributes, and id
+/// selectors in `<style>` elements; those to ids the diagram doesn't define
+/// (such as links to headings on the page) are left unchanged.
+pub(crate) fn namespace_ids(svg: String, prefix: &str) -> String {
+    let ids = element_ids(&svg);
+    if ids.is_empty() {
+        return svg;
+    }
+    let svg = prefix_refs(&svg, &ids, prefix, |before| {
+        before.ends_with("url(") || before.ends_with("href=\"")
+    });
+    let svg = ids.iter().fold(svg, |svg, id| {
+        let from = format!(" id=\"{}\"", id);
+        svg.replace(&from, &format!(" id=\"{}{}\"", prefix, id))
+    });
+    let mut namespaced = String::with_capacity(svg.len());
+    let mut offset = 0;
+    for range in style_elements(&svg) {
+        namespaced.push_str(&svg[offset..range.start]);
+        // A `#` following a colon starts a colour value rather than a selector.
+        namespaced.push_str(&prefix_refs(&svg[range.clone()], &ids, prefix, |before| {
+            !before.trim_end().ends_with(':')
+        }));
+        offset = range.end;
+    }
+    namespaced.push_str(&svg[offset..]);
+    namespaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_tag_skips_quoted_brackets() {
+        let svg = r#"<?xml?><svg a="1>2" b='>'><rect/></svg>"#;
+        assert_eq!(&svg[root_tag(svg).unwrap()], r#"<svg a="1>2" b='>'>"#);
+    }
+
+    #[test]
+    fn remove_attr_from_root_only() {
+        let svg = r#"<svg width="10mm" height="5mm"><rect width="2"/></svg>"#;
+        let svg = remove_root_attr(svg, "width");
+        assert_eq!(svg, r#"<svg height="5mm"><rect width="2"/></svg>"#);
+        assert_eq!(remove_root_attr(&svg, "width"), svg);
+    }
+
+    #[test]
+    fn read_root_attr() {
+        let svg = r#"<svg id="svgdx-1234" width="10mm"><rect id="a"/></svg>"#;
+        assert_eq!(root_attr(svg, "id"), Some("svgdx-1234"));
+        assert_eq!(root_attr(svg, "height"), None);
+    }
+
+    #[test]
+    fn root_class_added() {
+        let svg = add_root_class(r#"<svg id="a"><rect class="b"/></svg>"#.to_string(), "diag");
+        assert_eq!(svg, r#"<svg class="diag" id="a"><rect class="b"/></svg>"#);
+        let svg = add_root_class(r#"<svg class="x"><rect/></svg>"#.to_string(), "diag");
+        assert_eq!(svg, r#"<svg class="diag x"><rect/></svg>"#);
+        assert_eq!(add_root_class("<div/>".to_string(), "diag"), "<div/>");
+    }
+
+    #[test]
+    fn styles_scoped_to_root_class() {
+        let svg = r#"<svg id="svgdx-1"><style><![CDATA[
+  svg#svgdx-1 { background: none; }
+  #svgdx-1 {
+  rect, circle { fill: white; }
+  .d-red { stroke: red; }
+  @media (prefers-color-scheme: dark) { text { fill: white; } }
+  }
+]]></style><rect class="d-red"/></svg>"#;
+        assert_eq!(
+            scope_styles(svg.to_string()),
+            r#"<svg class="svgdx-1" id="svgdx-1"><style><![CDATA[svg.svgdx-1 { background: none; } .svgdx-1 rect, .svgdx-1 circle { fill: white; } .svgdx-1 .d-red { stroke: red; } @media (prefers-color-scheme: dark) { .svgdx-1 text { fill: white; } }]]></style><rect class="d-red"/></svg>"#
+        );
+        assert_eq!(
+            strip_styles(svg.to_string()),
+            r#"<svg id="svgdx-1"><rect class="d-red"/></svg>"#
+        );
+    }
+
+    #[test]
+    fn author_styles_scoped() {
+        let svg = r#"<svg id="svgdx-1"><style>#svgdx-1 { rect { fill: white; } }</style><style>
+@font-face { font-family: Hand; src: url(hand.woff); }
+rect, .x text { fill: red; }
+</style><rect/></svg>"#;
+        assert_eq!(
+            scope_author_styles(svg.to_string()),
+            r#"<svg class="svgdx-1" id="svgdx-1"><style>#svgdx-1 { rect { fill: white; } }</style><style><![CDATA[@font-face { font-family: Hand; src: url(hand.woff); } .svgdx-1 rect, .svgdx-1 .x text { fill: red; }]]></style><rect/></svg>"#
+        );
+        let nested = r#"<svg id="svgdx-1"><style>#svgdx-1 { rect { fill: white; } }</style></svg>"#;
+        assert_eq!(scope_author_styles(nested.to_string()), nested);
+    }
+
+    #[test]
+    fn foreign_object_styles_kept() {
+        let svg = r#"<svg id="svgdx-1"><style>#svgdx-1 { rect { fill: red; } }</style><foreignObject><div xmlns="http://www.w3.org/1999/xhtml"><style>p { margin: 0; }</style><p>Hi</p></div></foreignObject></svg>"#;
+        let foreign = r#"<foreignObject><div xmlns="http://www.w3.org/1999/xhtml"><style>p { margin: 0; }</style><p>Hi</p></div></foreignObject></svg>"#;
+        assert!(strip_styles(svg.to_string()).ends_with(&format!("<svg id=\"svgdx-1\">{foreign}")));
+        assert!(scope_styles(svg.to_string()).ends_with(&format!(
+            "<style><![CDATA[.svgdx-1 rect {{ fill: red; }}]]></style>{foreign}"
+        )));
+    }
+
+    #[test]
+    fn foreign_text_unescaped() {
+        let svg = r#"<svg><text>a &amp;amp; b</text><foreignObject><p title="&amp;amp;">p &amp;amp; q &amp;lt; &amp;#169;</p></foreignObject></svg>"#;
+        assert_eq!(
+            unescape_foreign_text(svg.to_string()),
+            r#"<svg><text>a &amp;amp; b</text><foreignObject><p title="&amp;amp;">p &amp; q &lt; &#169;</p></foreignObject></svg>"#
+        );
+    }
+
+    #[test]
+    fn blank_lines_encoded() {
+        let svg = "<svg>\n<text>a\n\n  \nb</text>\n<!-- x\n\ny -->\n\n</svg>\n";
+        assert_eq!(
+            encode_blank_lines(svg),
+            "<svg>\n<text>a\n&#10;  &#10;b</text>\n<!-- x\ny -->\n&#10;</svg>"
+        );
+    }
+
+    #[test]
+    fn high_contrast_variant() {
+        let svg = r#"<svg id="svgdx-1234"><style><![CDATA[#svgdx-1234 {
+rect { stroke-width: 0.5; fill: white; stroke: black; }
+text, tspan { stroke-width: 0; }
+.d-fill-red { fill: red; }
+text.d-fill-red, text.d-fill-red * { fill: white; }
+.d-blue { stroke: blue; }
+.d-thicker { stroke-width: 2; }
+}]]></style><rect class="d-fill-red d-blue"/></svg>"#;
+        let svg = add_high_contrast_style(svg.to_string(), 1.);
+        let toggled = ":root.svgdx-high-contrast #svgdx-1234";
+        assert!(svg.contains(&format!("{toggled} rect {{ stroke-width: 1; }}")));
+        assert!(svg.contains(&format!("{toggled} .d-thicker {{ stroke-width: 2; }}")));
+        assert!(svg.contains(&format!(
+            "{toggled} .d-fill-red {{ fill: url(#svgdx-1234-hc-0); }}"
+        )));
+        assert!(svg.contains(r#"<pattern id="svgdx-1234-hc-0""#));
+        assert!(svg.contains(&format!("{toggled} .d-blue {{ stroke-dasharray: 4 1; }}")));
+        assert!(!svg.contains(&format!("{toggled} text.d-fill-red")));
+        assert!(svg.contains(
+            "@media (prefers-contrast: more) { :root:not(.svgdx-standard-contrast) #svgdx-1234 rect { stroke-width: 1; }"
+        ));
+        assert!(svg.ends_with("]]></style></svg>"));
+        assert_eq!(add_high_contrast_style("<svg/>".to_string(), 1.), "<svg/>");
+    }
+
+    #[test]
+    fn class_patterns() {
+        let patterns = BTreeMap::from([
+            ("d-red".to_string(), "4 2".to_string()),
+            ("d-fill-blue".to_string(), "hatch".to_string()),
+        ]);
+        let svg = add_pattern_styles(r#"<svg id="svgdx-1"><rect/></svg>"#.to_string(), &patterns);
+        assert!(svg.contains(r#"<pattern id="svgdx-1-hatch""#));
+        assert!(svg.contains("#svgdx-1 .d-fill-blue { fill: url(#svgdx-1-hatch); }"));
+        assert!(svg.contains("#svgdx-1 .d-red { stroke-dasharray: 4 2; }"));
+    }
+
+    #[test]
+    fn parse_view_box() {
+        assert_eq!(
+            view_box(r#"<svg viewBox="-5 -5 30 15.5">"#),
+            Some([-5., -5., 30., 15.5])
+        );
+        assert_eq!(view_box(r#"<svg viewBox="0 0 1">"#), None);
+        assert_eq!(view_box("<svg>"), None);
+    }
+
+    #[test]
+    fn draft_banner_centred() {
+        let svg = add_draft_banner(
+            r#"<svg viewBox="-5 -5 30 15"><rect/></svg>"#.to_string(),
+            "DRAFT",
+        );
+        assert!(svg.starts_with(r#"<svg viewBox="-5 -5 30 15"><rect/><text x="10" y="2.5" "#));
+        assert!(svg.ends_with(">DRAFT</text></svg>"));
+        assert_eq!(add_draft_banner("<svg/>".to_string(), "DRAFT"), "<svg/>");
+    }
+
+    #[test]
+    fn footer_extends_view_box() {
+        let svg = add_footer(
+            r#"<svg width="45mm" height="30mm" viewBox="-5 -5 30 20"><rect/></svg>"#.to_string(),
+            "DOC-1",
+        );
+        assert_eq!(
+            svg,
+            r#"<svg width="45mm" height="39mm" viewBox="-5 -5 30 26"><rect/><text class="svgdx-stamp" x="25" y="19.2" style="font: 3px sans-serif; text-anchor: end; fill: gray; stroke: none;">DOC-1</text></svg>"#
+        );
+        assert_eq!(add_footer("<svg/>".to_string(), "DOC-1"), "<svg/>");
+    }
+
+    #[test]
+    fn version_comment_inside_root() {
+        let svg = add_version_comment(r#"<svg width="10"><rect/></svg>"#.to_string());
+        assert_eq!(
+            svg,
+            format!(
+                r#"<svg width="10"><!-- svgdx {} --><rect/></svg>"#,
+                svgdx::VERSION
+            )
+        );
+        assert_eq!(
+            add_version_comment("<div>error</div>".to_string()),
+            "<div>error</div>"
+        );
+    }
+
+    #[test]
+    fn accessible_svg() {
+        let svg = r#"<svg width="10"><text x="1">Web <tspan>&amp; app</tspan></text><rect/><text>DB</text><text>DB</text></svg>"#;
+        assert_eq!(
+            add_accessibility(svg.to_string(), Some("Tiers <2>")),
+            r#"<svg width="10" role="img" aria-label="Tiers &lt;2&gt;"><title>Tiers &lt;2&gt;</title><desc>Web &amp; app, DB</desc><text x="1">Web <tspan>&amp; app</tspan></text><rect/><text>DB</text><text>DB</text></svg>"#
+        );
+        assert_eq!(
+            add_accessibility("<svg><rect/></svg>".to_string(), None),
+            r#"<svg role="img"><rect/></svg>"#
+        );
+    }
+
+    #[test]
+    fn style_id_replaced() {
+        let svg = "<svg id=\"svgdx-1a2b3c4d\"><style>#svgdx-1a2b3c4d rect {}</style></svg>";
+        assert_eq!(
+            set_style_id(svg.to_string(), "svgdx-x-1"),
+            "<svg id=\"svgdx-x-1\"><style>#svgdx-x-1 rect {}</style></svg>"
+        );
+        let svg = "<svg id=\"svgdx-main\"/>";
+        assert_eq!(set_style_id(svg.to_string(), "svgdx-x-1"), svg);
+    }
+
+    #[test]
+    fn ids_namespaced() {
+        let svg = concat!(
+            r##"<svg id="root"><style>#root .a { marker-end: url(#arrow); } #box:hover { fill: #abc; }</style>"##,
+            r##"<marker id="arrow"/><rect id="box" style="fill: url(#grad)"/><rect id="abc"/>"##,
+            r##"<use href="#box"/><a href="#intro">Intro</a></svg>"##
+        );
+        assert_eq!(
+            namespace_ids(svg.to_string(), "p-"),
+            concat!(
+                r##"<svg id="root"><style>#root .a { marker-end: url(#p-arrow); } #p-box:hover { fill: #abc; }</style>"##,
+                r##"<marker id="p-arrow"/><rect id="p-box" style="fill: url(#grad)"/><rect id="p-abc"/>"##,
+                r##"<use href="#p-box"/><a href="#intro">Intro</a></svg>"##
+            )
+        );
+        assert_eq!(
+            namespace_ids("<svg id=\"root\"/>".to_string(), "p-"),
+            "<svg id=\"root\"/>"
+        );
+    }
+
+    #[test]
+    fn text_size_harmonized() {
+        let svg = concat!(
+            r#"<svg width="90mm" height="30mm" viewBox="0 0 30 10">"#,
+            r#"<style>text { font-size: 3px; } .big { font-size: 120%; }</style>"#,
+            r#"<text font-size="4" style="font-size:2px">a</text></svg>"#
+        );
+        assert_eq!(
+            harmonize_text_size(svg.to_string(), 1.5),
+            concat!(
+                r#"<svg width="90mm" height="30mm" viewBox="0 0 30 10">"#,
+                r#"<style>text { font-size: 1.5px; } .big { font-size: 120%; }</style>"#,
+                r#"<text font-size="2" style="font-size:1px">a</text></svg>"#
+            )
+        );
+        let unchanged =
+            r#"<svg width="45mm" viewBox="0 0 30 10"><text font-size="4">a</text></svg>"#;
+        assert_eq!(harmonize_text_size(unchanged.to_string(), 1.5), unchanged);
+    }
+
+    #[test]
+    fn title_of_root() {
+        let svg = r#"<svg><style>a</style><g><title>Group</title></g><title>Web &amp;
+  DB</title></svg>"#;
+        assert_eq!(root_title(svg).as_deref(), Some("Web & DB"));
+        assert_eq!(root_title("<svg><g><title>Group</title></g></svg>"), None);
+        assert_eq!(root_title("<svg><rect/></svg><title>After</title>"), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Diagrams generated from the rows of a markdown table.
+//!
+//! A `svgdx-template` block must be followed by a table. The template is
+//! instantiated once per table row, replacing `{{column}}` placeholders with
+//! the values from that row, and all instances are rendered as one diagram.
+//! The table itself is left unchanged.
+
+use pulldown_cmark::{
+    Event::{self, Code, End, Start, Text},
+    Tag, TagEnd,
+};
+
+use crate::escape_html;
+use crate::svg::root_tag;
+
+/// Records collected from a table's events.
+#[derive(Default)]
+pub(crate) struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    cell: Option<String>,
+    in_head: bool,
+}
+
+impl Table {
+    /// Add the next event of the table, returning true at the end of the table.
+    pub fn push(&mut self, ev: &Event) -> bool {
+        match ev {
+            Start(Tag::TableHead) => self.in_head = true,
+            End(TagEnd::TableHead) => self.in_head = false,
+            Start(Tag::TableRow) => self.rows.push(Vec::new()),
+            Start(Tag::TableCell) => self.cell = Some(String::new()),
+            Text(text) | Code(text) => {
+                if let Some(cell) = &mut self.cell {
+                    cell.push_str(text);
+                }
+            }
+            End(TagEnd::TableCell) => {
+                let cell = self.cell.take().unwrap_or_default().trim().to_string();
+                if self.in_head {
+                    self.header.push(cell);
+                } else if let Some(row) = self.rows.last_mut() {
+                    row.push(cell);
+                }
+            }
+            _ => {}
+        }
+        matches!(ev, End(TagEnd::Table))
+    }
+
+    /// svgdx source with the `template` instantiated for each row.
+    ///
+    /// If the template has a root `<svg>` element, only its content is
+    /// repeated; otherwise a root element is added.
+    pub fn instantiate(&self, template: &str) -> String {
+        let close = template.rfind("</svg>");
+        let (open, body, close) = match (root_tag(template), close) {
+            (Some(tag), Some(close)) if template.trim_start().starts_with("<svg") => (
+                &template[..tag.end],
+                &template[tag.end..close],
+                &template[close..],
+            ),
+            _ => ("<svg>", template, "</svg>\n"),
+        };
+        let mut source = format!("{}\n", open);
+        for row in &self.rows {
+            let mut item = body.trim_matches('\n').to_string();
+            for (column, value) in self.header.iter().zip(row) {
+                item = item.replace(&format!("{{{{{}}}}}", column), &escape_html(value));
+            }
+            source.push_str(&item);
+            source.push('\n');
+        }
+        source.push_str(close);
+        source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(markdown: &str) -> Table {
+        let mut table = Table::default();
+        let mut events = mdbook::utils::new_cmark_parser(markdown, false)
+            .skip_while(|ev| !matches!(ev, Start(Tag::Table(_))));
+        // The end of the table is reported, but not the following paragraph.
+        assert!(events.any(|ev| table.push(&ev)));
+        assert!(matches!(events.next(), Some(Start(Tag::Paragraph))));
+        table
+    }
+
+    #[test]
+    fn instantiate_per_row() {
+        let records =
+            table("| name | x |\n|------|---|\n| Web | 0 |\n| `DB` & co | 40 |\n\nAfter\n");
+        assert_eq!(
+            records.instantiate(r#"<rect x="{{x}}" wh="30 8" text="{{name}}"/>"#),
+            "<svg>\n<rect x=\"0\" wh=\"30 8\" text=\"Web\"/>\n\
+             <rect x=\"40\" wh=\"30 8\" text=\"DB &amp; co\"/>\n</svg>\n"
+        );
+
+        assert_eq!(
+            records.instantiate("<svg border=\"2\">\n  <circle cx=\"{{x}}\" r=\"1\"/>\n</svg>\n"),
+            "<svg border=\"2\">\n  <circle cx=\"0\" r=\"1\"/>\n  <circle cx=\"40\" r=\"1\"/>\n</svg>\n"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Time spent in each phase of preprocessing, reported with the `timings`
+//! option (or the `--timings` flag).
+//!
+//! Each chapter's time is split between parsing its markdown, transforming
+//! svgdx sources to SVG, post-processing the output (e.g. wrapping, themes and
+//! accessibility) and splicing it back into the chapter. Blocks rendered by an
+//! earlier chapter are taken from the memo, so only count for the first.
+
+use std::fmt::Write;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// Time spent in each phase of processing a chapter.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Timings {
+    pub parse: Duration,
+    pub transform: Duration,
+    pub postprocess: Duration,
+    pub serialize: Duration,
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        self.parse += other.parse;
+        self.transform += other.transform;
+        self.postprocess += other.postprocess;
+        self.serialize += other.serialize;
+    }
+}
+
+/// Milliseconds in `duration`, to one decimal place.
+fn millis(duration: Duration) -> String {
+    format!("{:.1}", duration.as_secs_f64() * 1000.0)
+}
+
+/// Append a row of `timings` to `table`.
+fn push_row(table: &mut String, timings: &Timings, name: &str) {
+    let _ = writeln!(
+        table,
+        "{:>10} {:>10} {:>12} {:>10}  {}",
+        millis(timings.parse),
+        millis(timings.transform),
+        millis(timings.postprocess),
+        millis(timings.serialize),
+        name
+    );
+}
+
+/// A table of the `timings` of each chapter, in milliseconds, with totals.
+pub(crate) fn timings_table(timings: &[(String, Timings)]) -> String {
+    let mut table = format!(
+        "{:>10} {:>10} {:>12} {:>10}  chapter\n",
+        "parse", "transform", "post-process", "serialize"
+    );
+    let mut total = Timings::default();
+    for (name, row) in timings {
+        push_row(&mut table, row, name);
+        total += *row;
+    }
+    push_row(&mut table, &total, "total");
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_with_totals() {
+        let row = Timings {
+            parse: Duration::from_micros(1500),
+            transform: Duration::from_millis(20),
+            postprocess: Duration::from_micros(300),
+            serialize: Duration::ZERO,
+        };
+        let table = timings_table(&[("Intro".to_string(), row), ("Usage".to_string(), row)]);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            "       1.5       20.0          0.3        0.0  Intro"
+        );
+        assert_eq!(
+            lines[3],
+            "       3.0       40.0          0.6        0.0  total"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Whitespace layout of rendered SVG, for the `svg-format` option.
+//!
+//! Diagrams can be minified to a single line or pretty-printed with one
+//! element per line. Either way, the content of elements whose whitespace may
+//! be significant (text, styles and `foreignObject` HTML) is kept, other
+//! than its newlines when minifying.
+//!
+//! The same distinction gives the layout-independent form of svgdx source
+//! used to identify diagrams.
+
+/// Elements whose content is left unchanged.
+const OPAQUE: [&str; 6] = ["text", "foreignObject", "style", "script", "title", "desc"];
+
+/// A piece of SVG markup.
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    /// A start tag, with the element name.
+    Start(&'a str, &'a str),
+    /// An end tag, with the element name.
+    End(&'a str, &'a str),
+    /// An empty-element tag, processing instruction or declaration.
+    Empty(&'a str),
+    /// A comment or CDATA section.
+    Raw(&'a str),
+    Text(&'a str),
+}
+
+impl<'a> Token<'a> {
+    /// The markup of the token.
+    fn as_str(&self) -> &'a str {
+        match self {
+            Self::Start(s, _) | Self::End(s, _) => s,
+            Self::Empty(s) | Self::Raw(s) | Self::Text(s) => s,
+        }
+    }
+}
+
+/// Length of the tag at the start of `s`, allowing for quoted `>` characters.
+fn tag_len(s: &str) -> usize {
+    let mut quote = None;
+    for (idx, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '>') => return idx + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+/// Name of the element of `tag`, e.g. "rect" for `<rect x="1"/>`.
+fn tag_name(tag: &str) -> &str {
+    let name = tag.trim_start_matches(['<', '/']);
+    let end = name
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(name.len());
+    &name[..end]
+}
+
+/// Split `svg` into tokens.
+fn tokens(svg: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = svg;
+    while !rest.is_empty() {
+        let len = if let Some(end) = ["<!--", "<![CDATA["]
+            .iter()
+            .zip(["-->", "]]>"])
+            .find_map(|(open, end)| rest.starts_with(open).then_some(end))
+        {
+            rest.find(end).map_or(rest.len(), |idx| idx + end.len())
+        } else if rest.starts_with('<') {
+            tag_len(rest)
+        } else {
+            rest.find('<').unwrap_or(rest.len())
+        };
+        let (token, remaining) = rest.split_at(len);
+        tokens.push(
+            if token.starts_with("<!--") || token.starts_with("<![CDATA[") {
+                Token::Raw(token)
+            } else if token.starts_with("</") {
+                Token::End(token, tag_name(token))
+            } else if token.starts_with("<?") || token.starts_with("<!") || token.ends_with("/>") {
+                Token::Empty(token)
+            } else if token.starts_with('<') {
+                Token::Start(token, tag_name(token))
+            } else {
+                Token::Text(token)
+            },
+        );
+        rest = remaining;
+    }
+    tokens
+}
+
+/// Tracks whether tokens are within an element whose content is kept.
+#[derive(Default)]
+struct Opaque<'a> {
+    /// Name of the outermost such element, and the depth of its nesting.
+    element: Option<(&'a str, usize)>,
+}
+
+impl<'a> Opaque<'a> {
+    /// Update for `token`, returning whether it is within (or is the end tag
+    /// of) an element whose content is kept.
+    fn update(&mut self, token: &Token<'a>) -> bool {
+        match (&mut self.element, token) {
+            (Some((name, depth)), Token::Start(_, start)) if start == name => *depth += 1,
+            (Some((name, depth)), Token::End(_, end)) if end == name => {
+                *depth -= 1;
+                if *depth == 0 {
+                    self.element = None;
+                    return true;
+                }
+            }
+            (Some(_), _) => {}
+            (None, Token::Start(_, name)) if OPAQUE.contains(name) => {
+                self.element = Some((name, 1));
+                return false;
+            }
+            (None, _) => return false,
+        }
+        true
+    }
+}
+
+/// `svg` on a single line.
+///
+/// Whitespace between elements is removed, unless within an element whose
+/// content is kept. Other newlines become spaces, except in text (such as a
+/// `<pre>` within a `foreignObject`), where they are encoded.
+pub(crate) fn minify(svg: &str) -> String {
+    let mut output = String::with_capacity(svg.len());
+    let mut opaque = Opaque::default();
+    for token in tokens(svg.trim()) {
+        let kept = opaque.update(&token);
+        match token {
+            Token::Text(text) if text.trim().is_empty() => {
+                if kept {
+                    output.push_str(&text.replace('\n', " "));
+                }
+            }
+            Token::Text(text) => output.push_str(&text.replace('\n', "&#10;")),
+            _ => output.push_str(&token.as_str().replace('\n', " ")),
+        }
+    }
+    output
+}
+
+/// `tag` with runs of whitespace outside attribute values collapsed to a
+/// single space, and none before its closing `>` or `/>`.
+fn collapse_tag(tag: &str) -> String {
+    let mut output = String::with_capacity(tag.len());
+    let mut quote = None;
+    let mut space = false;
+    for c in tag.chars() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => {
+                space = true;
+                continue;
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            _ => {}
+        }
+        if space && !(quote.is_none() && (c == '>' || c == '/')) {
+            output.push(' ');
+        }
+        space = false;
+        output.push(c);
+    }
+    output
+}
+
+/// `source` without its layout: whitespace between elements is removed and
+/// whitespace within tags is collapsed, but the content of elements whose
+/// whitespace may be significant, attribute values and text are kept as
+/// they are.
+pub(crate) fn without_layout(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut opaque = Opaque::default();
+    for token in tokens(source) {
+        if opaque.update(&token) {
+            output.push_str(token.as_str());
+            continue;
+        }
+        match token {
+            Token::Text(text) if text.trim().is_empty() => {}
+            Token::Text(text) | Token::Raw(text) => output.push_str(text),
+            Token::Start(tag, _) | Token::End(tag, _) | Token::Empty(tag) => {
+                output.push_str(&collapse_tag(tag))
+            }
+        }
+    }
+    output
+}
+
+/// `svg` with each element on its own line, indented by its depth.
+pub(crate) fn pretty_print(svg: &str) -> String {
+    let mut output = String::with_capacity(svg.len() * 2);
+    let mut opaque = Opaque::default();
+    let mut depth = 0;
+    for token in tokens(svg.trim()) {
+        if opaque.update(&token) {
+            // Within an element whose content is kept, so continue its line.
+            output.push_str(token.as_str());
+            continue;
+        }
+        let text = match token {
+            Token::Text(text) if text.trim().is_empty() => continue,
+            Token::Text(text) => text.trim(),
+            Token::End(tag, _) => {
+                depth -= 1;
+                tag
+            }
+            _ => token.as_str(),
+        };
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&"  ".repeat(depth));
+        output.push_str(text);
+        if let Token::Start(_, name) = token {
+            if !OPAQUE.contains(&name) {
+                depth += 1;
+            }
+        }
+    }
+    output.push('\n');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG: &str = r#"<svg a="1>2">
+<style>.a { fill: red; }
+.b { fill: blue; }</style>
+  <g>
+<rect
+  x="1"/><!-- a
+comment -->
+</g>
+<text x="1">
+<tspan>a</tspan> <tspan>b</tspan>
+</text>
+</svg>
+"#;
+
+    #[test]
+    fn minified() {
+        assert_eq!(
+            minify(SVG),
+            "<svg a=\"1>2\"><style>.a { fill: red; }&#10;.b { fill: blue; }</style>\
+             <g><rect   x=\"1\"/><!-- a comment --></g>\
+             <text x=\"1\"> <tspan>a</tspan> <tspan>b</tspan> </text></svg>"
+        );
+    }
+
+    #[test]
+    fn layout_removed() {
+        assert_eq!(
+            without_layout(SVG),
+            "<svg a=\"1>2\"><style>.a { fill: red; }\n.b { fill: blue; }</style>\
+             <g><rect x=\"1\"/><!-- a\ncomment --></g>\
+             <text x=\"1\">\n<tspan>a</tspan> <tspan>b</tspan>\n</text></svg>"
+        );
+        let pre = |indent: &str| {
+            without_layout(&format!(
+                "<svg>\n  <foreignObject>\n<pre>\n{}x\n</pre></foreignObject>\n</svg>",
+                indent
+            ))
+        };
+        assert_ne!(pre("  "), pre("        "));
+    }
+
+    #[test]
+    fn pretty_printed() {
+        assert_eq!(
+            pretty_print(SVG),
+            r#"<svg a="1>2">
+  <style>.a { fill: red; }
+.b { fill: blue; }</style>
+  <g>
+    <rect
+  x="1"/>
+    <!-- a
+comment -->
+  </g>
+  <text x="1">
+<tspan>a</tspan> <tspan>b</tspan>
+</text>
+</svg>
+"#
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Rendering of svgdx blocks in isolated worker subprocesses.
+//!
+//! With `isolation = "process"`, each block is piped to a fresh invocation of
+//! this executable's hidden `render-worker` subcommand, which runs under
+//! memory and CPU limits so a single broken or malicious diagram cannot take
+//! down the preprocessor.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+use crate::config::{SvgdxConfig, TransformOptions};
+use crate::{error_html, svgdx_handler};
+
+/// Entry point for the worker subprocess: render svgdx source from stdin to stdout.
+///
+/// `transform` holds the book's transform options as JSON, if given.
+pub fn run_worker(transform: Option<&str>) -> io::Result<()> {
+    let options: TransformOptions = match transform {
+        Some(json) => serde_json::from_str(json)?,
+        None => TransformOptions::default(),
+    };
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source)?;
+    io::stdout().write_all(svgdx_handler(&source, &options).as_bytes())
+}
+
+/// Render `source` in a worker subprocess, returning either the SVG output
+/// or an error message suitable for display in place of the diagram.
+pub(crate) fn render_isolated(
+    source: &str,
+    cfg: &SvgdxConfig,
+    transform: &TransformOptions,
+) -> String {
+    spawn_worker(source, cfg, transform)
+        .unwrap_or_else(|e| error_html(&format!("svgdx worker failed: {}", e)))
+}
+
+fn spawn_worker(
+    source: &str,
+    cfg: &SvgdxConfig,
+    transform: &TransformOptions,
+) -> io::Result<String> {
+    let mut cmd = Command::new(env::current_exe()?);
+    cmd.arg("render-worker")
+        .arg("--transform")
+        .arg(serde_json::to_string(transform)?)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    apply_limits(&mut cmd, cfg);
+
+    let mut child = cmd.spawn()?;
+    // The worker reads all its input before writing any output, so this
+    // cannot deadlock on a full stdout pipe.
+    child
+        .stdin
+        .take()
+        .expect("stdin should be piped")
+        .write_all(source.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout).map_err(io::Error::other)
+}
+
+#[cfg(unix)]
+fn apply_limits(cmd: &mut Command, cfg: &SvgdxConfig) {
+    use std::os::unix::process::CommandExt;
+
+    let bytes = cfg.worker_memory_limit.saturating_mul(1024 * 1024) as libc::rlim_t;
+    let memory = libc::rlimit {
+        rlim_cur: bytes,
+        rlim_max: bytes,
+    };
+    let cpu = libc::rlimit {
+        rlim_cur: cfg.worker_cpu_limit as libc::rlim_t,
+        rlim_max: cfg.worker_cpu_limit as libc::rlim_t,
+    };
+    // SAFETY: setrlimit is async-signal-safe, and only touches the
+    // (already forked) child process.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &memory) != 0
+                || libc::setrlimit(libc::RLIMIT_CPU, &cpu) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}